@@ -0,0 +1,103 @@
+use std::fmt;
+
+/// what failed, which selects the Python exception raised for an error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// invalid arguments and any other failure.
+    #[default]
+    Other,
+    /// a model file could not be loaded.
+    ModelLoad,
+    /// predicting a text failed.
+    Prediction,
+    /// the model does not support the operation.
+    ModelType,
+    /// the batch was interrupted, e.g. by Ctrl-C.
+    Interrupted,
+}
+
+/// an error message, from fasttext or from validating arguments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::new(ErrorKind::Other, message)
+    }
+}
+
+/// the Python exceptions raised for errors, by kind.
+// `create_exception!` of pyo3 0.18 checks a `cfg` unknown to newer rustc.
+#[cfg(feature = "python")]
+#[allow(unexpected_cfgs)]
+pub mod exceptions {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyException;
+
+    create_exception!(
+        fasttext_parallel,
+        FastTextError,
+        PyException,
+        "Base class of the errors raised by fasttext_parallel."
+    );
+    create_exception!(
+        fasttext_parallel,
+        ModelLoadError,
+        FastTextError,
+        "A model file could not be loaded."
+    );
+    create_exception!(
+        fasttext_parallel,
+        PredictionError,
+        FastTextError,
+        "Predicting a text failed."
+    );
+    create_exception!(
+        fasttext_parallel,
+        ModelTypeError,
+        FastTextError,
+        "The model does not support the operation."
+    );
+}
+
+#[cfg(feature = "python")]
+impl From<Error> for pyo3::PyErr {
+    fn from(e: Error) -> Self {
+        use exceptions::*;
+        match e.kind {
+            ErrorKind::Other => FastTextError::new_err(e.message),
+            ErrorKind::ModelLoad => ModelLoadError::new_err(e.message),
+            ErrorKind::Prediction => PredictionError::new_err(e.message),
+            ErrorKind::ModelType => ModelTypeError::new_err(e.message),
+            ErrorKind::Interrupted => pyo3::exceptions::PyKeyboardInterrupt::new_err(e.message),
+        }
+    }
+}
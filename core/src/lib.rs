@@ -0,0 +1,45 @@
+//! Multithreaded fasttext batch inference.
+//!
+//! Texts flow from a sender through rayon workers to a writer that places
+//! each result at its input index, so the output is in input order.
+//!
+//! ```no_run
+//! use fasttext_parallel_core::ParallelPredictor;
+//!
+//! let predictor = ParallelPredictor::load("lid.176.bin").unwrap();
+//! let texts = ["你好", "how are you"].map(|s| Some(s.to_string()));
+//! let (labels, probs) = predictor.predict_batch(texts.into_iter(), 1, 0.0).unwrap();
+//! let label = &predictor.reverse_label_dict()[&labels[[0, 0]]];
+//! println!("{label} {}", probs[[0, 0]]);
+//! ```
+//!
+//! The pipeline runs on a rayon pool owned by this crate, rather than the
+//! global one. It is built on first use with `init_threads` or `configure`,
+//! `FASTTEXT_PARALLEL_THREADS` or at least `MIN_THREADS` threads, and
+//! `set_num_threads` replaces it at any time.
+
+mod error;
+mod mapped;
+mod meter;
+mod options;
+mod pipeline;
+mod pool;
+mod predictor;
+mod stats;
+mod train;
+
+#[cfg(feature = "python")]
+pub use error::exceptions;
+pub use error::{Error, ErrorKind, Result};
+pub use meter::{LabelCounts, Meter};
+pub use options::{
+    EmptyPolicy, ErrorPolicy, NewlinePolicy, PredictOptions, Preprocess, Thresholds, UnicodeForm,
+};
+pub use pipeline::{
+    chunk_size, run_pipeline, run_pipeline_until, set_chunk_size, set_interrupt_check, ChunkSender,
+    DEFAULT_CHUNK_SIZE,
+};
+pub use pool::{configure, init_threads, set_num_threads, thread_pool, MIN_THREADS, THREADS_ENV};
+pub use predictor::{LabelId, ParallelPredictor, Predictions, Probability, Row, SparsePredictions};
+pub use stats::BatchStats;
+pub use train::{AutotuneArgs, TrainArgs};
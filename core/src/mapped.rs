@@ -0,0 +1,819 @@
+//! a model file whose matrices are memory mapped, for
+//! `ParallelPredictor::load_mapped`.
+//!
+//! fasttext copies the matrices of a model into its own buffers, so the
+//! matrices of mapped models are read here instead. Texts are split into
+//! words, subwords and word n-grams, and the layers are computed, with the
+//! same operations in the same order as fasttext, so that predictions and
+//! vectors are bit-identical to those of the model loaded by fasttext.
+
+use crate::{Error, ErrorKind, Result};
+use fasttext::Prediction;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// the magic number starting fasttext model files.
+const FASTTEXT_FILEFORMAT_MAGIC_INT32: i32 = 793712314;
+
+/// the latest file format version fasttext loads.
+const FASTTEXT_VERSION: i32 = 12;
+
+/// the version whose supervised models have no character n-grams.
+const OLD_VERSION: i32 = 11;
+
+/// the id of the supervised model in the saved arguments.
+const MODEL_SUP: i32 = 3;
+
+/// the token of a line break.
+const EOS: &[u8] = b"</s>";
+
+/// the label prefix of loaded models, as fasttext does not save it.
+const LABEL_PREFIX: &[u8] = b"__label__";
+
+const SIGMOID_TABLE_SIZE: usize = 512;
+const MAX_SIGMOID: f32 = 8.0;
+
+/// the loss the model was trained with, which computes its output layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Loss {
+    HierarchicalSoftmax,
+    NegativeSampling,
+    Softmax,
+    OneVsAll,
+}
+
+/// a node of the hierarchical softmax tree, whose first nodes are the
+/// labels.
+#[derive(Clone, Copy)]
+struct Node {
+    left: i32,
+    right: i32,
+}
+
+/// a dense matrix of the file, by the offset of its data.
+#[derive(Clone, Copy)]
+struct Matrix {
+    offset: usize,
+    rows: usize,
+}
+
+/// the dictionary, arguments and matrix offsets of a mapped model file.
+pub(crate) struct MappedModel {
+    mmap: Mmap,
+    /// the end of the dictionary, where the matrices start.
+    header_len: usize,
+    dim: usize,
+    word_ngrams: i32,
+    loss: Loss,
+    supervised: bool,
+    bucket: i32,
+    minn: i32,
+    maxn: i32,
+    nwords: i32,
+    /// the id of each word and label, the last one of duplicates.
+    ids: HashMap<Box<[u8]>, i32>,
+    /// whether each entry is a label.
+    is_label: Vec<bool>,
+    labels: Vec<String>,
+    input: Matrix,
+    output: Matrix,
+    /// the hierarchical softmax tree, built from the label counts.
+    tree: Vec<Node>,
+    sigmoid_table: Vec<f32>,
+}
+
+impl MappedModel {
+    /// map the model file at `path`, which must not be quantized.
+    pub(crate) fn open(path: &str) -> Result<Self> {
+        let load_error = |e: String| Error::new(ErrorKind::ModelLoad, e);
+        let read_error = |e| load_error(format!("failed to read {path}, {e}"));
+        let file = File::open(path).map_err(read_error)?;
+        if file.metadata().map_err(read_error)?.len() == 0 {
+            return Err(load_error(format!(
+                "invalid model file {path}, unexpected end of file"
+            )));
+        }
+        // Safety: the file must not be modified while the model is loaded,
+        // which would change its predictions, or raise SIGBUS on reading
+        // pages lost to truncation.
+        let mmap = unsafe { Mmap::map(&file) }.map_err(read_error)?;
+        Self::parse(mmap).map_err(|e| load_error(format!("invalid model file {path}, {e}")))
+    }
+
+    fn parse(mmap: Mmap) -> std::result::Result<Self, String> {
+        let mut cursor = Cursor {
+            bytes: &mmap,
+            pos: 0,
+        };
+        let magic = cursor.i32()?;
+        let version = cursor.i32()?;
+        if magic != FASTTEXT_FILEFORMAT_MAGIC_INT32 || version > FASTTEXT_VERSION {
+            return Err("wrong file format".into());
+        }
+        let dim = cursor.i32()?;
+        // ws, epoch, minCount and neg.
+        cursor.skip(4 * 4)?;
+        let word_ngrams = cursor.i32()?;
+        let loss = cursor.i32()?;
+        let model = cursor.i32()?;
+        let bucket = cursor.i32()?;
+        let minn = cursor.i32()?;
+        let mut maxn = cursor.i32()?;
+        // lrUpdateRate and t.
+        cursor.skip(4 + 8)?;
+        let supervised = model == MODEL_SUP;
+        if version == OLD_VERSION && supervised {
+            maxn = 0;
+        }
+        let loss = match loss {
+            1 => Loss::HierarchicalSoftmax,
+            2 => Loss::NegativeSampling,
+            3 => Loss::Softmax,
+            4 => Loss::OneVsAll,
+            _ => return Err(format!("unknown loss {loss}")),
+        };
+        if dim <= 0 {
+            return Err(format!("invalid dimension {dim}"));
+        }
+
+        let size = cursor.i32()?;
+        let nwords = cursor.i32()?;
+        let nlabels = cursor.i32()?;
+        let _ntokens = cursor.i64()?;
+        let pruneidx_size = cursor.i64()?;
+        if size < 0 || nwords < 0 || nlabels < 0 || nwords as i64 + nlabels as i64 > size as i64 {
+            return Err(format!(
+                "invalid dictionary of {size} entries, {nwords} words and {nlabels} labels"
+            ));
+        }
+        let mut ids = HashMap::with_capacity(size as usize);
+        let mut is_label = Vec::with_capacity(size as usize);
+        let mut labels = Vec::with_capacity(nlabels as usize);
+        let mut label_counts = Vec::new();
+        for id in 0..size {
+            let word = cursor.word()?;
+            let count = cursor.i64()?;
+            let label = cursor.u8()? == 1;
+            if label {
+                label_counts.push(count);
+            }
+            if (nwords..nwords + nlabels).contains(&id) {
+                labels.push(String::from_utf8_lossy(&word).into_owned());
+            }
+            ids.insert(word.into(), id);
+            is_label.push(label);
+        }
+        if label_counts.len() != nlabels as usize {
+            return Err(format!(
+                "{} entries are labels, expected {nlabels}",
+                label_counts.len()
+            ));
+        }
+        if pruneidx_size > 0 {
+            cursor.skip(pruneidx_size as usize * 8)?;
+        }
+        let header_len = cursor.pos;
+
+        if cursor.u8()? != 0 {
+            return Err("quantized models cannot be memory mapped".into());
+        }
+        if pruneidx_size >= 0 {
+            return Err("pruned models must be quantized".into());
+        }
+        let input = cursor.matrix(dim as usize)?;
+        cursor.skip(1)?;
+        let output = cursor.matrix(dim as usize)?;
+        let input_rows = nwords as i64 + bucket.max(0) as i64;
+        if (input.rows as i64) < input_rows {
+            return Err(format!(
+                "the input matrix has {} rows, expected {input_rows}",
+                input.rows
+            ));
+        }
+        let output_rows = if supervised { nlabels } else { nwords };
+        if output.rows < output_rows as usize {
+            return Err(format!(
+                "the output matrix has {} rows, expected {output_rows}",
+                output.rows
+            ));
+        }
+
+        let sigmoid_table = (0..=SIGMOID_TABLE_SIZE)
+            .map(|i| {
+                let x =
+                    (i * 2 * MAX_SIGMOID as usize) as f32 / SIGMOID_TABLE_SIZE as f32 - MAX_SIGMOID;
+                (1.0 / (1.0 + (-x).exp() as f64)) as f32
+            })
+            .collect();
+        let tree = match loss {
+            Loss::HierarchicalSoftmax if supervised => build_tree(&label_counts),
+            _ => Vec::new(),
+        };
+        Ok(MappedModel {
+            header_len,
+            dim: dim as usize,
+            word_ngrams,
+            loss,
+            supervised,
+            bucket,
+            minn,
+            maxn,
+            nwords,
+            ids,
+            is_label,
+            labels,
+            input,
+            output,
+            tree,
+            sigmoid_table,
+            mmap,
+        })
+    }
+
+    /// the model file without its matrices, which fasttext loads for the
+    /// dictionary and arguments.
+    fn skeleton(&self) -> Vec<u8> {
+        let mut skeleton = self.mmap[..self.header_len].to_vec();
+        for quantized in [false, false] {
+            skeleton.push(quantized as u8);
+            skeleton.extend_from_slice(&0i64.to_le_bytes());
+            skeleton.extend_from_slice(&(self.dim as i64).to_le_bytes());
+        }
+        skeleton
+    }
+
+    /// write the skeleton to a temporary file and pass its path to `f`.
+    pub(crate) fn with_skeleton<T>(&self, f: impl FnOnce(&str) -> Result<T>) -> Result<T> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "fasttext-parallel-skeleton-{}-{}.bin",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, self.skeleton())
+            .map_err(|e| Error::from(format!("failed to write {}, {e}", path.display())))?;
+        let result = f(&path.to_string_lossy());
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    /// predict the labels of `text` as `FastText::predict` does.
+    pub(crate) fn predict(
+        &self,
+        text: &str,
+        k: i32,
+        threshold: f32,
+    ) -> std::result::Result<Vec<Prediction>, String> {
+        check_nul(text)?;
+        let words = self.line(text.as_bytes());
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.supervised {
+            return Err("Model needs to be supervised for prediction!".into());
+        }
+        let k = match k {
+            -1 => self.output.rows as i32,
+            k if k <= 0 => return Err("k needs to be 1 or higher!".into()),
+            k => k,
+        };
+        let hidden = self.hidden(&words);
+        let mut heap = Vec::with_capacity(k as usize + 1);
+        match self.loss {
+            Loss::HierarchicalSoftmax => {
+                let root = 2 * self.labels.len() as i32 - 2;
+                if root >= 0 {
+                    self.dfs(k, threshold, root, 0.0, &mut heap, &hidden)?;
+                }
+            }
+            _ => {
+                let output = self.output_layer(&hidden)?;
+                find_k_best(k, threshold, &mut heap, &output);
+            }
+        }
+        sort_heap(&mut heap);
+        Ok(heap
+            .into_iter()
+            .map(|(score, label)| Prediction {
+                prob: score.exp(),
+                label: self.labels[label as usize].clone(),
+            })
+            .collect())
+    }
+
+    /// the vector of `word` as `FastText::get_word_vector` does.
+    pub(crate) fn word_vector(&self, word: &str) -> std::result::Result<Vec<f32>, String> {
+        check_nul(word)?;
+        Ok(self.word_vector_of(word.as_bytes()))
+    }
+
+    /// the vector of `text` as `FastText::get_sentence_vector` does.
+    pub(crate) fn sentence_vector(&self, text: &str) -> std::result::Result<Vec<f32>, String> {
+        check_nul(text)?;
+        let text = text.as_bytes();
+        if self.supervised {
+            let words = self.line(text);
+            return Ok(if words.is_empty() {
+                vec![0.0; self.dim]
+            } else {
+                self.hidden(&words)
+            });
+        }
+        let mut vector = vec![0.0; self.dim];
+        let mut count = 0;
+        let line = text.split(|&c| c == b'\n').next().unwrap_or_default();
+        for word in line
+            .split(|c| matches!(c, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r'))
+            .filter(|word| !word.is_empty())
+        {
+            let mut word_vector = self.word_vector_of(word);
+            let norm = word_vector.iter().fold(0.0f32, |sum, v| sum + v * v).sqrt();
+            if norm > 0.0 {
+                scale(&mut word_vector, (1.0 / norm as f64) as f32);
+                for (v, w) in vector.iter_mut().zip(word_vector) {
+                    *v += w;
+                }
+                count += 1;
+            }
+        }
+        if count > 0 {
+            scale(&mut vector, (1.0 / count as f64) as f32);
+        }
+        Ok(vector)
+    }
+
+    fn word_vector_of(&self, word: &[u8]) -> Vec<f32> {
+        let mut ngrams = Vec::new();
+        match self.ids.get(word) {
+            // labels have no rows unless the buckets follow, which fasttext
+            // reads past the matrix for.
+            Some(&id) if id as usize >= self.input.rows => {}
+            Some(&id) => self.add_subwords_of(&mut ngrams, word, id),
+            None if word != EOS => self.compute_subwords(&mut ngrams, word),
+            None => {}
+        }
+        if ngrams.is_empty() {
+            vec![0.0; self.dim]
+        } else {
+            self.hidden(&ngrams)
+        }
+    }
+
+    /// the input rows of the words, subwords and word n-grams of the first
+    /// line of `text`.
+    fn line(&self, text: &[u8]) -> Vec<i32> {
+        let mut words = Vec::new();
+        let mut hashes = Vec::new();
+        let mut tokens = Tokens { text, pos: 0 };
+        while let Some(token) = tokens.next() {
+            let id = self.ids.get(token).copied();
+            let is_label = match id {
+                Some(id) => self.is_label[id as usize],
+                None => token.starts_with(LABEL_PREFIX),
+            };
+            if !is_label {
+                match id {
+                    Some(id) if self.maxn <= 0 => words.push(id),
+                    Some(id) => self.add_subwords_of(&mut words, token, id),
+                    None if token != EOS => self.compute_subwords(&mut words, token),
+                    None => {}
+                }
+                hashes.push(hash(token) as i32);
+            }
+            if token == EOS {
+                break;
+            }
+        }
+        self.add_word_ngrams(&mut words, &hashes);
+        words
+    }
+
+    /// the input rows of the entry `id` that is `word`: its own and those of
+    /// its character n-grams.
+    fn add_subwords_of(&self, rows: &mut Vec<i32>, word: &[u8], id: i32) {
+        rows.push(id);
+        if word != EOS {
+            self.compute_subwords(rows, word);
+        }
+    }
+
+    /// the input rows of the character n-grams of `word`, which is then
+    /// enclosed in `<` and `>`.
+    fn compute_subwords(&self, rows: &mut Vec<i32>, word: &[u8]) {
+        let mut enclosed = Vec::with_capacity(word.len() + 2);
+        enclosed.push(b'<');
+        enclosed.extend_from_slice(word);
+        enclosed.push(b'>');
+        let word = &enclosed[..];
+        let (minn, maxn) = (self.minn as usize, self.maxn as usize);
+        for i in 0..word.len() {
+            if word[i] & 0xC0 == 0x80 {
+                continue;
+            }
+            let mut j = i;
+            let mut n = 1;
+            while j < word.len() && n <= maxn {
+                j += 1;
+                while j < word.len() && word[j] & 0xC0 == 0x80 {
+                    j += 1;
+                }
+                if n >= minn && !(n == 1 && (i == 0 || j == word.len())) {
+                    if let Some(h) = hash(&word[i..j]).checked_rem(self.bucket as u32) {
+                        self.push_hash(rows, h as i32);
+                    }
+                }
+                n += 1;
+            }
+        }
+    }
+
+    fn add_word_ngrams(&self, rows: &mut Vec<i32>, hashes: &[i32]) {
+        let n = self.word_ngrams.max(0) as usize;
+        for i in 0..hashes.len() {
+            let mut h = hashes[i] as i64 as u64;
+            for &hash in &hashes[i + 1..hashes.len().min(i + n).max(i + 1)] {
+                h = h.wrapping_mul(116049371).wrapping_add(hash as i64 as u64);
+                if let Some(h) = h.checked_rem(self.bucket as i64 as u64) {
+                    self.push_hash(rows, h as i32);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn push_hash(&self, rows: &mut Vec<i32>, id: i32) {
+        if id >= 0 {
+            rows.push(self.nwords + id);
+        }
+    }
+
+    #[inline]
+    fn row(&self, matrix: Matrix, i: usize) -> impl Iterator<Item = f32> + '_ {
+        let start = matrix.offset + i * self.dim * 4;
+        self.mmap[start..start + self.dim * 4]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// the average of the input rows.
+    fn hidden(&self, rows: &[i32]) -> Vec<f32> {
+        let mut hidden = vec![0.0f32; self.dim];
+        for &i in rows {
+            for (h, v) in hidden.iter_mut().zip(self.row(self.input, i as usize)) {
+                *h += v;
+            }
+        }
+        scale(&mut hidden, (1.0 / rows.len() as f64) as f32);
+        hidden
+    }
+
+    fn dot_row(&self, hidden: &[f32], i: usize) -> std::result::Result<f32, String> {
+        let mut d = 0.0f32;
+        for (v, h) in self.row(self.output, i).zip(hidden) {
+            d += v * h;
+        }
+        if d.is_nan() {
+            return Err("Encountered NaN.".into());
+        }
+        Ok(d)
+    }
+
+    /// the score of each label of the softmax, negative sampling and
+    /// one-vs-all losses.
+    fn output_layer(&self, hidden: &[f32]) -> std::result::Result<Vec<f32>, String> {
+        let mut output = (0..self.labels.len())
+            .map(|i| self.dot_row(hidden, i))
+            .collect::<std::result::Result<Vec<f32>, String>>()?;
+        match self.loss {
+            Loss::Softmax => {
+                let Some(&first) = output.first() else {
+                    return Ok(output);
+                };
+                let max = output
+                    .iter()
+                    .fold(first, |max, &o| if o < max { max } else { o });
+                let mut z = 0.0f32;
+                // fasttext calls the `exp` of doubles here, unlike elsewhere.
+                for o in &mut output {
+                    *o = ((*o - max) as f64).exp() as f32;
+                    z += *o;
+                }
+                for o in &mut output {
+                    *o /= z;
+                }
+            }
+            _ => {
+                for o in &mut output {
+                    *o = self.sigmoid(*o);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    #[inline]
+    fn sigmoid(&self, x: f32) -> f32 {
+        if x < -MAX_SIGMOID {
+            0.0
+        } else if x > MAX_SIGMOID {
+            1.0
+        } else {
+            let i = ((x + MAX_SIGMOID) * SIGMOID_TABLE_SIZE as f32 / MAX_SIGMOID / 2.0) as i64;
+            self.sigmoid_table[i as usize]
+        }
+    }
+
+    /// walk the hierarchical softmax tree from `node` for the `k` most
+    /// likely labels.
+    fn dfs(
+        &self,
+        k: i32,
+        threshold: f32,
+        node: i32,
+        score: f32,
+        heap: &mut Vec<(f32, i32)>,
+        hidden: &[f32],
+    ) -> std::result::Result<(), String> {
+        if score < std_log(threshold) {
+            return Ok(());
+        }
+        if heap.len() == k as usize && score < heap[0].0 {
+            return Ok(());
+        }
+        let Node { left, right } = self.tree[node as usize];
+        if left == -1 && right == -1 {
+            heap.push((score, node));
+            push_heap(heap);
+            if heap.len() > k as usize {
+                pop_heap(heap);
+                heap.pop();
+            }
+            return Ok(());
+        }
+        let f = self.dot_row(hidden, (node as usize) - self.labels.len())?;
+        let f = (1.0 / (1.0 + (-f).exp()) as f64) as f32;
+        self.dfs(
+            k,
+            threshold,
+            left,
+            score + std_log((1.0 - f as f64) as f32),
+            heap,
+            hidden,
+        )?;
+        self.dfs(k, threshold, right, score + std_log(f), heap, hidden)
+    }
+}
+
+/// the words of a text, as fasttext reads them.
+struct Tokens<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    /// the next word, or `</s>` for a line break.
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let mut start = None;
+        while let Some(&c) = self.text.get(self.pos) {
+            if matches!(c, b' ' | b'\n' | b'\r' | b'\t' | b'\x0b' | b'\x0c' | b'\0') {
+                match start {
+                    None => {
+                        self.pos += 1;
+                        if c == b'\n' {
+                            return Some(EOS);
+                        }
+                    }
+                    Some(start) => {
+                        let word = &self.text[start..self.pos];
+                        // a line break ends the word, then is read as `</s>`.
+                        if c != b'\n' {
+                            self.pos += 1;
+                        }
+                        return Some(word);
+                    }
+                }
+            } else {
+                start.get_or_insert(self.pos);
+                self.pos += 1;
+            }
+        }
+        start.map(|start| &self.text[start..])
+    }
+}
+
+impl Cursor<'_> {
+    fn take(&mut self, n: usize) -> std::result::Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len());
+        let bytes = &self.bytes[self.pos..end.ok_or("unexpected end of file")?];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> std::result::Result<(), String> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> std::result::Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> std::result::Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> std::result::Result<i64, String> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// a NUL-terminated word of the dictionary.
+    fn word(&mut self) -> std::result::Result<Vec<u8>, String> {
+        let rest = &self.bytes[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&c| c == 0)
+            .ok_or("unexpected end of file")?;
+        let word = rest[..len].to_vec();
+        self.pos += len + 1;
+        Ok(word)
+    }
+
+    /// the shape of a dense matrix with `dim` columns, then its data.
+    fn matrix(&mut self, dim: usize) -> std::result::Result<Matrix, String> {
+        let rows = self.i64()?;
+        let cols = self.i64()?;
+        if cols != dim as i64 || rows < 0 {
+            return Err(format!("invalid matrix shape ({rows}, {cols})"));
+        }
+        let offset = self.pos;
+        let len = (rows as usize)
+            .checked_mul(dim * 4)
+            .ok_or("invalid matrix shape")?;
+        self.skip(len)?;
+        Ok(Matrix {
+            offset,
+            rows: rows as usize,
+        })
+    }
+}
+
+/// a position in the bytes of a model file.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+/// the error of the `CString` fasttext is passed texts as.
+fn check_nul(text: &str) -> std::result::Result<(), String> {
+    match CString::new(text) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{e:?}")),
+    }
+}
+
+/// the FNV-1a hash of fasttext, over signed bytes.
+#[inline]
+fn hash(word: &[u8]) -> u32 {
+    word.iter().fold(2166136261u32, |h, &c| {
+        (h ^ c as i8 as u32).wrapping_mul(16777619)
+    })
+}
+
+#[inline]
+fn std_log(x: f32) -> f32 {
+    (x as f64 + 1e-5).ln() as f32
+}
+
+#[inline]
+fn scale(vector: &mut [f32], a: f32) {
+    for v in vector {
+        *v *= a;
+    }
+}
+
+/// the hierarchical softmax tree of labels with `counts`, whose root is the
+/// last node.
+fn build_tree(counts: &[i64]) -> Vec<Node> {
+    let osz = counts.len();
+    if osz == 0 {
+        return Vec::new();
+    }
+    let mut tree = vec![
+        Node {
+            left: -1,
+            right: -1
+        };
+        2 * osz - 1
+    ];
+    let mut count = vec![1e15 as i64; 2 * osz - 1];
+    count[..osz].copy_from_slice(counts);
+    let mut leaf = osz as isize - 1;
+    let mut node = osz;
+    for i in osz..2 * osz - 1 {
+        let mut mini = [0; 2];
+        for m in &mut mini {
+            if leaf >= 0 && count[leaf as usize] < count[node] {
+                *m = leaf as usize;
+                leaf -= 1;
+            } else {
+                *m = node;
+                node += 1;
+            }
+        }
+        tree[i] = Node {
+            left: mini[0] as i32,
+            right: mini[1] as i32,
+        };
+        count[i] = count[mini[0]] + count[mini[1]];
+    }
+    tree
+}
+
+/// keep the `k` highest scores of `output` at least `threshold` in `heap`.
+fn find_k_best(k: i32, threshold: f32, heap: &mut Vec<(f32, i32)>, output: &[f32]) {
+    for (i, &o) in output.iter().enumerate() {
+        if o < threshold {
+            continue;
+        }
+        if heap.len() == k as usize && std_log(o) < heap[0].0 {
+            continue;
+        }
+        heap.push((std_log(o), i as i32));
+        push_heap(heap);
+        if heap.len() > k as usize {
+            pop_heap(heap);
+            heap.pop();
+        }
+    }
+}
+
+// The heap operations of libstdc++, whose order of equal scores fasttext's
+// predictions keep, with the lowest score at the front.
+
+#[inline]
+fn less(a: (f32, i32), b: (f32, i32)) -> bool {
+    a.0 > b.0
+}
+
+fn push_heap(heap: &mut [(f32, i32)]) {
+    let last = heap.len() - 1;
+    let value = heap[last];
+    sift_up(heap, last, 0, value);
+}
+
+fn sift_up(heap: &mut [(f32, i32)], mut hole: usize, top: usize, value: (f32, i32)) {
+    while hole > top {
+        let parent = (hole - 1) / 2;
+        if !less(heap[parent], value) {
+            break;
+        }
+        heap[hole] = heap[parent];
+        hole = parent;
+    }
+    heap[hole] = value;
+}
+
+fn adjust_heap(heap: &mut [(f32, i32)], mut hole: usize, value: (f32, i32)) {
+    let len = heap.len() as isize;
+    let top = hole;
+    let mut second = hole as isize;
+    while second < (len - 1) / 2 {
+        second = 2 * (second + 1);
+        if less(heap[second as usize], heap[second as usize - 1]) {
+            second -= 1;
+        }
+        heap[hole] = heap[second as usize];
+        hole = second as usize;
+    }
+    if len & 1 == 0 && second == (len - 2) / 2 {
+        second = 2 * (second + 1);
+        heap[hole] = heap[second as usize - 1];
+        hole = second as usize - 1;
+    }
+    sift_up(heap, hole, top, value);
+}
+
+fn pop_heap(heap: &mut [(f32, i32)]) {
+    let last = heap.len() - 1;
+    if last > 0 {
+        let value = heap[last];
+        heap[last] = heap[0];
+        adjust_heap(&mut heap[..last], 0, value);
+    }
+}
+
+fn sort_heap(heap: &mut [(f32, i32)]) {
+    let mut last = heap.len();
+    while last > 1 {
+        last -= 1;
+        let value = heap[last];
+        heap[last] = heap[0];
+        adjust_heap(&mut heap[..last], 0, value);
+    }
+}
@@ -0,0 +1,78 @@
+//! precision and recall of predictions against gold labels.
+
+use std::collections::BTreeMap;
+
+/// counts of predicted and gold labels, as the `Meter` of fasttext.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Meter {
+    /// the number of examples with at least one gold label.
+    pub examples: usize,
+    /// the number of gold labels.
+    pub gold: usize,
+    /// the number of predicted labels.
+    pub predicted: usize,
+    /// the number of predicted labels that are gold labels.
+    pub correct: usize,
+    /// the counts of each label that is predicted or gold, by label id.
+    pub labels: BTreeMap<i32, LabelCounts>,
+}
+
+/// counts of a single label.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LabelCounts {
+    pub gold: usize,
+    pub predicted: usize,
+    pub correct: usize,
+}
+
+impl Meter {
+    /// count the predictions of an example.
+    pub fn add(&mut self, gold: &[i32], predicted: &[i32]) {
+        self.examples += 1;
+        self.gold += gold.len();
+        self.predicted += predicted.len();
+        for &label in gold {
+            self.labels.entry(label).or_default().gold += 1;
+        }
+        for &label in predicted {
+            let counts = self.labels.entry(label).or_default();
+            counts.predicted += 1;
+            if gold.contains(&label) {
+                counts.correct += 1;
+                self.correct += 1;
+            }
+        }
+    }
+
+    /// the share of predicted labels that are correct, NaN without any.
+    pub fn precision(&self) -> f64 {
+        self.correct as f64 / self.predicted as f64
+    }
+
+    /// the share of gold labels that are predicted, NaN without any.
+    pub fn recall(&self) -> f64 {
+        self.correct as f64 / self.gold as f64
+    }
+
+    /// the F1 score over all predictions, NaN without any label.
+    pub fn micro_f1(&self) -> f64 {
+        f1(self.correct, self.gold, self.predicted)
+    }
+
+    /// the mean F1 score of the labels that are predicted or gold, NaN
+    /// without any.
+    pub fn macro_f1(&self) -> f64 {
+        let sum: f64 = self
+            .labels
+            .values()
+            .map(|c| f1(c.correct, c.gold, c.predicted))
+            .sum();
+        sum / self.labels.len() as f64
+    }
+}
+
+/// the harmonic mean of precision and recall.
+#[inline]
+fn f1(correct: usize, gold: usize, predicted: usize) -> f64 {
+    2.0 * correct as f64 / (gold + predicted) as f64
+}
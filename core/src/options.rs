@@ -0,0 +1,383 @@
+//! how each text is turned into a row of predictions.
+
+use crate::{BatchStats, Error, Result};
+use log::trace;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use unicode_normalization::{is_nfc_quick, is_nfd_quick, is_nfkc_quick, is_nfkd_quick};
+use unicode_normalization::{IsNormalized, UnicodeNormalization};
+
+/// how to treat `\n` inside a text, which fasttext reads as end of line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NewlinePolicy {
+    /// replace newlines with spaces before prediction.
+    #[default]
+    Strip,
+    /// leave texts as they are, so fasttext only reads their first line.
+    Keep,
+    /// fail the batch with the index of the text.
+    Error,
+}
+
+impl NewlinePolicy {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "strip" => Ok(NewlinePolicy::Strip),
+            "keep" => Ok(NewlinePolicy::Keep),
+            "error" => Ok(NewlinePolicy::Error),
+            _ => Err(Error::from(format!(
+                "unknown newline policy `{s}`, expected one of `strip`, `keep`, `error`"
+            ))),
+        }
+    }
+}
+
+/// how texts that are missing, empty or only whitespace are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyPolicy {
+    /// predict them like any other text, missing texts give empty rows.
+    #[default]
+    Zero,
+    /// leave them out of the output.
+    Skip,
+    /// raise an exception listing their indices.
+    Error,
+}
+
+impl EmptyPolicy {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "zero" => Ok(EmptyPolicy::Zero),
+            "skip" => Ok(EmptyPolicy::Skip),
+            "error" => Ok(EmptyPolicy::Error),
+            _ => Err(Error::from(format!(
+                "unknown empty policy `{s}`, expected one of `zero`, `skip`, `error`"
+            ))),
+        }
+    }
+}
+
+/// how texts whose prediction fails are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// log the error and output an empty row.
+    #[default]
+    Ignore,
+    /// abort the batch and raise an exception.
+    Raise,
+    /// like `Ignore`, and also return the indices of failed rows.
+    Report,
+}
+
+impl ErrorPolicy {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "ignore" => Ok(ErrorPolicy::Ignore),
+            "raise" => Ok(ErrorPolicy::Raise),
+            "report" => Ok(ErrorPolicy::Report),
+            _ => Err(Error::from(format!(
+                "unknown error policy `{s}`, expected one of `ignore`, `raise`, `report`"
+            ))),
+        }
+    }
+}
+
+/// a unicode normal form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnicodeForm {
+    Nfc,
+    Nfkc,
+    Nfd,
+    Nfkd,
+}
+
+impl UnicodeForm {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "NFC" => Ok(UnicodeForm::Nfc),
+            "NFKC" => Ok(UnicodeForm::Nfkc),
+            "NFD" => Ok(UnicodeForm::Nfd),
+            "NFKD" => Ok(UnicodeForm::Nfkd),
+            _ => Err(Error::from(format!(
+                "unknown unicode normalization `{s}`, expected one of `NFC`, `NFKC`, `NFD`, `NFKD`"
+            ))),
+        }
+    }
+
+    /// `s` in this normal form, copied only if it is not already.
+    #[inline]
+    fn normalize(self, s: Cow<str>) -> Cow<str> {
+        let quick = match self {
+            UnicodeForm::Nfc => is_nfc_quick(s.chars()),
+            UnicodeForm::Nfkc => is_nfkc_quick(s.chars()),
+            UnicodeForm::Nfd => is_nfd_quick(s.chars()),
+            UnicodeForm::Nfkd => is_nfkd_quick(s.chars()),
+        };
+        if quick == IsNormalized::Yes {
+            return s;
+        }
+        Cow::Owned(match self {
+            UnicodeForm::Nfc => s.nfc().collect(),
+            UnicodeForm::Nfkc => s.nfkc().collect(),
+            UnicodeForm::Nfd => s.nfd().collect(),
+            UnicodeForm::Nfkd => s.nfkd().collect(),
+        })
+    }
+}
+
+/// text transformations applied by the workers before prediction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Preprocess {
+    /// the normal form to convert texts to, before the other steps.
+    pub unicode: Option<UnicodeForm>,
+    pub newline: NewlinePolicy,
+    pub lowercase: bool,
+    /// trim leading and trailing whitespace.
+    pub strip: bool,
+    /// keep only the first tokens of each text, as split by fasttext.
+    pub max_tokens: Option<usize>,
+}
+
+impl Preprocess {
+    /// enable the steps of a `normalize` argument, e.g. `"lower+strip"`.
+    pub fn normalize(mut self, normalize: Option<&str>) -> Result<Self> {
+        for step in normalize.into_iter().flat_map(|n| n.split('+')) {
+            match step.trim() {
+                "lower" => self.lowercase = true,
+                "strip" => self.strip = true,
+                _ => {
+                    return Err(Error::from(format!(
+                        "unknown normalization `{step}`, expected `lower`, `strip` joined by `+`"
+                    )))
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// transform a text, returning the offending text as `Err` if rejected.
+    ///
+    /// Texts are only copied by the steps that change them.
+    #[inline]
+    pub fn apply<'a>(&self, s: Cow<'a, str>) -> std::result::Result<Cow<'a, str>, Cow<'a, str>> {
+        let s = match self.unicode {
+            Some(form) => form.normalize(s),
+            None => s,
+        };
+        let s = normalize_newlines(s, self.newline)?;
+        let s = match self.max_tokens {
+            Some(max_tokens) => truncate_tokens(s, max_tokens),
+            None => s,
+        };
+        let s = if self.strip { trim(s) } else { s };
+        if self.lowercase {
+            Ok(Cow::Owned(s.to_lowercase()))
+        } else {
+            Ok(s)
+        }
+    }
+}
+
+/// minimal probabilities of predictions, by label id.
+#[derive(Debug, Default)]
+pub struct Thresholds {
+    pub default: f32,
+    pub labels: BTreeMap<i32, f32>,
+}
+
+impl Thresholds {
+    /// the same threshold for all labels.
+    pub fn global(threshold: f32) -> Self {
+        Thresholds {
+            default: threshold,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// the threshold passed to fasttext, so that no prediction
+    /// above its label's threshold is dropped.
+    #[inline]
+    pub fn min(&self) -> f32 {
+        self.labels.values().fold(self.default, |a, &b| a.min(b))
+    }
+
+    #[inline]
+    pub fn keep(&self, label: i32, prob: f32) -> bool {
+        prob >= *self.labels.get(&label).unwrap_or(&self.default)
+    }
+}
+
+/// how the workers turn each text into a row of predictions.
+pub struct PredictOptions {
+    pub k: i32,
+    pub thresholds: Thresholds,
+    /// if set, drop other labels before taking the top `k`.
+    pub allowed: Option<BTreeSet<i32>>,
+    pub transform: Preprocess,
+    pub on_empty: EmptyPolicy,
+    pub on_error: ErrorPolicy,
+    /// scale the probabilities of each row to sum to one.
+    pub renormalize: bool,
+    /// output natural logs of the probabilities, after renormalization.
+    pub log_probs: bool,
+    /// compute the entropy of the full label distribution of each row,
+    /// which predicts all labels rather than the top `k`.
+    pub entropy: bool,
+    /// fail the batch if it does not finish within this time.
+    pub timeout: Option<Duration>,
+    /// fail the batch once set, e.g. by another thread whose caller gave up.
+    pub cancelled: Option<Arc<AtomicBool>>,
+    /// fail on predicted labels without an id, rather than giving them `-1`.
+    pub strict_labels: bool,
+    /// if set, time the workers and the writer of `predict_rows`.
+    pub stats: Option<Arc<BatchStats>>,
+}
+
+impl PredictOptions {
+    /// options without any of the optional steps.
+    pub fn new(k: i32, thresholds: Thresholds) -> Self {
+        PredictOptions {
+            k,
+            thresholds,
+            allowed: None,
+            transform: Preprocess::default(),
+            on_empty: EmptyPolicy::Zero,
+            on_error: ErrorPolicy::Ignore,
+            renormalize: false,
+            log_probs: false,
+            entropy: false,
+            timeout: None,
+            cancelled: None,
+            strict_labels: false,
+            stats: None,
+        }
+    }
+
+    /// the number of predictions to request from fasttext, enough to keep
+    /// `k` allowed labels after filtering out all disallowed ones.
+    pub(crate) fn candidates(&self, num_labels: usize) -> i32 {
+        if self.entropy {
+            return num_labels as i32;
+        }
+        match &self.allowed {
+            Some(allowed) => {
+                let disallowed = num_labels.saturating_sub(allowed.len());
+                (self.k as usize + disallowed).min(num_labels) as i32
+            }
+            None => self.k,
+        }
+    }
+
+    /// the threshold to request predictions from fasttext with, which must
+    /// not cut the distribution short when computing its entropy.
+    #[inline]
+    pub(crate) fn floor(&self) -> f32 {
+        if self.entropy {
+            -1.0
+        } else {
+            self.thresholds.min()
+        }
+    }
+
+    #[inline]
+    pub(crate) fn allows(&self, label: i32) -> bool {
+        match &self.allowed {
+            Some(allowed) => allowed.contains(&label),
+            None => true,
+        }
+    }
+
+    /// apply `renormalize` then `log_probs` to the probabilities of a row.
+    #[inline]
+    pub(crate) fn rescale(&self, probs: &mut [f32]) {
+        if self.renormalize {
+            let sum: f32 = probs.iter().sum();
+            if sum > 0.0 {
+                probs.iter_mut().for_each(|p| *p /= sum);
+            }
+        }
+        if self.log_probs {
+            probs.iter_mut().for_each(|p| *p = p.ln());
+        }
+    }
+
+    /// fail with "batch cancelled" if `cancelled` is set.
+    #[inline]
+    pub(crate) fn check_cancelled(&self) -> Result<()> {
+        match &self.cancelled {
+            Some(cancelled) if cancelled.load(Ordering::Relaxed) => {
+                Err(Error::from("batch cancelled"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// the value of padding cells in the probabilities.
+    #[inline]
+    pub(crate) fn padding(&self) -> f32 {
+        if self.log_probs {
+            f32::NEG_INFINITY
+        } else {
+            0.0
+        }
+    }
+}
+
+/// apply the newline policy, returning the offending text as `Err`.
+#[inline]
+fn normalize_newlines(
+    s: Cow<str>,
+    newline: NewlinePolicy,
+) -> std::result::Result<Cow<str>, Cow<str>> {
+    if !s.contains('\n') {
+        return Ok(s);
+    }
+    match newline {
+        NewlinePolicy::Strip => Ok(Cow::Owned(s.replace('\n', " "))),
+        NewlinePolicy::Keep => Ok(s),
+        NewlinePolicy::Error => Err(s),
+    }
+}
+
+/// cut `s` before its token `max_tokens`, splitting on the delimiters of the
+/// fasttext tokenizer.
+#[inline]
+fn truncate_tokens(s: Cow<str>, max_tokens: usize) -> Cow<str> {
+    let mut tokens = 0;
+    let mut in_token = false;
+    for (i, c) in s.char_indices() {
+        if matches!(c, ' ' | '\n' | '\t' | '\x0b' | '\x0c' | '\r' | '\0') {
+            in_token = false;
+        } else if !in_token {
+            if tokens == max_tokens {
+                trace!(
+                    "text truncated to {max_tokens} tokens from {} bytes",
+                    s.len()
+                );
+                return match s {
+                    Cow::Borrowed(s) => Cow::Borrowed(&s[..i]),
+                    Cow::Owned(mut s) => {
+                        s.truncate(i);
+                        Cow::Owned(s)
+                    }
+                };
+            }
+            tokens += 1;
+            in_token = true;
+        }
+    }
+    s
+}
+
+/// `s` without leading and trailing whitespace.
+#[inline]
+fn trim(s: Cow<str>) -> Cow<str> {
+    match s {
+        Cow::Borrowed(s) => Cow::Borrowed(s.trim()),
+        Cow::Owned(s) if s.trim().len() == s.len() => Cow::Owned(s),
+        Cow::Owned(s) => Cow::Owned(s.trim().to_string()),
+    }
+}
@@ -0,0 +1,266 @@
+//! the sender -> processor -> writer channel pipeline.
+
+use crate::pool::thread_pool;
+use crate::Error;
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, SendError, Sender};
+use log::{debug, trace};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+const CHANNEL_SIZE: usize = 128;
+
+/// how often the writer calls the interrupt check.
+const INTERRUPT_INTERVAL: Duration = Duration::from_millis(100);
+
+static INTERRUPT_CHECK: OnceLock<fn() -> Result<(), Error>> = OnceLock::new();
+
+/// the default number of items per chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 64;
+
+static CHUNK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_CHUNK_SIZE);
+
+/// set the number of items the sender groups into one chunk.
+///
+/// Each chunk is predicted by a single rayon task, so larger chunks cut the
+/// scheduling overhead on short texts, at the cost of coarser load balancing.
+pub fn set_chunk_size(chunk_size: usize) -> Result<(), Error> {
+    if chunk_size == 0 {
+        return Err(Error::from("chunk_size must be at least 1"));
+    }
+    CHUNK_SIZE.store(chunk_size, Ordering::Relaxed);
+    Ok(())
+}
+
+/// the number of items per chunk, see `set_chunk_size`.
+pub fn chunk_size() -> usize {
+    CHUNK_SIZE.load(Ordering::Relaxed)
+}
+
+/// install a check the writer of every pipeline calls about every 100 ms,
+/// even while waiting for results, e.g. to stop on Ctrl-C.
+///
+/// An error of `check` stops the writer as an error of `write`. Only the
+/// first check installed is kept.
+pub fn set_interrupt_check(check: fn() -> Result<(), Error>) {
+    let _ = INTERRUPT_CHECK.set(check);
+}
+
+/// sends items to the pipeline in chunks of `chunk_size()`.
+///
+/// The last partial chunk is sent by `flush`, or when the sender is dropped.
+pub struct ChunkSender<T> {
+    sender: Sender<Vec<T>>,
+    chunk: Vec<T>,
+    chunk_size: usize,
+    /// the time spent waiting for the processor to take chunks.
+    waited: Duration,
+}
+
+impl<T> ChunkSender<T> {
+    fn new(sender: Sender<Vec<T>>, chunk_size: usize) -> Self {
+        ChunkSender {
+            sender,
+            chunk: Vec::with_capacity(chunk_size),
+            chunk_size,
+            waited: Duration::ZERO,
+        }
+    }
+
+    /// the time spent so far waiting for the processor to take chunks.
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+
+    /// queue an item, sending the chunk once it is full.
+    ///
+    /// Fails once the pipeline has stopped receiving.
+    pub fn send(&mut self, item: T) -> Result<(), SendError<Vec<T>>> {
+        self.chunk.push(item);
+        if self.chunk.len() >= self.chunk_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// send the queued items, if any.
+    pub fn flush(&mut self) -> Result<(), SendError<Vec<T>>> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(self.chunk_size));
+        let start = Instant::now();
+        let sent = self.sender.send(chunk);
+        self.waited += start.elapsed();
+        sent
+    }
+}
+
+impl<T> Drop for ChunkSender<T> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// run the channel pipeline: `send` feeds items, `process` maps them in
+/// parallel on the rayon pool, and `write` receives each result together
+/// with the index of its input.
+///
+/// Items travel in chunks of `chunk_size()`, and each chunk is processed
+/// in order by a single rayon task. The sender and the writer block on
+/// channels, so they run on their own threads rather than the rayon pool,
+/// which would deadlock once concurrent calls occupy all its threads.
+///
+/// Results arrive out of order, so output order relies solely on each result
+/// carrying its input index. The writer checks that every index is written
+/// exactly once, and fails the call otherwise.
+///
+/// The first error of `write`, or of the check of `set_interrupt_check`,
+/// stops the writer, whose dropped receiver in turn stops the processor and
+/// the sender, and is returned once they have exited. Errors of `send` are
+/// returned after the pipeline drains.
+pub fn run_pipeline<T, R, E, S, P, W>(send: S, process: P, write: W) -> Result<(), E>
+where
+    T: Send,
+    R: Send,
+    E: From<Error> + Send,
+    S: FnOnce(ChunkSender<T>) -> Result<(), E> + Send,
+    P: Fn(usize, T) -> R + Sync,
+    W: FnMut(usize, R) -> Result<(), E> + Send,
+{
+    run_pipeline_until(None, send, process, write)
+}
+
+/// `run_pipeline` that fails with "batch timed out" if the writer is still
+/// waiting for results at `deadline`.
+///
+/// The writer then stops as on an error of `write`. Items being processed
+/// cannot be interrupted, so the call returns once they finish.
+pub fn run_pipeline_until<T, R, E, S, P, W>(
+    deadline: Option<Instant>,
+    send: S,
+    process: P,
+    mut write: W,
+) -> Result<(), E>
+where
+    T: Send,
+    R: Send,
+    E: From<Error> + Send,
+    S: FnOnce(ChunkSender<T>) -> Result<(), E> + Send,
+    P: Fn(usize, T) -> R + Sync,
+    W: FnMut(usize, R) -> Result<(), E> + Send,
+{
+    let pool = thread_pool()?;
+    let (item_sender, item_receiver) = bounded::<Vec<T>>(CHANNEL_SIZE);
+    let (result_sender, result_receiver) = bounded::<(usize, Vec<R>)>(CHANNEL_SIZE);
+    let item_sender = ChunkSender::new(item_sender, chunk_size());
+    let mut sender_result = Ok(());
+    let mut writer_result = Ok(());
+    let mut processed = 0;
+    let mut written = Vec::<bool>::new();
+    std::thread::scope(|s| {
+        // text sender
+        s.spawn(|| {
+            sender_result = send(item_sender);
+            debug!("text sender thread finished");
+        });
+
+        // processor
+        s.spawn(|| {
+            processed = pool.install(|| process_items(item_receiver, result_sender, &process));
+            debug!("processor thread finished");
+        });
+
+        // result writer
+        let interrupt_check = INTERRUPT_CHECK.get();
+        let mut checked = Instant::now();
+        'chunks: loop {
+            let wake = interrupt_check.map(|_| checked + INTERRUPT_INTERVAL);
+            let received = match deadline.into_iter().chain(wake).min() {
+                Some(until) => result_receiver.recv_deadline(until),
+                None => result_receiver.recv().map_err(RecvTimeoutError::from),
+            };
+            if let Some(check) = interrupt_check {
+                if checked.elapsed() >= INTERRUPT_INTERVAL {
+                    checked = Instant::now();
+                    if let Err(e) = check() {
+                        writer_result = Err(e.into());
+                        break;
+                    }
+                }
+            }
+            let (start, results) = match received {
+                Ok(chunk) => chunk,
+                Err(RecvTimeoutError::Timeout) if deadline.is_none_or(|d| Instant::now() < d) => {
+                    continue
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    writer_result = Err(Error::from("batch timed out").into());
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            trace!("results {start}..{} received", start + results.len());
+            if start + results.len() > written.len() {
+                written.resize(start + results.len(), false);
+            }
+            for (i, result) in (start..).zip(results) {
+                written[i] = true;
+                writer_result = write(i, result);
+                if writer_result.is_err() {
+                    break 'chunks;
+                }
+            }
+        }
+        drop(result_receiver);
+    });
+    sender_result?;
+    writer_result?;
+    if written.len() != processed || written.contains(&false) {
+        return Err(Error::from(format!(
+            "internal error: {processed} results processed, but not all of {} rows written",
+            written.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// returns the number of results sent.
+#[inline]
+fn process_items<T, R, P>(
+    item_receiver: Receiver<Vec<T>>,
+    result_sender: Sender<(usize, Vec<R>)>,
+    process: &P,
+) -> usize
+where
+    T: Send,
+    R: Send,
+    P: Fn(usize, T) -> R + Sync,
+{
+    let count = item_receiver
+        .iter()
+        .scan(0, |next, chunk| {
+            let start = *next;
+            *next += chunk.len();
+            Some((start, chunk))
+        })
+        .par_bridge()
+        .map(|(start, chunk)| {
+            let len = chunk.len();
+            let results = (start..)
+                .zip(chunk)
+                .map(|(i, item)| process(i, item))
+                .collect();
+            if result_sender.send((start, results)).is_err() {
+                None
+            } else {
+                Some(len)
+            }
+        })
+        .while_some()
+        .sum();
+    drop(result_sender);
+    count
+}
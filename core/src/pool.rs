@@ -0,0 +1,136 @@
+//! the crate-owned rayon pool that runs the pipeline.
+//!
+//! The pool is separate from the rayon global pool, so neither another
+//! rayon consumer in the process nor a module reload can get in its way.
+//! Unlike the global pool, it can be replaced by `set_num_threads` at any
+//! time.
+//!
+//! The number of threads of the pool built on first use is, by precedence:
+//! that of `init_threads` or `configure`, then `FASTTEXT_PARALLEL_THREADS`,
+//! then `max(MIN_THREADS, available_parallelism())`. The standard library
+//! bounds the available parallelism by the CPU affinity and, on Linux, by
+//! the cgroup CPU quota of the process.
+
+use crate::{Error, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::cmp::max;
+use std::sync::{Arc, RwLock};
+use std::thread::available_parallelism;
+
+/// the minimum number of threads of the pool built by default, and the
+/// default floor of `configure`.
+pub const MIN_THREADS: usize = 3;
+
+/// the environment variable giving the number of threads of the pool built
+/// on first use, unless set in code.
+pub const THREADS_ENV: &str = "FASTTEXT_PARALLEL_THREADS";
+
+/// the pool, `None` until built. Calls hold an `Arc` of the pool they
+/// started on, so a replaced pool lives until they finish.
+static THREAD_POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+/// build the pool with `max(min_threads, num_threads)` threads, where they
+/// default to `MIN_THREADS` and `FASTTEXT_PARALLEL_THREADS` or the available
+/// parallelism. The number of threads must be at least 1.
+///
+/// Fails if the pool has already been built.
+pub fn configure(num_threads: Option<usize>, min_threads: Option<usize>) -> Result<()> {
+    let mut pool = THREAD_POOL.write().unwrap();
+    if pool.is_some() {
+        return Err(Error::from(
+            "thread pool already built, configure() must be called before the first batch",
+        ));
+    }
+    let num_threads = max(
+        min_threads.unwrap_or(MIN_THREADS),
+        match num_threads {
+            Some(num_threads) => num_threads,
+            None => env_threads()?.map_or_else(default_parallelism, Ok)?,
+        },
+    );
+    if num_threads == 0 {
+        return Err(Error::from("at least 1 thread is required, got 0"));
+    }
+    *pool = Some(Arc::new(build_thread_pool(num_threads)?));
+    Ok(())
+}
+
+/// build the pool with exactly `num_threads` threads, which must be at
+/// least 1.
+///
+/// Fails if the pool has already been built.
+pub fn init_threads(num_threads: usize) -> Result<()> {
+    if num_threads == 0 {
+        return Err(Error::from("at least 1 thread is required, got 0"));
+    }
+    let mut pool = THREAD_POOL.write().unwrap();
+    if pool.is_some() {
+        return Err(Error::from(
+            "thread pool already built, init_threads() must be called before the first batch",
+        ));
+    }
+    *pool = Some(Arc::new(build_thread_pool(num_threads)?));
+    Ok(())
+}
+
+/// build the pool with `num_threads` threads, replacing the current one if
+/// any.
+///
+/// Calls in progress finish on the pool they started on, later calls run on
+/// the new one.
+pub fn set_num_threads(num_threads: usize) -> Result<()> {
+    if num_threads == 0 {
+        return Err(Error::from("at least 1 thread is required, got 0"));
+    }
+    let new_pool = Arc::new(build_thread_pool(num_threads)?);
+    let old_pool = THREAD_POOL.write().unwrap().replace(new_pool);
+    // the last call on the old pool, if any, drops it.
+    drop(old_pool);
+    Ok(())
+}
+
+/// the number of threads set by `FASTTEXT_PARALLEL_THREADS`, if any.
+fn env_threads() -> Result<Option<usize>> {
+    let Some(value) = std::env::var_os(THREADS_ENV) else {
+        return Ok(None);
+    };
+    match value.to_str().and_then(|v| v.trim().parse::<usize>().ok()) {
+        Some(n) if n > 0 => Ok(Some(n)),
+        _ => Err(Error::from(format!(
+            "invalid {THREADS_ENV}={value:?}, expected a positive integer"
+        ))),
+    }
+}
+
+#[inline]
+fn default_parallelism() -> Result<usize> {
+    available_parallelism()
+        .map(|n| n.get())
+        .map_err(|e| Error::from(format!("failed to initialize rayon crate, {e}")))
+}
+
+/// the pool, built with the default policy on first use.
+pub fn thread_pool() -> Result<Arc<ThreadPool>> {
+    if let Some(pool) = THREAD_POOL.read().unwrap().as_ref() {
+        return Ok(pool.clone());
+    }
+    let mut pool = THREAD_POOL.write().unwrap();
+    if let Some(pool) = pool.as_ref() {
+        return Ok(pool.clone());
+    }
+    let num_threads = match env_threads()? {
+        Some(num_threads) => num_threads,
+        None => max(MIN_THREADS, default_parallelism()?),
+    };
+    let built = Arc::new(build_thread_pool(num_threads)?);
+    *pool = Some(built.clone());
+    Ok(built)
+}
+
+fn build_thread_pool(num_threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|i| format!("fasttext-parallel-{i}"))
+        .build()
+        .map_err(|e| Error::from(format!("failed to initialize rayon crate, {e}")))
+}
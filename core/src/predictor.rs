@@ -0,0 +1,1192 @@
+//! a fasttext model with its label ids, predicting in parallel.
+
+use crate::mapped::MappedModel;
+use crate::meter::Meter;
+use crate::options::{EmptyPolicy, ErrorPolicy, PredictOptions, Thresholds};
+use crate::pipeline::{run_pipeline, run_pipeline_until};
+use crate::pool::thread_pool;
+use crate::ChunkSender;
+use crate::{Error, ErrorKind, Result};
+use fasttext::{FastText, ModelName, Prediction};
+use flate2::bufread::MultiGzDecoder;
+use log::{debug, error, trace};
+use memmap2::Mmap;
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayViewMut2, Axis, Ix2};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::time::Instant;
+
+/// a fasttext model whose labels are numbered `0..num_labels` as i32.
+pub struct ParallelPredictor {
+    model: FastText,
+    /// the mapped matrices of models loaded by `load_mapped`, which `model`
+    /// lacks.
+    mapped: Option<MappedModel>,
+    label_dict: BTreeMap<String, i32>,
+    reverse_label_dict: BTreeMap<i32, String>,
+    /// the training frequency of each label, indexed by label id.
+    label_counts: Vec<i64>,
+    model_name: ModelName,
+}
+
+/// an integer type label ids are written as, which must hold the ids of
+/// all labels of the model and the `-1` of unknown labels.
+pub trait LabelId: Copy + Send + Sync {
+    fn from_id(id: i32) -> Self;
+}
+
+impl LabelId for i16 {
+    #[inline]
+    fn from_id(id: i32) -> Self {
+        id as i16
+    }
+}
+
+impl LabelId for i32 {
+    #[inline]
+    fn from_id(id: i32) -> Self {
+        id
+    }
+}
+
+/// a float type probabilities are written as.
+pub trait Probability: Copy + Send + Sync {
+    const ZERO: Self;
+    fn from_prob(prob: f32) -> Self;
+}
+
+impl Probability for f32 {
+    const ZERO: Self = 0.0;
+
+    #[inline]
+    fn from_prob(prob: f32) -> Self {
+        prob
+    }
+}
+
+impl Probability for f64 {
+    const ZERO: Self = 0.0;
+
+    #[inline]
+    fn from_prob(prob: f32) -> Self {
+        prob as f64
+    }
+}
+
+/// the bits of an IEEE 754 half-precision float, which numpy views as
+/// float16, rounded to nearest even.
+impl Probability for u16 {
+    const ZERO: Self = 0;
+
+    #[inline]
+    fn from_prob(prob: f32) -> Self {
+        let bits = prob.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+        if exponent == 0xff {
+            let nan = if mantissa != 0 { 0x200 } else { 0 };
+            return sign | 0x7c00 | nan;
+        }
+        let exponent = exponent - 127 + 15;
+        if exponent >= 0x1f {
+            return sign | 0x7c00;
+        }
+        // the half mantissa, with the bits shifted out to round.
+        let (half, shift) = if exponent <= 0 {
+            if exponent < -10 {
+                return sign;
+            }
+            (mantissa | 0x80_0000, (14 - exponent) as u32)
+        } else {
+            (((exponent as u32) << 23) | mantissa, 13)
+        };
+        let rest = half & ((1 << shift) - 1);
+        let middle = 1 << (shift - 1);
+        let half = half >> shift;
+        // a carry out of the mantissa rightly increments the exponent.
+        let rounded = if rest > middle || (rest == middle && half & 1 == 1) {
+            half + 1
+        } else {
+            half
+        };
+        sign | rounded as u16
+    }
+}
+
+/// the predictions of one text, as produced by the workers.
+#[derive(Default)]
+pub struct Row {
+    pub labels: Vec<i32>,
+    pub probs: Vec<f32>,
+    /// the text is missing or blank, only checked if `on_empty` is not `Zero`.
+    pub empty: bool,
+    /// the prediction failed with this error.
+    pub error: Option<String>,
+    /// the entropy of the full label distribution, if requested.
+    pub entropy: Option<f32>,
+    /// the text was rejected for containing a newline.
+    pub newline: bool,
+    /// a predicted label without an id, only checked if `strict_labels`.
+    pub unknown_label: Option<String>,
+}
+
+impl Row {
+    fn empty() -> Self {
+        Row {
+            empty: true,
+            ..Default::default()
+        }
+    }
+
+    /// fail on a rejected newline, an unknown label, or an error under
+    /// `ErrorPolicy::Raise`, where `index` is the input index of the text.
+    ///
+    /// Returns whether the prediction failed.
+    fn check(&self, index: usize, options: &PredictOptions) -> Result<bool> {
+        if self.newline {
+            return Err(Error::new(
+                ErrorKind::Prediction,
+                format!("text {index} contains a newline"),
+            ));
+        }
+        if let Some(label) = &self.unknown_label {
+            return Err(Error::new(
+                ErrorKind::Prediction,
+                format!("text {index} predicted label {label}, which has no id"),
+            ));
+        }
+        match &self.error {
+            Some(e) if options.on_error == ErrorPolicy::Raise => Err(Error::new(
+                ErrorKind::Prediction,
+                format!("error predicting text {index}: {e}"),
+            )),
+            Some(_) => Ok(true),
+            None => Ok(false),
+        }
+    }
+}
+
+/// predictions of a batch, rows padded with zeros beyond their counts, with
+/// probabilities written as `P`.
+pub struct Predictions<P = f32> {
+    pub labels: Array2<i32>,
+    pub probs: Array2<P>,
+    /// the number of predictions written in each row.
+    pub counts: Array1<i32>,
+    /// sorted indices of the empty rows.
+    pub empty: Vec<usize>,
+    /// the input indices of the rows whose prediction failed, sorted, with
+    /// their errors.
+    pub errors: Vec<(usize, String)>,
+    /// the entropy of each row in nats, NaN if not computed.
+    pub entropy: Array1<f32>,
+}
+
+impl<P: Probability> Predictions<P> {
+    fn new(rows: usize, options: &PredictOptions) -> Self {
+        let k = options.k.max(0) as usize;
+        Predictions {
+            labels: Array2::default(Ix2(rows, k)),
+            probs: Array2::from_elem(Ix2(rows, k), P::from_prob(options.padding())),
+            counts: Array1::zeros(rows),
+            empty: Vec::new(),
+            errors: Vec::new(),
+            entropy: Array1::from_elem(rows, f32::NAN),
+        }
+    }
+
+    /// write row `i`, whose text is at `offset + i` of the input.
+    fn write(&mut self, i: usize, row: Row, offset: usize, options: &PredictOptions) -> Result<()> {
+        let n = row.labels.len();
+        self.labels.row_mut(i).as_slice_mut().unwrap()[..n].copy_from_slice(&row.labels);
+        let probs = self.probs.row_mut(i).into_slice().unwrap();
+        for (prob, &p) in probs.iter_mut().zip(&row.probs) {
+            *prob = P::from_prob(p);
+        }
+        self.counts[i] = n as i32;
+        if let Some(entropy) = row.entropy {
+            self.entropy[i] = entropy;
+        }
+        if row.empty {
+            self.empty.push(i);
+        }
+        if row.check(offset + i, options)? {
+            self.errors
+                .push((offset + i, row.error.unwrap_or_default()));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Self {
+        self.empty.sort_unstable();
+        self.errors.sort_unstable_by_key(|&(i, _)| i);
+        self
+    }
+
+    /// keep only the rows that are not empty, returning their indices.
+    pub fn skip_empty(&mut self) -> Array1<i64> {
+        let empty = std::mem::take(&mut self.empty);
+        self.skip(&empty)
+    }
+
+    /// keep only the rows not in the sorted `rows`, returning their indices.
+    ///
+    /// Clears `empty`, whose indices no longer apply.
+    pub fn skip(&mut self, rows: &[usize]) -> Array1<i64> {
+        let mut skipped = rows.iter().peekable();
+        let keep: Vec<usize> = (0..self.counts.len())
+            .filter(|i| skipped.next_if_eq(&i).is_none())
+            .collect();
+        self.labels = self.labels.select(Axis(0), &keep);
+        self.probs = self.probs.select(Axis(0), &keep);
+        self.counts = self.counts.select(Axis(0), &keep);
+        self.entropy = self.entropy.select(Axis(0), &keep);
+        self.empty.clear();
+        keep.into_iter().map(|i| i as i64).collect()
+    }
+
+    /// whether each cell holds a prediction rather than padding.
+    pub fn mask(&self) -> Array2<bool> {
+        Array2::from_shape_fn(self.labels.raw_dim(), |(i, j)| j < self.counts[i] as usize)
+    }
+
+    /// whether each row may have been cut off by `k`, judged by it holding
+    /// exactly `k` predictions. A row whose labels above the threshold are
+    /// exactly `k` is reported as truncated too, as the two cases cannot be
+    /// told apart from the predictions alone.
+    pub fn truncated(&self) -> Array1<bool> {
+        let k = self.labels.ncols();
+        self.counts.mapv(|count| k > 0 && count as usize == k)
+    }
+}
+
+/// predictions of a batch in coordinate format, one entry per prediction,
+/// sorted by row.
+pub struct SparsePredictions {
+    /// the index of the text of each prediction.
+    pub rows: Array1<i64>,
+    pub labels: Array1<i32>,
+    pub probs: Array1<f32>,
+}
+
+impl ParallelPredictor {
+    /// number the labels of a loaded model.
+    ///
+    /// Fails if a label appears twice, as in a corrupt model, since the ids
+    /// and the labels would no longer map one to one.
+    pub fn new(model: FastText) -> Result<Self> {
+        let (labels, label_counts) = model.get_labels()?;
+        let mut label_dict = BTreeMap::new();
+        for (i, label) in labels.iter().enumerate() {
+            if let Some(first) = label_dict.insert(label.clone(), i as i32) {
+                return Err(Error::new(
+                    ErrorKind::ModelLoad,
+                    format!("duplicate label {label} at ids {first} and {i}"),
+                ));
+            }
+        }
+        let reverse_label_dict: BTreeMap<i32, String> = labels
+            .into_iter()
+            .enumerate()
+            .map(|(i, lab)| (i as i32, lab))
+            .collect();
+        let model_name = model.get_args().model();
+        Ok(ParallelPredictor {
+            model,
+            mapped: None,
+            label_dict,
+            reverse_label_dict,
+            label_counts,
+            model_name,
+        })
+    }
+
+    /// number the labels by `label_ids` rather than by their order in the
+    /// model, so that ids stay the same across models trained separately.
+    ///
+    /// `label_ids` must give each label of the model a distinct id below the
+    /// number of labels, as label ids index arrays. Labels the model does not
+    /// have are ignored.
+    pub fn with_label_ids(mut self, label_ids: &BTreeMap<String, i32>) -> Result<Self> {
+        let num_labels = self.label_dict.len();
+        let mut reverse_label_dict = BTreeMap::new();
+        let mut label_counts = vec![0; num_labels];
+        for (label, &old_id) in &self.label_dict {
+            let id = *label_ids
+                .get(label)
+                .ok_or_else(|| Error::from(format!("no id for label {label}")))?;
+            if id < 0 || id as usize >= num_labels {
+                return Err(Error::from(format!(
+                    "id {id} of label {label} is not in 0..{num_labels}"
+                )));
+            }
+            if let Some(other) = reverse_label_dict.insert(id, label.clone()) {
+                return Err(Error::from(format!(
+                    "labels {other} and {label} have the same id {id}"
+                )));
+            }
+            label_counts[id as usize] = self.label_counts[old_id as usize];
+        }
+        for id in self.label_dict.values_mut() {
+            *id = label_ids[&self.reverse_label_dict[id]];
+        }
+        self.reverse_label_dict = reverse_label_dict;
+        self.label_counts = label_counts;
+        Ok(self)
+    }
+
+    /// load a model from path.
+    pub fn load(path: &str) -> Result<Self> {
+        let mut model = FastText::new();
+        model
+            .load_model(path)
+            .map_err(|e| Error::new(ErrorKind::ModelLoad, e))?;
+        debug!("model loaded");
+        Self::new(model)
+    }
+
+    /// load a model from path, memory mapping its matrices rather than
+    /// reading them.
+    ///
+    /// The pages of the matrices are read on first use, and shared by the
+    /// processes mapping the same file. Predictions and vectors are
+    /// bit-identical to those of `load`. Quantized models cannot be mapped.
+    pub fn load_mapped(path: &str) -> Result<Self> {
+        let mapped = MappedModel::open(path)?;
+        let mut model = FastText::new();
+        mapped.with_skeleton(|skeleton| {
+            model
+                .load_model(skeleton)
+                .map_err(|e| Error::new(ErrorKind::ModelLoad, e))
+        })?;
+        debug!("model mapped");
+        let mut predictor = Self::new(model)?;
+        predictor.mapped = Some(mapped);
+        Ok(predictor)
+    }
+
+    /// the fasttext model, which has the dictionary but no matrices for
+    /// models loaded by `load_mapped`. Predict and compute vectors with
+    /// `predict_text`, `word_vector` and `sentence_vector` instead.
+    pub fn model(&self) -> &FastText {
+        &self.model
+    }
+
+    /// the fasttext model, which fails with `ErrorKind::ModelType` for models
+    /// loaded by `load_mapped`, as they have no matrices to change.
+    pub fn model_mut(&mut self) -> Result<&mut FastText> {
+        match self.mapped {
+            Some(_) => Err(Error::new(
+                ErrorKind::ModelType,
+                "memory mapped models cannot be changed",
+            )),
+            None => Ok(&mut self.model),
+        }
+    }
+
+    /// whether the model was loaded by `load_mapped`.
+    pub fn is_mapped(&self) -> bool {
+        self.mapped.is_some()
+    }
+
+    /// predict the labels of `text` as `FastText::predict`.
+    #[inline]
+    pub fn predict_text(
+        &self,
+        text: &str,
+        k: i32,
+        threshold: f32,
+    ) -> std::result::Result<Vec<Prediction>, String> {
+        match &self.mapped {
+            Some(mapped) => mapped.predict(text, k, threshold),
+            None => self.model.predict(text, k, threshold),
+        }
+    }
+
+    /// the vector of `word` as `FastText::get_word_vector`.
+    pub fn word_vector(&self, word: &str) -> std::result::Result<Vec<f32>, String> {
+        match &self.mapped {
+            Some(mapped) => mapped.word_vector(word),
+            None => self.model.get_word_vector(word),
+        }
+    }
+
+    /// the vector of `text` as `FastText::get_sentence_vector`.
+    pub fn sentence_vector(&self, text: &str) -> std::result::Result<Vec<f32>, String> {
+        match &self.mapped {
+            Some(mapped) => mapped.sentence_vector(text),
+            None => self.model.get_sentence_vector(text),
+        }
+    }
+
+    /// the mapping from label to label id.
+    pub fn label_dict(&self) -> &BTreeMap<String, i32> {
+        &self.label_dict
+    }
+
+    /// the mapping from label id to label.
+    pub fn reverse_label_dict(&self) -> &BTreeMap<i32, String> {
+        &self.reverse_label_dict
+    }
+
+    /// the training frequency of each label, indexed by label id.
+    pub fn label_counts(&self) -> &[i64] {
+        &self.label_counts
+    }
+
+    /// the index of each label in the model, e.g. its row of the output
+    /// matrix, indexed by label id. The two differ for labels numbered by
+    /// `with_label_ids`.
+    pub fn label_indices(&self) -> Result<Vec<usize>> {
+        let (labels, _) = self.model.get_labels()?;
+        let mut indices = vec![0; labels.len()];
+        for (index, label) in labels.iter().enumerate() {
+            indices[self.label_dict[label] as usize] = index;
+        }
+        Ok(indices)
+    }
+
+    pub fn num_labels(&self) -> usize {
+        self.label_dict.len()
+    }
+
+    /// the architecture the model was trained with: `"supervised"`,
+    /// `"cbow"` or `"skipgram"`.
+    pub fn model_type(&self) -> &'static str {
+        match self.model_name {
+            ModelName::SUP => "supervised",
+            ModelName::CBOW => "cbow",
+            ModelName::SG => "skipgram",
+        }
+    }
+
+    /// fail with `ErrorKind::ModelType` unless the model is supervised,
+    /// as unsupervised models have no labels to predict.
+    pub fn check_supervised(&self) -> Result<()> {
+        match self.model_name {
+            ModelName::SUP => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::ModelType,
+                format!(
+                    "this method requires a supervised model, got a {} model",
+                    self.model_type()
+                ),
+            )),
+        }
+    }
+
+    /// predict texts using multithreading.
+    ///
+    /// Returns the label ids and probabilities of the top `k` predictions
+    /// above `threshold`, in input order. Rows with fewer than k predictions
+    /// are padded with zeros, and `None` texts give empty rows.
+    pub fn predict_batch(
+        &self,
+        texts: impl Iterator<Item = Option<String>> + Send,
+        k: i32,
+        threshold: f32,
+    ) -> Result<(Array2<i32>, Array2<f32>)> {
+        self.check_supervised()?;
+        let options = PredictOptions::new(k, Thresholds::global(threshold));
+        let mut rows = Vec::new();
+        run_pipeline(
+            |mut text_sender: ChunkSender<Option<String>>| {
+                for text in texts {
+                    if text_sender.send(text).is_err() {
+                        break;
+                    }
+                }
+                Ok::<_, Error>(())
+            },
+            |i, text| self.predict_row(i, text, &options),
+            |i, row| {
+                if i >= rows.len() {
+                    rows.resize_with(i + 1, Row::default);
+                }
+                rows[i] = row;
+                Ok(())
+            },
+        )?;
+        let mut predictions = Predictions::new(rows.len(), &options);
+        for (i, row) in rows.into_iter().enumerate() {
+            predictions.write(i, row, 0, &options)?;
+        }
+        Ok((predictions.labels, predictions.probs))
+    }
+
+    /// predict `rows` texts fed by `send` using multithreading, where
+    /// `offset` is the input index of the first text, used in errors.
+    ///
+    /// Probabilities are converted to `P` by the writer as rows arrive.
+    pub fn predict_rows<P, E, S, T>(
+        &self,
+        rows: usize,
+        offset: usize,
+        options: &PredictOptions,
+        send: S,
+    ) -> std::result::Result<Predictions<P>, E>
+    where
+        P: Probability,
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let mut predictions = Predictions::new(rows, options);
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| match &options.stats {
+                None => self.predict_row(i, text, options),
+                Some(stats) => {
+                    let start = Instant::now();
+                    let row = self.predict_row(i, text, options);
+                    stats.add_worker(start.elapsed());
+                    row
+                }
+            },
+            |i, row| {
+                let start = options.stats.as_ref().map(|_| Instant::now());
+                options.check_cancelled()?;
+                predictions.write(i, row, offset, options)?;
+                if let (Some(stats), Some(start)) = (&options.stats, start) {
+                    stats.add_writer(start.elapsed());
+                }
+                Ok(())
+            },
+        )?;
+        Ok(predictions.finish())
+    }
+
+    /// predict texts fed by `send` using multithreading into `labels` and
+    /// `probs`, which are `(rows, options.k)` arrays owned by the caller.
+    ///
+    /// Each row is written whole, with the same padding as `predict_rows`,
+    /// so the arrays need not be initialized. Returns the number of
+    /// predictions of each row.
+    pub fn predict_into<L, E, S, T>(
+        &self,
+        options: &PredictOptions,
+        mut labels: ArrayViewMut2<L>,
+        mut probs: ArrayViewMut2<f32>,
+        send: S,
+    ) -> std::result::Result<Array1<i32>, E>
+    where
+        L: LabelId,
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let k = options.k.max(0) as usize;
+        let rows = labels.nrows();
+        if labels.dim() != (rows, k) || probs.dim() != (rows, k) {
+            return Err(Error::from(format!(
+                "expected output arrays of shape ({rows}, {k}), got {:?} and {:?}",
+                labels.dim(),
+                probs.dim()
+            ))
+            .into());
+        }
+        let padding = options.padding();
+        let mut counts = Array1::zeros(rows);
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| self.predict_row(i, text, options),
+            |i, row| {
+                row.check(i, options)?;
+                let n = row.labels.len();
+                let (mut labels, mut probs) = (labels.row_mut(i), probs.row_mut(i));
+                labels.fill(L::from_id(0));
+                probs.fill(padding);
+                for (cell, &label) in labels.iter_mut().zip(&row.labels) {
+                    *cell = L::from_id(label);
+                }
+                probs
+                    .slice_mut(s![..n])
+                    .assign(&ArrayView1::from(&row.probs));
+                counts[i] = n as i32;
+                Ok(())
+            },
+        )?;
+        Ok(counts)
+    }
+
+    /// predict the best label of `rows` texts fed by `send` using
+    /// multithreading, where `options.k` should be 1.
+    ///
+    /// Returns the label id and probability of each text in flat arrays,
+    /// `-1` and NaN where nothing is predicted.
+    pub fn predict_top1<E, S, T>(
+        &self,
+        rows: usize,
+        options: &PredictOptions,
+        send: S,
+    ) -> std::result::Result<(Array1<i32>, Array1<f32>), E>
+    where
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let mut labels = Array1::from_elem(rows, -1);
+        let mut probs = Array1::from_elem(rows, f32::NAN);
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| self.predict_row(i, text, options),
+            |i, row| {
+                row.check(i, options)?;
+                if let (Some(&label), Some(&prob)) = (row.labels.first(), row.probs.first()) {
+                    labels[i] = label;
+                    probs[i] = prob;
+                }
+                Ok(())
+            },
+        )?;
+        Ok((labels, probs))
+    }
+
+    /// evaluate the predictions of texts fed by `send` against the `gold`
+    /// label ids of each text, using multithreading.
+    ///
+    /// A prediction is correct if it is among the gold labels of its text.
+    /// Texts without gold labels are not counted, like in `test_lines`.
+    pub fn evaluate<E, S, T>(
+        &self,
+        gold: &[Vec<i32>],
+        options: &PredictOptions,
+        send: S,
+    ) -> std::result::Result<Meter, E>
+    where
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let mut meter = Meter::default();
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| self.predict_row(i, text, options),
+            |i, row| {
+                options.check_cancelled()?;
+                row.check(i, options)?;
+                let gold = gold.get(i).ok_or_else(|| {
+                    Error::from(format!(
+                        "text {i} is beyond the {} gold label lists",
+                        gold.len()
+                    ))
+                })?;
+                if !gold.is_empty() {
+                    meter.add(gold, &row.labels);
+                }
+                Ok(())
+            },
+        )?;
+        Ok(meter)
+    }
+
+    /// count the best predicted label of texts fed by `send` against the
+    /// `gold` label id of each text, using multithreading.
+    ///
+    /// Returns a `(num_labels + 1, num_labels + 1)` matrix whose rows are
+    /// gold labels and columns predicted labels. The last row counts gold
+    /// labels of `-1`, i.e. unknown, and the last column texts without
+    /// prediction.
+    pub fn confusion_matrix<E, S, T>(
+        &self,
+        gold: &[i32],
+        options: &PredictOptions,
+        send: S,
+    ) -> std::result::Result<Array2<i64>, E>
+    where
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let n = self.num_labels();
+        let index = |label: i32| if label < 0 { n } else { label as usize };
+        let mut matrix = Array2::zeros((n + 1, n + 1));
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| self.predict_row(i, text, options),
+            |i, row| {
+                options.check_cancelled()?;
+                row.check(i, options)?;
+                let gold = gold.get(i).ok_or_else(|| {
+                    Error::from(format!("text {i} is beyond the {} gold labels", gold.len()))
+                })?;
+                let predicted = row.labels.first().copied().unwrap_or(-1);
+                matrix[[index(*gold), index(predicted)]] += 1;
+                Ok(())
+            },
+        )?;
+        Ok(matrix)
+    }
+
+    /// predict the probability of every label for `rows` texts fed by `send`
+    /// using multithreading, where `options.k` should be the number of
+    /// labels.
+    ///
+    /// Returns a `(rows, num_labels)` matrix of `P` whose columns are label
+    /// ids, with zeros for labels not predicted.
+    pub fn predict_proba<P, E, S, T>(
+        &self,
+        rows: usize,
+        options: &PredictOptions,
+        send: S,
+    ) -> std::result::Result<Array2<P>, E>
+    where
+        P: Probability,
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let mut probs = Array2::from_elem((rows, self.num_labels()), P::ZERO);
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| self.predict_row(i, text, options),
+            |i, row| {
+                row.check(i, options)?;
+                for (&label, &prob) in row.labels.iter().zip(&row.probs) {
+                    probs[[i, label as usize]] = P::from_prob(prob);
+                }
+                Ok(())
+            },
+        )?;
+        Ok(probs)
+    }
+
+    /// predict texts fed by `send` using multithreading, without padding.
+    ///
+    /// Results are appended to flat vectors as they arrive, then sorted by
+    /// row, so memory grows with the number of predictions rather than with
+    /// `rows * k`.
+    pub fn predict_sparse<E, S, T>(
+        &self,
+        options: &PredictOptions,
+        send: S,
+    ) -> std::result::Result<SparsePredictions, E>
+    where
+        E: From<Error> + Send,
+        S: FnOnce(ChunkSender<Option<T>>) -> std::result::Result<(), E> + Send,
+        T: AsRef<str> + Send,
+    {
+        self.check_supervised()?;
+        let mut entries = Vec::<(usize, i32, f32)>::new();
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, text| self.predict_row(i, text, options),
+            |i, row| {
+                row.check(i, options)?;
+                entries.extend(
+                    row.labels
+                        .into_iter()
+                        .zip(row.probs)
+                        .map(|(label, prob)| (i, label, prob)),
+                );
+                Ok(())
+            },
+        )?;
+        // stable, so that the predictions of a row stay in order.
+        entries.sort_by_key(|&(i, _, _)| i);
+        Ok(SparsePredictions {
+            rows: entries.iter().map(|&(i, _, _)| i as i64).collect(),
+            labels: entries.iter().map(|&(_, label, _)| label).collect(),
+            probs: entries.iter().map(|&(_, _, prob)| prob).collect(),
+        })
+    }
+
+    /// write the word vectors of the vocabulary to `path` in the text `.vec`
+    /// format: a `num_words dim` line, then a line of each word followed by
+    /// its space-separated vector, at full `f32` precision.
+    ///
+    /// Vectors are computed in parallel a block of words at a time, and
+    /// streamed to the file in vocabulary order.
+    pub fn export_vectors(&self, path: &str) -> Result<()> {
+        const BLOCK: usize = 4096;
+        let (words, _) = self.model.get_vocab()?;
+        let dim = self.model.get_dimension();
+        let io_error = |e: std::io::Error| Error::from(format!("failed to write {path}, {e}"));
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        writeln!(writer, "{} {dim}", words.len()).map_err(io_error)?;
+        let pool = thread_pool()?;
+        for block in words.chunks(BLOCK) {
+            let lines = pool.install(|| {
+                block
+                    .par_iter()
+                    .map(|word| {
+                        let vector = self.word_vector(word)?;
+                        let mut line = word.clone();
+                        for v in vector {
+                            line.push(' ');
+                            line.push_str(&v.to_string());
+                        }
+                        line.push('\n');
+                        Ok(line)
+                    })
+                    .collect::<Result<Vec<String>>>()
+            })?;
+            for line in lines {
+                writer.write_all(line.as_bytes()).map_err(io_error)?;
+            }
+        }
+        writer.flush().map_err(io_error)
+    }
+
+    /// predict `n` texts covering the whole vocabulary, to load the pages of
+    /// a freshly loaded model before the first real prediction.
+    ///
+    /// Each word goes to one text, so the texts have about `vocab / n` words
+    /// each. This changes nothing but the latency of later predictions.
+    pub fn warmup(&self, n: usize) -> Result<()> {
+        if n == 0 {
+            return Ok(());
+        }
+        let (words, _) = self.model.get_vocab()?;
+        let options = PredictOptions::new(1, Thresholds::global(-1.0));
+        self.predict_rows::<f32, _, _, _>(n, 0, &options, |mut text_sender| {
+            for i in 0..n {
+                let text: Vec<&str> = words
+                    .iter()
+                    .skip(i)
+                    .step_by(n)
+                    .map(String::as_str)
+                    .collect();
+                if text_sender.send(Some(text.join(" "))).is_err() {
+                    break;
+                }
+            }
+            Ok::<_, Error>(())
+        })?;
+        Ok(())
+    }
+
+    /// predict each line of a text file using multithreading.
+    ///
+    /// The file is memory mapped, then predicted by `predict_lines`. Files
+    /// starting with the gzip magic bytes are decompressed as they are read
+    /// by `predict_reader`.
+    pub fn predict_file(&self, path: &str, options: &PredictOptions) -> Result<Predictions> {
+        match open_gzip(path)? {
+            Some(reader) => self.predict_reader(reader, options),
+            None => with_mapped_file(path, |content| self.predict_lines(content, options)),
+        }
+    }
+
+    /// predict each line read from `reader` using multithreading.
+    ///
+    /// Lines are split as in `predict_lines`, and read by the sender while
+    /// the workers predict the previous ones.
+    pub fn predict_reader(
+        &self,
+        reader: impl BufRead + Send,
+        options: &PredictOptions,
+    ) -> Result<Predictions> {
+        self.check_supervised()?;
+        let mut rows = Vec::new();
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            |line_sender| send_lines(reader, line_sender),
+            |i, line: Vec<u8>| {
+                let line = line.strip_suffix(b"\n").unwrap_or(&line);
+                self.predict_line(i, line.strip_suffix(b"\r").unwrap_or(line), options)
+            },
+            |i, row| {
+                if i >= rows.len() {
+                    rows.resize_with(i + 1, Row::default);
+                }
+                rows[i] = row;
+                Ok(())
+            },
+        )?;
+        let mut predictions = Predictions::new(rows.len(), options);
+        for (i, row) in rows.into_iter().enumerate() {
+            predictions.write(i, row, 0, options)?;
+        }
+        Ok(predictions.finish())
+    }
+
+    /// predict each line of `content` using multithreading.
+    ///
+    /// Lines are sent to the workers as slices of `content` rather than
+    /// allocated one by one. They end with `\n` or `\r\n`, and lines that
+    /// are not valid UTF-8 are logged and give empty rows.
+    pub fn predict_lines(&self, content: &[u8], options: &PredictOptions) -> Result<Predictions> {
+        self.check_supervised()?;
+        let lines = || {
+            let content = content.strip_suffix(b"\n").unwrap_or(content);
+            content
+                .split(|&b| b == b'\n')
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        };
+        let rows = if content.is_empty() {
+            0
+        } else {
+            lines().count()
+        };
+        let mut predictions = Predictions::new(rows, options);
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            |mut line_sender: ChunkSender<&[u8]>| {
+                for line in lines().take(rows) {
+                    if line_sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Ok::<_, Error>(())
+            },
+            |i, line| self.predict_line(i, line, options),
+            |i, row| predictions.write(i, row, 0, options),
+        )?;
+        Ok(predictions.finish())
+    }
+
+    /// predict the `i`-th line of a file, giving an empty row if it is not
+    /// valid UTF-8.
+    fn predict_line(&self, i: usize, line: &[u8], options: &PredictOptions) -> Row {
+        match std::str::from_utf8(line) {
+            Ok(text) => self.predict_row(i, Some(text), options),
+            Err(e) => {
+                error!("Line {i} is not valid UTF-8, ignoring: {e}");
+                Row::default()
+            }
+        }
+    }
+
+    /// evaluate the model on a labeled text file, as `fasttext test`.
+    ///
+    /// The file is memory mapped, then evaluated by `test_lines`. Files
+    /// starting with the gzip magic bytes are decompressed as they are read
+    /// by `test_reader`.
+    pub fn test_file(&self, path: &str, options: &PredictOptions) -> Result<Meter> {
+        match open_gzip(path)? {
+            Some(reader) => self.test_reader(reader, options),
+            None => with_mapped_file(path, |content| self.test_lines(content, options)),
+        }
+    }
+
+    /// evaluate the model on lines of words and labels, as `fasttext test`.
+    ///
+    /// Labels are the words starting with the label prefix of the model.
+    /// Labels unknown to the model are dropped, and lines left without
+    /// labels are not counted, like fasttext does, nor are lines without
+    /// words. Fails if no line is counted, as precision and recall are then
+    /// undefined.
+    pub fn test_lines(&self, content: &[u8], options: &PredictOptions) -> Result<Meter> {
+        self.test_with(options, |mut line_sender: ChunkSender<&[u8]>| {
+            for line in content.split(|&b| b == b'\n') {
+                if line_sender.send(line).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// evaluate the model on lines read from `reader`, as `test_lines`.
+    pub fn test_reader(
+        &self,
+        reader: impl BufRead + Send,
+        options: &PredictOptions,
+    ) -> Result<Meter> {
+        self.test_with(options, |line_sender| send_lines(reader, line_sender))
+    }
+
+    /// evaluate the model on lines fed by `send`.
+    fn test_with<T, S>(&self, options: &PredictOptions, send: S) -> Result<Meter>
+    where
+        T: AsRef<[u8]> + Send,
+        S: FnOnce(ChunkSender<T>) -> Result<()> + Send,
+    {
+        self.check_supervised()?;
+        let prefix = self.model.get_args().label().into_owned();
+        let mut meter = Meter::default();
+        run_pipeline_until(
+            options.timeout.map(|timeout| Instant::now() + timeout),
+            send,
+            |i, line| {
+                let line = match std::str::from_utf8(line.as_ref()) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        error!("Line {i} is not valid UTF-8, ignoring: {e}");
+                        return None;
+                    }
+                };
+                let mut gold = Vec::new();
+                let mut words = Vec::new();
+                for word in line.split(is_delimiter).filter(|w| !w.is_empty()) {
+                    if word.starts_with(&prefix) {
+                        gold.extend(self.label_dict.get(word));
+                    } else {
+                        words.push(word);
+                    }
+                }
+                if gold.is_empty() || words.is_empty() {
+                    return None;
+                }
+                let row = self.predict_row(i, Some(words.join(" ")), options);
+                Some((gold, row))
+            },
+            |i, example| {
+                options.check_cancelled()?;
+                if let Some((gold, row)) = example {
+                    row.check(i, options)?;
+                    meter.add(&gold, &row.labels);
+                }
+                Ok(())
+            },
+        )?;
+        if meter.examples == 0 {
+            return Err(Error::from("no line has a label known to the model"));
+        }
+        Ok(meter)
+    }
+
+    /// predict the `i`-th text.
+    #[inline]
+    pub fn predict_row(
+        &self,
+        i: usize,
+        text: Option<impl AsRef<str>>,
+        options: &PredictOptions,
+    ) -> Row {
+        let thresholds = &options.thresholds;
+        let k = options.candidates(self.num_labels());
+        let text = text
+            .as_ref()
+            .map(|s| options.transform.apply(s.as_ref().into()));
+        if options.on_empty != EmptyPolicy::Zero {
+            match &text {
+                None => return Row::empty(),
+                Some(Ok(s)) if s.trim().is_empty() => return Row::empty(),
+                _ => {}
+            }
+        }
+        match text {
+            Some(Ok(s)) => {
+                trace!("text received: {:?}", s);
+                match self.predict_text(&s, k, options.floor()) {
+                    Ok(predictions) => {
+                        let entropy = options.entropy.then(|| entropy(&predictions));
+                        let mut unknown_label = None;
+                        let (labels, mut probs): (Vec<i32>, Vec<f32>) = predictions
+                            .into_iter()
+                            .map(|p| match self.label_dict.get(&p.label) {
+                                Some(&label) => (label, p.prob),
+                                None => {
+                                    if options.strict_labels {
+                                        unknown_label.get_or_insert(p.label);
+                                    }
+                                    (-1, p.prob)
+                                }
+                            })
+                            .filter(|&(label, prob)| thresholds.keep(label, prob))
+                            .filter(|(label, _)| options.allows(*label))
+                            .take(options.k as usize)
+                            .unzip();
+                        options.rescale(&mut probs);
+                        Row {
+                            labels,
+                            probs,
+                            entropy,
+                            unknown_label,
+                            ..Default::default()
+                        }
+                    }
+                    Err(e) => {
+                        if options.on_error != ErrorPolicy::Raise {
+                            error!("Error making prediction, ignoring: {e}");
+                        }
+                        Row {
+                            error: Some(e),
+                            ..Default::default()
+                        }
+                    }
+                }
+            }
+            Some(Err(s)) => {
+                trace!("Text {i} rejected for containing a newline: {:?}", s);
+                Row {
+                    newline: true,
+                    ..Default::default()
+                }
+            }
+            None => Row::default(),
+        }
+    }
+}
+
+/// whether fasttext splits words at `c`.
+#[inline]
+fn is_delimiter(c: char) -> bool {
+    matches!(c, ' ' | '\n' | '\t' | '\x0b' | '\x0c' | '\r' | '\0')
+}
+
+/// the Shannon entropy in nats of predicted probabilities.
+#[inline]
+fn entropy(predictions: &[fasttext::Prediction]) -> f32 {
+    -predictions
+        .iter()
+        .filter(|p| p.prob > 0.0)
+        .map(|p| p.prob * p.prob.ln())
+        .sum::<f32>()
+}
+
+/// open the file at `path` for decompression if it starts with the gzip
+/// magic bytes.
+fn open_gzip(path: &str) -> Result<Option<impl BufRead + Send>> {
+    let read_error = |e| Error::from(format!("failed to read {path}, {e}"));
+    let mut file = File::open(path).map_err(read_error)?;
+    let mut magic = [0u8; 2];
+    let read = file.read(&mut magic).map_err(read_error)?;
+    if read < 2 || magic != [0x1f, 0x8b] {
+        return Ok(None);
+    }
+    file.rewind().map_err(read_error)?;
+    Ok(Some(BufReader::new(MultiGzDecoder::new(BufReader::new(
+        file,
+    )))))
+}
+
+/// send the lines read from `reader` with their line endings, failing on a
+/// read error such as corrupt gzip data.
+fn send_lines(mut reader: impl BufRead, mut line_sender: ChunkSender<Vec<u8>>) -> Result<()> {
+    loop {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                if line_sender.send(line).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => return Err(Error::from(format!("failed to read lines, {e}"))),
+        }
+    }
+}
+
+/// memory map the file at `path` and pass its content to `f`.
+///
+/// Empty files are passed as an empty slice, as they cannot be mapped.
+fn with_mapped_file<T>(path: &str, f: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    let read_error = |e| Error::from(format!("failed to read {path}, {e}"));
+    let file = File::open(path).map_err(read_error)?;
+    if file.metadata().map_err(read_error)?.len() == 0 {
+        return f(&[]);
+    }
+    // Safety: the file must not be truncated while it is predicted, which
+    // would raise SIGBUS on reading the lost pages.
+    let content = unsafe { Mmap::map(&file) }.map_err(read_error)?;
+    f(&content)
+}
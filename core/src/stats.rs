@@ -0,0 +1,69 @@
+//! time spent in each stage of a batch, collected only if requested.
+
+use crate::pool::thread_pool;
+use crate::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// the busy time of the sender, the workers and the writer of a batch,
+/// added to by their threads.
+///
+/// Waits on the channels between the stages are not counted, so a stage
+/// much busier than the others is the bottleneck.
+#[derive(Debug, Default)]
+pub struct BatchStats {
+    /// nanoseconds spent reading and sending texts.
+    sender: AtomicU64,
+    /// nanoseconds spent predicting, by thread index in the pool.
+    workers: Vec<AtomicU64>,
+    /// nanoseconds spent writing rows.
+    writer: AtomicU64,
+}
+
+impl BatchStats {
+    /// stats with a slot for each thread of the current pool.
+    pub fn new() -> Result<Self> {
+        let threads = thread_pool()?.current_num_threads();
+        Ok(BatchStats {
+            workers: (0..threads).map(|_| AtomicU64::new(0)).collect(),
+            ..Default::default()
+        })
+    }
+
+    pub fn add_sender(&self, elapsed: Duration) {
+        self.sender.fetch_add(nanos(elapsed), Ordering::Relaxed);
+    }
+
+    /// count `elapsed` for the pool thread running the call, if any.
+    pub fn add_worker(&self, elapsed: Duration) {
+        // a pool replaced by `set_num_threads` may have more threads.
+        if let Some(worker) = rayon::current_thread_index().and_then(|i| self.workers.get(i)) {
+            worker.fetch_add(nanos(elapsed), Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_writer(&self, elapsed: Duration) {
+        self.writer.fetch_add(nanos(elapsed), Ordering::Relaxed);
+    }
+
+    pub fn sender_secs(&self) -> f64 {
+        secs(&self.sender)
+    }
+
+    /// the seconds each pool thread spent predicting.
+    pub fn worker_secs(&self) -> Vec<f64> {
+        self.workers.iter().map(secs).collect()
+    }
+
+    pub fn writer_secs(&self) -> f64 {
+        secs(&self.writer)
+    }
+}
+
+fn nanos(elapsed: Duration) -> u64 {
+    elapsed.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+fn secs(nanos: &AtomicU64) -> f64 {
+    nanos.load(Ordering::Relaxed) as f64 * 1e-9
+}
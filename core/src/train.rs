@@ -0,0 +1,322 @@
+//! train supervised models: continue training on new data, or search
+//! hyperparameters with autotune.
+//!
+//! The `fasttext` crate can neither set pretrained vectors nor autotune, so
+//! the arguments are parsed and the model trained through `cfasttext-sys`
+//! directly.
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::pool::thread_pool;
+use crate::predictor::ParallelPredictor;
+use cfasttext_sys::{
+    cft_args_free, cft_args_new, cft_args_parse, cft_fasttext_free, cft_fasttext_new,
+    cft_fasttext_save_model, cft_fasttext_train, error_message, fasttext_args_t, fasttext_t,
+};
+use fasttext::LossName;
+use log::debug;
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Instant;
+
+/// the label prefix of the training files of autotune, fasttext's default.
+const LABEL_PREFIX: &str = "__label__";
+
+/// the hyperparameters of a training run, as fasttext command line arguments.
+pub struct TrainArgs {
+    /// the training file in the fasttext format, one labeled text per line.
+    pub input: String,
+    pub epoch: i32,
+    pub lr: f64,
+}
+
+/// an opaque cfasttext autotune.
+#[repr(C)]
+struct AutotuneHandle {
+    _private: [u8; 0],
+}
+
+// defined by cfasttext but missing from the bindings of `cfasttext-sys`.
+extern "C" {
+    fn cft_autotune_new(handle: *mut fasttext_t) -> *mut AutotuneHandle;
+    fn cft_autotune_free(handle: *mut AutotuneHandle);
+    fn cft_autotune_train(handle: *mut AutotuneHandle, args: *mut fasttext_args_t);
+}
+
+/// the options of an autotune run.
+pub struct AutotuneArgs {
+    /// the training file in the fasttext format, one labeled text per line.
+    pub input: String,
+    /// the validation file scored by `metric`, in the same format.
+    pub validation: String,
+    /// the time budget of the search, in seconds, at least twice a training
+    /// run with the default hyperparameters.
+    pub duration_secs: i32,
+    /// the objective: `f1`, `f1:LABEL`, `precisionAtRecall:R[:LABEL]` or
+    /// `recallAtPrecision:P[:LABEL]`.
+    pub metric: String,
+}
+
+impl ParallelPredictor {
+    /// train a supervised model on `args.input` with the hyperparameters
+    /// that score best on `args.validation` within `args.duration_secs`,
+    /// like `fasttext supervised -autotune-validation`.
+    ///
+    /// fasttext aborts the process if no trial finishes in time or if the
+    /// label of the metric is not trained, so both are checked first: the
+    /// label must be in `args.input`, and a training run with the default
+    /// hyperparameters, the first trial, is timed and must take at most
+    /// half of the duration, leaving time to score it.
+    pub fn autotune(args: &AutotuneArgs) -> Result<ParallelPredictor> {
+        for (name, path) in [("training", &args.input), ("validation", &args.validation)] {
+            check_file(name, path)?;
+        }
+        if let Some(label) = check_metric(&args.metric)? {
+            if !training_labels(&args.input)?.contains(label) {
+                return Err(Error::from(format!(
+                    "autotune metric label {label} is not in {}",
+                    args.input
+                )));
+            }
+        }
+        if args.duration_secs <= 0 {
+            return Err(Error::from(format!(
+                "autotune duration must be positive, got {}",
+                args.duration_secs
+            )));
+        }
+        let files = TempFiles::new(format!("autotune-{:p}", args));
+        let threads = thread_pool()?.current_num_threads();
+        let options = [
+            ("-input", args.input.clone()),
+            ("-output", files.model.to_string_lossy().into_owned()),
+            ("-thread", threads.to_string()),
+            ("-verbose", "0".to_string()),
+        ];
+        let start = Instant::now();
+        train(&command_line(options.clone()), &files.model, false)
+            .map_err(|e| Error::from(format!("autotune failed, {e}")))?;
+        let trial = start.elapsed().as_secs_f64();
+        debug!("training with the default hyperparameters took {trial:.1}s");
+        if 2.0 * trial > f64::from(args.duration_secs) {
+            return Err(Error::from(format!(
+                "autotune duration of {}s is too short, a training run with the default \
+                 hyperparameters took {trial:.1}s, allow at least twice as long",
+                args.duration_secs
+            )));
+        }
+        let [input, output, thread, verbose] = options;
+        let argv = command_line([
+            input,
+            output,
+            ("-autotune-validation", args.validation.clone()),
+            ("-autotune-duration", args.duration_secs.to_string()),
+            ("-autotune-metric", args.metric.clone()),
+            thread,
+            verbose,
+        ]);
+        debug!("autotune with {argv:?}");
+        train(&argv, &files.model, true)
+            .map_err(|e| Error::from(format!("autotune failed, {e}")))?;
+        ParallelPredictor::load(&files.model.to_string_lossy())
+    }
+
+    /// train a new model on `args.input`, starting from the word vectors of
+    /// this one, which is left unchanged.
+    ///
+    /// fasttext cannot update a trained model, so this trains a model with
+    /// the same hyperparameters whose word embeddings are initialized with
+    /// the word vectors of this one, like `-pretrainedVectors`. The words of
+    /// both vocabularies are kept, but subword and word n-gram rows and the
+    /// output layer are trained from scratch, and the labels are those seen
+    /// in `args.input` only.
+    pub fn train_continue(&self, args: &TrainArgs) -> Result<ParallelPredictor> {
+        self.check_supervised()?;
+        check_file("training", &args.input)?;
+        let files = TempFiles::new(format!("train-{:p}", self));
+        self.export_vectors(&files.vectors.to_string_lossy())?;
+        let argv = self.train_argv(args, &files)?;
+        debug!("continue training with {argv:?}");
+        train(&argv, &files.model, false)
+            .map_err(|e| Error::new(ErrorKind::Other, format!("training failed, {e}")))?;
+        ParallelPredictor::load(&files.model.to_string_lossy())
+    }
+
+    /// the arguments of a training run, which must be valid as fasttext
+    /// exits the process on invalid arguments.
+    fn train_argv(&self, args: &TrainArgs, files: &TempFiles) -> Result<Vec<String>> {
+        let saved = self.model().get_args();
+        let loss = match saved.loss() {
+            LossName::HS => "hs",
+            LossName::NS => "ns",
+            LossName::SOFTMAX => "softmax",
+            LossName::OVA => "one-vs-all",
+        };
+        let threads = thread_pool()?.current_num_threads();
+        let options = [
+            ("-input", args.input.clone()),
+            ("-output", files.model.to_string_lossy().into_owned()),
+            (
+                "-pretrainedVectors",
+                files.vectors.to_string_lossy().into_owned(),
+            ),
+            ("-epoch", args.epoch.to_string()),
+            ("-lr", args.lr.to_string()),
+            ("-dim", self.model().get_dimension().to_string()),
+            ("-ws", saved.ws().to_string()),
+            ("-loss", loss.to_string()),
+            ("-minCount", saved.min_count().to_string()),
+            ("-minCountLabel", saved.min_count_label().to_string()),
+            ("-neg", saved.neg().to_string()),
+            ("-wordNgrams", saved.word_ngrams().to_string()),
+            ("-bucket", saved.bucket().to_string()),
+            ("-minn", saved.minn().to_string()),
+            ("-maxn", saved.maxn().to_string()),
+            ("-lrUpdateRate", saved.lr_update_rate().to_string()),
+            ("-label", saved.label().into_owned()),
+            ("-thread", threads.to_string()),
+            ("-verbose", "0".to_string()),
+        ];
+        Ok(command_line(options))
+    }
+}
+
+fn check_file(name: &str, path: &str) -> Result<()> {
+    if Path::new(path).is_file() {
+        Ok(())
+    } else {
+        Err(Error::from(format!("{name} file {path} does not exist")))
+    }
+}
+
+/// fail unless fasttext can parse `metric`, as it aborts the process on
+/// unknown metrics, returning its label, if any.
+fn check_metric(metric: &str) -> Result<Option<&str>> {
+    let parsed = match metric.split_once(':') {
+        None => (metric == "f1").then_some(None),
+        Some(("f1", label)) => (!label.is_empty()).then_some(Some(label)),
+        Some(("precisionAtRecall" | "recallAtPrecision", rest)) => match rest.split_once(':') {
+            Some((value, label)) => {
+                (value.parse::<f32>().is_ok() && !label.is_empty()).then_some(Some(label))
+            }
+            None => rest.parse::<f32>().is_ok().then_some(None),
+        },
+        Some(_) => None,
+    };
+    if let Some(label) = parsed {
+        Ok(label)
+    } else {
+        Err(Error::from(format!(
+            "invalid autotune metric {metric:?}, expected f1, f1:LABEL, \
+             precisionAtRecall:R[:LABEL] or recallAtPrecision:P[:LABEL]"
+        )))
+    }
+}
+
+/// the labels of a training file, the tokens with the label prefix.
+fn training_labels(path: &str) -> Result<BTreeSet<String>> {
+    let io_error = |e: std::io::Error| Error::from(format!("failed to read {path}, {e}"));
+    let mut labels = BTreeSet::new();
+    for line in BufReader::new(File::open(path).map_err(io_error)?).split(b'\n') {
+        let line = line.map_err(io_error)?;
+        for token in String::from_utf8_lossy(&line).split_whitespace() {
+            if token.starts_with(LABEL_PREFIX) {
+                labels.insert(token.to_string());
+            }
+        }
+    }
+    Ok(labels)
+}
+
+/// a `fasttext supervised` command line of `options`.
+fn command_line<const N: usize>(options: [(&str, String); N]) -> Vec<String> {
+    let mut argv = vec!["fasttext".to_string(), "supervised".to_string()];
+    for (flag, value) in options {
+        argv.push(flag.to_string());
+        argv.push(value);
+    }
+    argv
+}
+
+/// parse `argv`, train, with autotune if `autotune`, and save the model to
+/// `output`.
+fn train(argv: &[String], output: &Path, autotune: bool) -> std::result::Result<(), String> {
+    let argv = argv
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).map_err(|e| e.to_string()))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let output = CString::new(output.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+    let mut c_argv: Vec<*mut c_char> = argv.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    let mut handles = Handles {
+        args: unsafe { cft_args_new() },
+        model: unsafe { cft_fasttext_new() },
+        autotune: ptr::null_mut(),
+    };
+    unsafe {
+        cft_args_parse(handles.args, c_argv.len() as i32, c_argv.as_mut_ptr());
+        let mut err = ptr::null_mut();
+        if autotune {
+            // the autotune takes ownership of the model.
+            handles.autotune = cft_autotune_new(handles.model);
+            cft_autotune_train(handles.autotune, handles.args);
+        } else {
+            cft_fasttext_train(handles.model, handles.args, &mut err);
+        }
+        if err.is_null() {
+            cft_fasttext_save_model(handles.model, output.as_ptr(), &mut err);
+        }
+        if !err.is_null() {
+            return Err(error_message(err));
+        }
+    }
+    Ok(())
+}
+
+/// the fasttext objects of a training run, freed on drop.
+struct Handles {
+    args: *mut fasttext_args_t,
+    model: *mut fasttext_t,
+    /// the autotune owning `model`, if any.
+    autotune: *mut AutotuneHandle,
+}
+
+impl Drop for Handles {
+    fn drop(&mut self) {
+        unsafe {
+            cft_args_free(self.args);
+            if self.autotune.is_null() {
+                cft_fasttext_free(self.model);
+            } else {
+                cft_autotune_free(self.autotune);
+            }
+        }
+    }
+}
+
+/// the exported vectors and the trained model, removed on drop.
+struct TempFiles {
+    vectors: PathBuf,
+    model: PathBuf,
+}
+
+impl TempFiles {
+    fn new(name: String) -> Self {
+        let name = format!("fasttext-parallel-{name}-{}", std::process::id());
+        let dir = std::env::temp_dir();
+        TempFiles {
+            vectors: dir.join(format!("{name}.vec")),
+            model: dir.join(format!("{name}.bin")),
+        }
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.vectors);
+        let _ = std::fs::remove_file(&self.model);
+    }
+}
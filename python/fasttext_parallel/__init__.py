@@ -2,4 +2,6 @@ from .fasttext_parallel import *
 
 __doc__ = fasttext_parallel.__doc__
 
-__all__ = ["load_model"]
+__all__ = ["load_model", "load_label_map", "train_autotune", "configure", "init_threads", "set_num_threads",
+           "set_chunk_size", "set_log_level", "batch_ensemble", "compare", "Ensemble", "FastTextError",
+           "ModelLoadError", "PredictionError", "ModelTypeError"]
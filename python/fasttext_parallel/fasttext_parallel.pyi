@@ -1,11 +1,250 @@
-__all__ = ["load_model", "FastText"]
+__all__ = ["load_model", "load_label_map", "train_autotune", "configure", "init_threads", "set_num_threads",
+           "set_chunk_size", "set_log_level", "batch_ensemble", "compare", "FastText", "Ensemble", "FastTextError",
+           "ModelLoadError", "PredictionError", "ModelTypeError"]
 
-from typing import Tuple, List, Dict, Union
+import asyncio
+from typing import Callable, Hashable, Iterator, Literal, Optional, Tuple, List, Dict, Union, overload
 import numpy as np
+import polars as pl
+import pyarrow as pa
 
-def load_model(path: str) -> FastText: ...
+Texts = Union[List[Union[str, bytes]], np.ndarray, pa.Array, pa.ChunkedArray, pl.Series]
+
+class FastTextError(Exception): ...
+class ModelLoadError(FastTextError): ...
+class PredictionError(FastTextError): ...
+class ModelTypeError(FastTextError): ...
+
+def load_model(
+    path: str,
+    label_dtype: Literal["i16", "i32"] = "i16",
+    label_to_int: Optional[Dict[str, int]] = None,
+    mmap: bool = False,
+) -> FastText: ...
+def load_label_map(path: str) -> Dict[str, int]: ...
+def train_autotune(
+    input_path: str,
+    validation_path: str,
+    duration_secs: int = 300,
+    metric: str = "f1",
+    label_dtype: Literal["i16", "i32"] = "i16",
+) -> FastText: ...
+def configure(num_threads: Optional[int] = None, min_threads: Optional[int] = None) -> None: ...
+def init_threads(num_threads: int) -> None: ...
+def set_num_threads(num_threads: int) -> None: ...
+def set_chunk_size(chunk_size: int = 64) -> None: ...
+def set_log_level(level: Literal["trace", "debug", "info", "warning", "error", "off"]) -> None: ...
+def batch_ensemble(
+    models: List[FastText], texts: Texts, k: int = 1, weights: Optional[List[float]] = None
+) -> Tuple[np.ndarray, np.ndarray]: ...
+def compare(
+    model_a: FastText, model_b: FastText, texts: Texts, k: int = 1, threshold: float = -1.0
+) -> Dict[str, Union[float, np.ndarray]]: ...
 
 class FastText:
-    def batch(self, texts: List[str], k: int = 1, threshold: float = -1.0) -> Tuple[np.ndarray, np.ndarray]: ...
-    def get_labels(self) -> Dict[int, str]: ...
+    def batch(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+        allowed_labels: Optional[List[int]] = None,
+        newline: str = "strip",
+        preprocess: Optional[Callable[[str], str]] = None,
+        lowercase: bool = False,
+        normalize: Optional[str] = None,
+        normalize_unicode: Optional[str] = None,
+        max_tokens: Optional[int] = None,
+        on_empty: str = "zero",
+        on_error: str = "ignore",
+        on_invalid: str = "empty",
+        encoding: str = "utf-8",
+        errors: str = "strict",
+        return_counts: bool = False,
+        return_mask: bool = False,
+        return_truncated: bool = False,
+        return_entropy: bool = False,
+        renormalize: bool = False,
+        log_probs: bool = False,
+        timeout_secs: Optional[float] = None,
+        output: str = "arrays",
+        collect_errors: bool = False,
+        dtype: Literal["float32", "float64"] = "float32",
+        strict_labels: bool = False,
+        return_stats: bool = False,
+    ) -> Union[Tuple[np.ndarray, ...], List[Dict[str, float]], Tuple[object, ...]]: ...
+    def batch_tokens(
+        self,
+        token_lists: List[List[str]],
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> Tuple[np.ndarray, np.ndarray]: ...
+    def predict_top1(
+        self,
+        texts: Texts,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> Tuple[np.ndarray, np.ndarray]: ...
+    def predict_dict(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> List[Dict[str, float]]: ...
+    def batch_keyed(
+        self,
+        items: List[Tuple[Hashable, Union[str, bytes, None]]],
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> Dict[Hashable, Tuple[np.ndarray, np.ndarray]]: ...
+    def predict_lines(
+        self,
+        text: str,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> List[Dict[str, float]]: ...
+    def detect_language(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> List[List[Tuple[str, float]]]: ...
+    def batch_proba(self, texts: Texts, dtype: Literal["float32", "float16"] = "float32") -> np.ndarray: ...
+    def batch_sparse(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> Tuple[np.ndarray, np.ndarray, np.ndarray]: ...
+    def batch_into(
+        self,
+        texts: Texts,
+        out_labels: np.ndarray,
+        out_probs: np.ndarray,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> np.ndarray: ...
+    def batch_file(
+        self,
+        path: str,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> Tuple[np.ndarray, np.ndarray]: ...
+    def test(
+        self,
+        path: str,
+        k: int = 1,
+        threshold: float = 0.0,
+    ) -> Tuple[int, float, float]: ...
+    def evaluate(
+        self,
+        texts: Texts,
+        gold_labels: List[List[Union[str, int]]],
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> Dict[str, float]: ...
+    def confusion_matrix(
+        self,
+        texts: Texts,
+        gold_labels: List[Optional[Union[str, int]]],
+    ) -> Tuple[np.ndarray, List[Optional[str]]]: ...
+    def batch_chunked(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+        chunk_size: int = 65536,
+        callback: Optional[Callable[[int, np.ndarray, np.ndarray], None]] = None,
+    ) -> None: ...
+    def batch_iter(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+        window: int = 65536,
+        ordered: bool = True,
+    ) -> Iterator[Tuple[int, np.ndarray, np.ndarray]]: ...
+    def batch_stream(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+        window: int = 65536,
+        ordered: bool = True,
+    ) -> Iterator[Tuple[int, np.ndarray, np.ndarray]]: ...
+    def batch_async(
+        self,
+        texts: Texts,
+        k: int = 1,
+        threshold: Union[float, Dict[Union[str, int], float]] = -1.0,
+        default_threshold: float = -1.0,
+    ) -> "asyncio.Future[Tuple[np.ndarray, np.ndarray]]": ...
+    def tokenize(self, texts: Texts) -> List[List[str]]: ...
+    def tokenize_one(self, text: str) -> List[str]: ...
+    def oov_stats(self, texts: Texts, top_n: int = 10) -> Tuple[np.ndarray, np.ndarray, Dict[str, object]]: ...
+    def get_subword_id(self, subword: str) -> int: ...
+    def get_word_vector(self, word: str, lowercase: bool = False) -> np.ndarray: ...
+    def get_output_vector(self, label_id: int) -> np.ndarray: ...
+    def get_input_matrix(self) -> np.ndarray: ...
+    def get_output_matrix(self) -> np.ndarray: ...
+    def get_sentence_vector(self, text: str, lowercase: bool = False) -> np.ndarray: ...
+    def get_weighted_vector(self, tokens: List[str], weights: List[float]) -> np.ndarray: ...
+    def text_similarity(self, a: str, b: str, lowercase: bool = False) -> float: ...
+    def batch_similarity(self, pairs: List[Tuple[str, str]], lowercase: bool = False) -> np.ndarray: ...
+    @overload
+    def similarity(self, a: str, b: str, mode: str = "sentence", lowercase: bool = False) -> float: ...
+    @overload
+    def similarity(
+        self, a: List[str], b: List[str], mode: str = "sentence", lowercase: bool = False
+    ) -> np.ndarray: ...
+    def nearest_neighbors_by_vector(self, vector: np.ndarray, k: int = 10) -> List[Tuple[str, float]]: ...
+    def batch_nearest_neighbors(self, words: Texts, k: int = 10) -> List[List[Tuple[str, float]]]: ...
+    @property
+    def model_type(self) -> Literal["supervised", "cbow", "skipgram"]: ...
+    @property
+    def label_dtype(self) -> Literal["i16", "i32"]: ...
+    def get_dimension(self) -> int: ...
+    @overload
+    def get_labels(self, include_freq: Literal[False] = ...) -> Dict[int, str]: ...
+    @overload
+    def get_labels(self, include_freq: Literal[True]) -> Tuple[List[str], np.ndarray]: ...
+    def get_labels_list(self) -> List[str]: ...
+    def label_counts(self) -> Dict[int, int]: ...
+    def get_label_counts(self) -> Dict[str, int]: ...
+    def dump_label_map(self, path: str) -> None: ...
+    def get_word_counts(self) -> Dict[str, int]: ...
     def get_label_by_id(self, id: int) -> Union[None, str]: ...
+    def ids_to_labels(self, ids: np.ndarray, placeholder: str = "") -> List[List[str]]: ...
+    def export_vectors(self, path: str) -> None: ...
+    def warmup(self, n: int = 1000) -> None: ...
+    def reload(self, path: str) -> None: ...
+    def reduce_model(self, target_dim: int) -> FastText: ...
+    def train_continue(self, input_path: str, epoch: int = 5, lr: float = 0.1) -> None: ...
+    def __len__(self) -> int: ...
+
+class Ensemble:
+    def __init__(
+        self,
+        models: List[FastText],
+        labels: str = "shared",
+        pooling: str = "mean",
+        weights: Optional[List[float]] = None,
+    ) -> None: ...
+    def batch(self, texts: Texts, k: int = 1, threshold: float = -1.0) -> Tuple[np.ndarray, np.ndarray]: ...
+    def predict_ensemble(
+        self, texts: Texts, k: int = 1, threshold: float = -1.0, agg: Optional[str] = None
+    ) -> Tuple[np.ndarray, np.ndarray]: ...
+    def get_labels(self) -> Dict[int, str]: ...
+    def __len__(self) -> int: ...
@@ -0,0 +1,221 @@
+//! read pyarrow string arrays through the arrow C data interface,
+//! see <https://arrow.apache.org/docs/format/CDataInterface.html>.
+
+use log::error;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::ffi::{c_char, c_void, CStr};
+use std::ops::Range;
+use std::ptr;
+
+#[repr(C)]
+struct ArrowSchema {
+    format: *const c_char,
+    name: *const c_char,
+    metadata: *const c_char,
+    flags: i64,
+    n_children: i64,
+    children: *mut *mut ArrowSchema,
+    dictionary: *mut ArrowSchema,
+    release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    private_data: *mut c_void,
+}
+
+#[repr(C)]
+struct ArrowArray {
+    length: i64,
+    null_count: i64,
+    offset: i64,
+    n_buffers: i64,
+    n_children: i64,
+    buffers: *mut *const c_void,
+    children: *mut *mut ArrowArray,
+    dictionary: *mut ArrowArray,
+    release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    private_data: *mut c_void,
+}
+
+impl ArrowSchema {
+    fn empty() -> Self {
+        ArrowSchema {
+            format: ptr::null(),
+            name: ptr::null(),
+            metadata: ptr::null(),
+            flags: 0,
+            n_children: 0,
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+}
+
+impl ArrowArray {
+    fn empty() -> Self {
+        ArrowArray {
+            length: 0,
+            null_count: 0,
+            offset: 0,
+            n_buffers: 0,
+            n_children: 0,
+            buffers: ptr::null_mut(),
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+}
+
+impl Drop for ArrowSchema {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+impl Drop for ArrowArray {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+/// offsets width of a `string` (`u`) or `large_string` (`U`) array.
+#[derive(Clone, Copy)]
+enum Offsets {
+    I32,
+    I64,
+}
+
+/// one exported chunk of a string array.
+struct Chunk {
+    // boxed so that the addresses handed to `_export_to_c` stay stable.
+    array: Box<ArrowArray>,
+    _schema: Box<ArrowSchema>,
+    offsets: Offsets,
+}
+
+/// borrowed view of a pyarrow `StringArray` / `LargeStringArray`,
+/// a `ChunkedArray` of them, or a polars `Series` exported to arrow.
+pub(crate) struct ArrowStrings {
+    chunks: Vec<Chunk>,
+}
+
+// the exported buffers are immutable and only released on drop.
+unsafe impl Send for Chunk {}
+unsafe impl Sync for Chunk {}
+
+impl ArrowStrings {
+    /// export `obj` if it is a pyarrow array, chunked array or polars series,
+    /// returns `None` for any other object.
+    pub(crate) fn from_py(obj: &PyAny) -> PyResult<Option<Self>> {
+        let module: String = obj.get_type().getattr("__module__")?.extract()?;
+        if module.starts_with("polars") && obj.hasattr("to_arrow")? {
+            return Self::from_py(obj.call_method0("to_arrow")?);
+        }
+        if !module.starts_with("pyarrow") {
+            return Ok(None);
+        }
+        let chunks = if obj.hasattr("chunks")? {
+            obj.getattr("chunks")?
+                .iter()?
+                .map(|chunk| export_chunk(chunk?))
+                .collect::<PyResult<Vec<_>>>()?
+        } else if obj.hasattr("_export_to_c")? {
+            vec![export_chunk(obj)?]
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(ArrowStrings { chunks }))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.chunks.iter().map(|c| c.array.length as usize).sum()
+    }
+
+    /// iterate `texts[range]`, yielding `None` for nulls and invalid utf-8.
+    pub(crate) fn iter_range(&self, range: Range<usize>) -> impl Iterator<Item = Option<&str>> {
+        let mut chunk_start = 0;
+        self.chunks.iter().flat_map(move |c| {
+            let len = c.array.length as usize;
+            let start = range.start.clamp(chunk_start, chunk_start + len) - chunk_start;
+            let end = range.end.clamp(chunk_start, chunk_start + len) - chunk_start;
+            chunk_start += len;
+            (start..end).map(move |i| c.get(i))
+        })
+    }
+}
+
+impl Chunk {
+    fn get(&self, i: usize) -> Option<&str> {
+        let array = &self.array;
+        let i = i + array.offset as usize;
+        unsafe {
+            let buffers = std::slice::from_raw_parts(array.buffers, 3);
+            let validity = buffers[0] as *const u8;
+            if !validity.is_null() && *validity.add(i / 8) & (1 << (i % 8)) == 0 {
+                return None;
+            }
+            let (start, end) = match self.offsets {
+                Offsets::I32 => {
+                    let offsets = buffers[1] as *const i32;
+                    (*offsets.add(i) as usize, *offsets.add(i + 1) as usize)
+                }
+                Offsets::I64 => {
+                    let offsets = buffers[1] as *const i64;
+                    (*offsets.add(i) as usize, *offsets.add(i + 1) as usize)
+                }
+            };
+            if start == end {
+                return Some("");
+            }
+            let bytes =
+                std::slice::from_raw_parts((buffers[2] as *const u8).add(start), end - start);
+            match std::str::from_utf8(bytes) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    error!("Invalid utf-8 encountered in arrow input, ignoring: {e}");
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn export_chunk(obj: &PyAny) -> PyResult<Chunk> {
+    // string views (e.g. from polars) have no offsets buffer.
+    let obj = if obj.getattr("type")?.str()?.to_str()? == "string_view" {
+        obj.call_method1("cast", ("large_string",))?
+    } else {
+        obj
+    };
+    let mut array = Box::new(ArrowArray::empty());
+    let mut schema = Box::new(ArrowSchema::empty());
+    obj.call_method1(
+        "_export_to_c",
+        (
+            array.as_mut() as *mut ArrowArray as usize,
+            schema.as_mut() as *mut ArrowSchema as usize,
+        ),
+    )?;
+    let format = unsafe { CStr::from_ptr(schema.format) }.to_bytes();
+    let offsets = match format {
+        b"u" => Offsets::I32,
+        b"U" => Offsets::I64,
+        _ => {
+            return Err(PyException::new_err(format!(
+                "unsupported arrow type `{}`, expected string or large_string",
+                String::from_utf8_lossy(format)
+            )))
+        }
+    };
+    Ok(Chunk {
+        array,
+        _schema: schema,
+        offsets,
+    })
+}
@@ -0,0 +1,102 @@
+//! resolve asyncio futures with predictions made on background threads.
+
+use crate::decode::Decode;
+use crate::{FastTextPy, Texts};
+use fasttext_parallel_core::PredictOptions;
+use log::warn;
+use numpy::ToPyArray;
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// predict `texts` on a background thread, returning a future of the running
+/// event loop that resolves with `(labels, probs)`.
+///
+/// Cancelling the future stops the pipeline as its next row is written.
+pub(crate) fn batch_async(
+    model: Py<FastTextPy>,
+    texts: Texts,
+    mut options: PredictOptions,
+    py: Python,
+) -> PyResult<PyObject> {
+    let event_loop: PyObject = py
+        .import("asyncio")?
+        .call_method0("get_running_loop")?
+        .into();
+    let future = event_loop.call_method0(py, "create_future")?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let on_done = Cancel {
+        cancelled: cancelled.clone(),
+    };
+    future.call_method1(py, "add_done_callback", (on_done,))?;
+    options.cancelled = Some(cancelled);
+    let resolve = Resolve {
+        future: future.clone_ref(py),
+        result: None,
+    };
+    std::thread::spawn(move || {
+        Python::with_gil(|py| {
+            let len = texts.len(py);
+            let model = model.borrow(py);
+            let result = model
+                .predict_rows::<f32>(&texts, 0..len, &options, None, &Decode::default(), py)
+                .map(|(predictions, _)| {
+                    let labels = model.label_dtype.to_pyarray(&predictions.labels, py);
+                    let probs = predictions.probs.to_pyarray(py);
+                    (labels, probs).into_py(py)
+                });
+            let resolve = Resolve {
+                result: Some(result),
+                ..resolve
+            };
+            if let Err(e) = event_loop.call_method1(py, "call_soon_threadsafe", (resolve,)) {
+                // the loop is closed, so nobody is left to await the result.
+                py.allow_threads(|| {
+                    warn!("Event loop closed before batch_async finished: {e}");
+                });
+            }
+        })
+    });
+    Ok(future)
+}
+
+/// the done callback of the future, which flags the pipeline to stop if the
+/// future was cancelled.
+#[pyclass]
+struct Cancel {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl Cancel {
+    fn __call__(&self, future: &PyAny) -> PyResult<()> {
+        if future.call_method0("cancelled")?.is_true()? {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// sets the result of the future, called on its event loop thread, unless
+/// the future was cancelled meanwhile.
+#[pyclass]
+struct Resolve {
+    future: PyObject,
+    result: Option<PyResult<PyObject>>,
+}
+
+#[pymethods]
+impl Resolve {
+    fn __call__(&mut self, py: Python) -> PyResult<()> {
+        let future = self.future.as_ref(py);
+        if future.call_method0("cancelled")?.is_true()? {
+            return Ok(());
+        }
+        match self.result.take() {
+            Some(Ok(result)) => future.call_method1("set_result", (result,))?,
+            Some(Err(e)) => future.call_method1("set_exception", (e.value(py),))?,
+            None => return Ok(()),
+        };
+        Ok(())
+    }
+}
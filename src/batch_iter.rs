@@ -0,0 +1,168 @@
+//! stream predictions as they complete, in input order or not.
+
+use crate::decode::Decode;
+use crate::{FastTextPy, LabelDtype, Texts};
+use crossbeam::channel::{bounded, Receiver, Sender};
+use fasttext_parallel_core::{ChunkSender, PredictOptions, Row};
+use ndarray::Array1;
+use numpy::ToPyArray;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// the number of completed rows the pipeline may run ahead of the consumer
+/// of an unordered iterator.
+const STREAM_BUFFER: usize = 1024;
+
+/// the input index, labels and probabilities of a row, without padding.
+type IndexedRow = (usize, Vec<i32>, Vec<f32>);
+
+/// the iterator returned by `FastText.batch_iter` and `FastText.batch_stream`.
+#[pyclass]
+pub(crate) struct BatchIter {
+    /// rows in completion order, ending with the error of the pipeline,
+    /// if any.
+    receiver: Receiver<PyResult<(usize, Row)>>,
+    /// restores input order, if requested.
+    reorder: Option<Reorder>,
+    label_dtype: LabelDtype,
+}
+
+/// rows received ahead of the next row in input order.
+///
+/// Texts are sent to the pipeline at most `window` rows ahead of the next
+/// row to yield, so at most `window` rows are pending, however late a row
+/// completes: the sender blocks until it is yielded.
+struct Reorder {
+    next: usize,
+    pending: BTreeMap<usize, Row>,
+    /// a credit per text sent and not yet yielded, `window` at most.
+    credits: Receiver<()>,
+}
+
+impl BatchIter {
+    /// run the whole pipeline in a background thread, which stops once the
+    /// iterator is dropped, yielding rows in input order if a reorder
+    /// `window` is given.
+    pub(crate) fn stream(
+        model: Py<FastTextPy>,
+        texts: Texts,
+        options: PredictOptions,
+        window: Option<usize>,
+        py: Python,
+    ) -> Self {
+        let label_dtype = model.borrow(py).label_dtype;
+        let (row_sender, row_receiver) = bounded(STREAM_BUFFER);
+        let (credit_sender, credit_receiver) = bounded(window.unwrap_or(0));
+        std::thread::spawn(move || {
+            Python::with_gil(|py| {
+                let model = model.borrow(py);
+                let model = &*model;
+                let len = texts.len(py);
+                let result = py.allow_threads(|| {
+                    fasttext_parallel_core::run_pipeline(
+                        |mut text_sender| match window {
+                            Some(window) => {
+                                send_windowed(&texts, len, window, &mut text_sender, credit_sender)
+                            }
+                            None => Python::with_gil(|py| {
+                                texts
+                                    .send(0..len, text_sender, None, &Decode::default(), py)
+                                    .map(drop)
+                            }),
+                        },
+                        |i, text| model.predictor.predict_row(i, text, &options),
+                        |i, row| {
+                            row_sender
+                                .send(Ok((i, row)))
+                                .map_err(|_| PyException::new_err("iterator dropped"))
+                        },
+                    )
+                });
+                if let Err(e) = result {
+                    // fails only if the iterator is gone, so nobody is left to tell.
+                    let _ = row_sender.send(Err(e));
+                }
+            })
+        });
+        BatchIter {
+            receiver: row_receiver,
+            reorder: window.map(|_| Reorder {
+                next: 0,
+                pending: BTreeMap::new(),
+                credits: credit_receiver,
+            }),
+            label_dtype,
+        }
+    }
+
+    fn next_row(&mut self, py: Python) -> PyResult<Option<IndexedRow>> {
+        loop {
+            if let Some(reorder) = &mut self.reorder {
+                if let Some(row) = reorder.pending.remove(&reorder.next) {
+                    reorder.next += 1;
+                    // lets the sender send one more text.
+                    let _ = reorder.credits.try_recv();
+                    return Ok(Some((reorder.next - 1, row.labels, row.probs)));
+                }
+            }
+            let (i, row) = match py.allow_threads(|| self.receiver.recv()) {
+                Ok(row) => row?,
+                // the pipeline has finished.
+                Err(_) => return Ok(None),
+            };
+            match &mut self.reorder {
+                Some(reorder) => {
+                    reorder.pending.insert(i, row);
+                }
+                None => return Ok(Some((i, row.labels, row.probs))),
+            }
+        }
+    }
+}
+
+/// send the texts in blocks of half a `window`, each once a credit per text
+/// is granted, so that the next block is predicted while the rows of the
+/// previous one are yielded.
+///
+/// Called without the GIL, which is taken to send each block only, so that
+/// the consumer can run while the sender waits.
+fn send_windowed<'t>(
+    texts: &'t Texts,
+    len: usize,
+    window: usize,
+    text_sender: &mut ChunkSender<Option<Cow<'t, str>>>,
+    credits: Sender<()>,
+) -> PyResult<()> {
+    let block = (window / 2).max(1);
+    for start in (0..len).step_by(block) {
+        let range = start..len.min(start + block);
+        for _ in range.clone() {
+            if credits.send(()).is_err() {
+                // the iterator is gone.
+                return Ok(());
+            }
+        }
+        Python::with_gil(|py| texts.send_to(range, text_sender, None, &Decode::default(), py))?;
+        // a partial chunk would wait for texts sent after its rows are yielded.
+        if text_sender.flush().is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[pymethods]
+impl BatchIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        Ok(self.next_row(py)?.map(|(i, labels, probs)| {
+            let labels = self.label_dtype.to_pyarray(&Array1::from(labels), py);
+            (i, labels, probs.to_pyarray(py)).to_object(py)
+        }))
+    }
+}
@@ -0,0 +1,94 @@
+//! compare the predictions of two models over the same texts.
+
+use crate::decode::Decode;
+use crate::{run_pipeline, FastTextPy, Texts};
+use fasttext_parallel_core::{PredictOptions, Row, Thresholds};
+use ndarray::{Array1, Array2, Ix2};
+use numpy::ToPyArray;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// predict texts with two models in one pass, e.g. to check that a retrained
+/// model agrees with the one it replaces.
+///
+/// Each text is read once and predicted by both models within the same
+/// worker task. Top-1 labels are compared by name, so the models may number
+/// their labels differently.
+///
+/// Args:
+///     model_a: a model loaded by `load_model`
+///     model_b: another model loaded by `load_model`
+///     texts: same as `FastText.batch`
+///     k: output k predictions per text and model
+///     threshold: ignore predictions with probability below it
+///
+/// Returns:
+///     a dict with `agreement`, the share of texts whose top-1 labels match,
+///     where texts neither model predicts match, and `1.0` without texts
+///     since none disagree, `match`, whether they match for each text in
+///     np.ndarray(bool), and `labels_a`, `probs_a`,
+///     `labels_b`, `probs_b`, the predictions of each model as returned by
+///     `FastText.batch`, with its own label ids.
+#[pyfunction]
+#[pyo3(signature = (model_a, model_b, texts, k=1, threshold=-1.0))]
+pub(crate) fn compare<'py>(
+    model_a: PyRef<FastTextPy>,
+    model_b: PyRef<FastTextPy>,
+    texts: PyObject,
+    k: i32,
+    threshold: f32,
+    py: Python<'py>,
+) -> PyResult<&'py PyDict> {
+    model_a.predictor.check_supervised()?;
+    model_b.predictor.check_supervised()?;
+    let (model_a, model_b): (&FastTextPy, &FastTextPy) = (&model_a, &model_b);
+    let options = PredictOptions::new(k, Thresholds::global(threshold));
+    let texts = Texts::from_py(texts.as_ref(py))?;
+    let rows = texts.len(py);
+    let k = k.max(0) as usize;
+    let mut labels = [(); 2].map(|_| Array2::<i32>::default(Ix2(rows, k)));
+    let mut probs = [(); 2].map(|_| Array2::<f32>::default(Ix2(rows, k)));
+    let mut matches = Array1::from_elem(rows, false);
+    run_pipeline(
+        py,
+        |text_sender, py| {
+            texts
+                .send(0..rows, text_sender, None, &Decode::default(), py)
+                .map(drop)
+        },
+        |i, text| {
+            let row_a = model_a.predictor.predict_row(i, text.clone(), &options);
+            let row_b = model_b.predictor.predict_row(i, text, &options);
+            let top1 = |model: &FastTextPy, row: &Row| {
+                row.labels
+                    .first()
+                    .and_then(|id| model.predictor.reverse_label_dict().get(id))
+                    .cloned()
+            };
+            let matched = top1(model_a, &row_a) == top1(model_b, &row_b);
+            ([row_a, row_b], matched)
+        },
+        |i, (model_rows, matched)| {
+            for (m, row) in model_rows.into_iter().enumerate() {
+                let n = row.labels.len();
+                labels[m].row_mut(i).as_slice_mut().unwrap()[..n].copy_from_slice(&row.labels);
+                probs[m].row_mut(i).as_slice_mut().unwrap()[..n].copy_from_slice(&row.probs);
+            }
+            matches[i] = matched;
+        },
+    )?;
+    let agreement = match rows {
+        0 => 1.0,
+        _ => matches.iter().filter(|&&m| m).count() as f64 / rows as f64,
+    };
+    let [labels_a, labels_b] = labels;
+    let [probs_a, probs_b] = probs;
+    let result = PyDict::new(py);
+    result.set_item("agreement", agreement)?;
+    result.set_item("match", matches.to_pyarray(py))?;
+    result.set_item("labels_a", model_a.label_dtype.to_pyarray(&labels_a, py))?;
+    result.set_item("probs_a", probs_a.to_pyarray(py))?;
+    result.set_item("labels_b", model_b.label_dtype.to_pyarray(&labels_b, py))?;
+    result.set_item("probs_b", probs_b.to_pyarray(py))?;
+    Ok(result)
+}
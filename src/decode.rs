@@ -0,0 +1,164 @@
+//! decode `bytes` texts and check other elements in the text sender thread.
+
+use fasttext_parallel_core::exceptions::PredictionError;
+use fasttext_parallel_core::ErrorPolicy;
+use log::error;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+/// how undecodable bytes are treated, as the `errors` of `bytes.decode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum DecodeErrors {
+    /// the text fails to decode, and follows the `on_error` policy.
+    #[default]
+    Strict,
+    /// invalid bytes are replaced by U+FFFD.
+    Replace,
+    /// invalid bytes are dropped.
+    Ignore,
+}
+
+impl DecodeErrors {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "strict" => Ok(DecodeErrors::Strict),
+            "replace" => Ok(DecodeErrors::Replace),
+            "ignore" => Ok(DecodeErrors::Ignore),
+            _ => Err(PyException::new_err(format!(
+                "unknown decode errors `{s}`, expected one of `strict`, `replace`, `ignore`"
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            DecodeErrors::Strict => "strict",
+            DecodeErrors::Replace => "replace",
+            DecodeErrors::Ignore => "ignore",
+        }
+    }
+}
+
+/// how elements that are neither text nor `None` are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum InvalidPolicy {
+    /// log them and output empty rows.
+    #[default]
+    Empty,
+    /// leave them out of the output.
+    Skip,
+    /// abort the batch with the index of the first one.
+    Raise,
+}
+
+impl InvalidPolicy {
+    pub(crate) fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "empty" => Ok(InvalidPolicy::Empty),
+            "skip" => Ok(InvalidPolicy::Skip),
+            "raise" => Ok(InvalidPolicy::Raise),
+            _ => Err(PyException::new_err(format!(
+                "unknown invalid input policy `{s}`, expected one of `empty`, `skip`, `raise`"
+            ))),
+        }
+    }
+}
+
+/// the input indices of the elements that could not be read.
+#[derive(Debug, Default)]
+pub(crate) struct Rejected {
+    /// `bytes` that failed to decode, with the error.
+    pub(crate) undecodable: Vec<(usize, String)>,
+    /// elements that are neither text nor `None`.
+    pub(crate) invalid: Vec<usize>,
+}
+
+/// how the elements of texts are read: the encoding of `bytes` texts,
+/// UTF-8 strict by default, and the treatment of invalid elements.
+///
+/// UTF-8 is decoded in Rust, other encodings by `bytes.decode`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Decode {
+    /// the Python codec name, `None` for UTF-8.
+    encoding: Option<String>,
+    errors: DecodeErrors,
+    on_error: ErrorPolicy,
+    pub(crate) on_invalid: InvalidPolicy,
+}
+
+impl Decode {
+    pub(crate) fn new(
+        encoding: &str,
+        errors: &str,
+        on_error: ErrorPolicy,
+        on_invalid: InvalidPolicy,
+        py: Python,
+    ) -> PyResult<Self> {
+        let errors = DecodeErrors::parse(errors)?;
+        let codec = py
+            .import("codecs")?
+            .call_method1("lookup", (encoding,))?
+            .getattr("name")?
+            .extract::<String>()?;
+        let encoding = (codec != "utf-8").then_some(codec);
+        Ok(Decode {
+            encoding,
+            errors,
+            on_error,
+            on_invalid,
+        })
+    }
+
+    /// handle the `i`-th element, which is neither text nor `None`.
+    ///
+    /// Fails if `on_invalid` is `Raise`, and logs it otherwise.
+    pub(crate) fn invalid(&self, i: usize, element: &PyAny) -> PyResult<()> {
+        let type_name = element.get_type().name()?;
+        if self.on_invalid == InvalidPolicy::Raise {
+            return Err(PyException::new_err(format!(
+                "invalid text {i}: expected str or bytes, got {type_name}"
+            )));
+        }
+        element.py().allow_threads(|| {
+            error!("Non-string element {i} of type {type_name} encountered in input, ignoring");
+        });
+        Ok(())
+    }
+
+    /// decode the `i`-th text.
+    ///
+    /// Returns the error if it fails to decode and `on_error` is not `Raise`,
+    /// after logging it.
+    pub(crate) fn decode(&self, i: usize, bytes: &PyBytes) -> PyResult<Result<String, String>> {
+        let decoded = match (&self.encoding, self.errors) {
+            (None, DecodeErrors::Strict) => std::str::from_utf8(bytes.as_bytes())
+                .map(str::to_string)
+                .map_err(|e| e.to_string()),
+            (None, DecodeErrors::Replace) => {
+                Ok(String::from_utf8_lossy(bytes.as_bytes()).into_owned())
+            }
+            (None, DecodeErrors::Ignore) => Ok(bytes
+                .as_bytes()
+                .utf8_chunks()
+                .map(|chunk| chunk.valid())
+                .collect()),
+            (Some(encoding), errors) => bytes
+                .call_method1("decode", (encoding, errors.as_str()))
+                .and_then(|s| s.extract::<String>())
+                .map_err(|e| e.to_string()),
+        };
+        match decoded {
+            Ok(text) => Ok(Ok(text)),
+            Err(e) if self.on_error == ErrorPolicy::Raise => Err(PredictionError::new_err(
+                format!("error decoding text {i}: {e}"),
+            )),
+            Err(e) => {
+                bytes.py().allow_threads(|| {
+                    error!("Error decoding text {i}, ignoring: {e}");
+                });
+                Ok(Err(format!("error decoding: {e}")))
+            }
+        }
+    }
+}
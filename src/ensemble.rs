@@ -0,0 +1,386 @@
+//! combine the predictions of several models over a shared label space.
+
+use crate::decode::Decode;
+use crate::{run_pipeline, FastTextPy, LabelDtype, Texts};
+use fasttext_parallel_core::Preprocess;
+use log::{error, trace};
+use ndarray::{Array2, Ix2};
+use numpy::ToPyArray;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{IntoPyDict, PyDict, PyTuple};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// how the probabilities of a label are combined across models.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pooling {
+    Mean,
+    Max,
+    /// the share of models whose top label it is.
+    Vote,
+}
+
+impl Pooling {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "mean" => Ok(Pooling::Mean),
+            "max" => Ok(Pooling::Max),
+            "vote" => Ok(Pooling::Vote),
+            _ => Err(PyException::new_err(format!(
+                "unknown pooling `{s}`, expected one of `mean`, `max`, `vote`"
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Pooling::Mean => "mean",
+            Pooling::Max => "max",
+            Pooling::Vote => "vote",
+        }
+    }
+}
+
+#[pyclass(name = "Ensemble")]
+pub(crate) struct EnsemblePy {
+    models: Vec<Py<FastTextPy>>,
+    /// for each model, the ensemble id of each of its label ids.
+    label_maps: Vec<Vec<i32>>,
+    labels: Vec<String>,
+    /// `i32` if any model has `i32` label ids.
+    label_dtype: LabelDtype,
+    pooling: Pooling,
+    /// the weight of each model in mean pooling and voting, summing to one.
+    weights: Vec<f32>,
+}
+
+#[pymethods]
+impl EnsemblePy {
+    /// combine models for prediction.
+    ///
+    /// Ensemble label ids follow the label order of the first model,
+    /// then the new labels of each following model.
+    ///
+    /// Args:
+    ///     models: a non-empty list of models loaded by `load_model`
+    ///     labels: `"shared"` requires all models to have the same labels,
+    ///         `"union"` predicts over the union of their labels, where a
+    ///         model gives zero probability to the labels it lacks.
+    ///     pooling: `"mean"` averages the probabilities of each label over
+    ///         the models, `"max"` takes their maximum, and `"vote"` gives
+    ///         each label the share of models predicting it as their top
+    ///         label, ties ranked by label id.
+    ///     weights: an optional non-negative weight per model for `"mean"`
+    ///         or `"vote"` pooling, which then take the weighted average or
+    ///         weighted vote.
+    #[new]
+    #[pyo3(signature = (models, labels="shared", pooling="mean", weights=None))]
+    pub(crate) fn new(
+        models: Vec<Py<FastTextPy>>,
+        labels: &str,
+        pooling: &str,
+        weights: Option<Vec<f32>>,
+        py: Python,
+    ) -> PyResult<Self> {
+        let pooling = Pooling::parse(pooling)?;
+        let weights = match weights {
+            Some(_) if pooling == Pooling::Max => {
+                return Err(PyException::new_err(
+                    "weights require pooling=\"mean\" or pooling=\"vote\"",
+                ))
+            }
+            Some(weights) => normalize_weights(weights, models.len())?,
+            None => vec![1.0 / models.len() as f32; models.len()],
+        };
+        let union = match labels {
+            "shared" => false,
+            "union" => true,
+            _ => {
+                return Err(PyException::new_err(format!(
+                    "unknown labels mode `{labels}`, expected one of `shared`, `union`"
+                )))
+            }
+        };
+        let Some(first) = models.first() else {
+            return Err(PyException::new_err("an ensemble needs at least one model"));
+        };
+        let first_labels: BTreeSet<String> = first
+            .borrow(py)
+            .predictor
+            .label_dict()
+            .keys()
+            .cloned()
+            .collect();
+        let mut label_dict = BTreeMap::<String, i32>::new();
+        let mut ensemble_labels = Vec::new();
+        let mut label_maps = Vec::with_capacity(models.len());
+        for (i, model) in models.iter().enumerate() {
+            let model = model.borrow(py);
+            model.predictor.check_supervised()?;
+            if !union && !model.predictor.label_dict().keys().eq(first_labels.iter()) {
+                return Err(PyException::new_err(format!(
+                    "model {i} has different labels from model 0, use labels=\"union\""
+                )));
+            }
+            let mut label_map = vec![-1; model.predictor.reverse_label_dict().len()];
+            for (&local, label) in model.predictor.reverse_label_dict() {
+                let id = *label_dict.entry(label.clone()).or_insert_with(|| {
+                    ensemble_labels.push(label.clone());
+                    (ensemble_labels.len() - 1) as i32
+                });
+                label_map[local as usize] = id;
+            }
+            label_maps.push(label_map);
+        }
+        let label_dtype = models
+            .iter()
+            .map(|model| model.borrow(py).label_dtype)
+            .max()
+            .unwrap_or_default();
+        if !label_dtype.fits(ensemble_labels.len()) {
+            return Err(PyException::new_err(format!(
+                "the {} labels of the ensemble do not fit in i16, load the models with \
+                 label_dtype=\"i32\"",
+                ensemble_labels.len()
+            )));
+        }
+        Ok(EnsemblePy {
+            models,
+            label_maps,
+            labels: ensemble_labels,
+            label_dtype,
+            pooling,
+            weights,
+        })
+    }
+
+    /// batch texts prediction with all models using multithreading.
+    ///
+    /// Args:
+    ///     texts: same as `FastText.batch`
+    ///     k: output k predictions per text
+    ///     threshold: the minimal pooled probability
+    ///
+    /// Returns:
+    ///     A label, probability pairs in np.ndarray(i16) and np.ndarray(f32)
+    ///     format, with ensemble label ids, np.ndarray(i32) if any model was
+    ///     loaded with `label_dtype="i32"`. Rows with fewer than k predictions
+    ///     are padded with zeros.
+    #[pyo3(signature = (texts, k=1, threshold=-1.0))]
+    pub(crate) fn batch(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        self.batch_pooled(texts, k, threshold, self.pooling, py)
+    }
+
+    /// `batch` with another pooling than the ensemble's.
+    ///
+    /// Args:
+    ///     texts: same as `FastText.batch`
+    ///     k: output k predictions per text
+    ///     threshold: the minimal pooled probability
+    ///     agg: `"mean"`, `"max"` or `"vote"` as the `pooling` of the
+    ///         ensemble, which is used if `None`. Weights are ignored by
+    ///         `"max"`.
+    ///
+    /// Returns:
+    ///     the same as `batch`.
+    #[pyo3(signature = (texts, k=1, threshold=-1.0, agg=None))]
+    fn predict_ensemble(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: f32,
+        agg: Option<&str>,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let pooling = agg.map_or(Ok(self.pooling), Pooling::parse)?;
+        self.batch_pooled(texts, k, threshold, pooling, py)
+    }
+
+    /// get the mapping from ensemble label id to label.
+    fn get_labels<'a>(&self, py: Python<'a>) -> &'a PyDict {
+        self.labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| (i as i32, label))
+            .into_py_dict(py)
+    }
+
+    /// the number of models.
+    fn __len__(&self) -> usize {
+        self.models.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Ensemble(models={}, labels={}, pooling={})",
+            self.models.len(),
+            self.labels.len(),
+            self.pooling.name()
+        )
+    }
+}
+
+impl EnsemblePy {
+    /// predict texts with all models, pooled by `pooling`.
+    fn batch_pooled(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: f32,
+        pooling: Pooling,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let models: Vec<PyRef<FastTextPy>> = self.models.iter().map(|m| m.borrow(py)).collect();
+        let models: Vec<&FastTextPy> = models.iter().map(|m| &**m).collect();
+        self.check_labels(&models)?;
+        let counts = texts.len(py);
+        let k = k.max(0) as usize;
+        let mut labels = Array2::<i32>::default(Ix2(counts, k));
+        let mut probs = Array2::<f32>::default(Ix2(counts, k));
+        run_pipeline(
+            py,
+            |text_sender, py| {
+                texts
+                    .send(0..counts, text_sender, None, &Decode::default(), py)
+                    .map(drop)
+            },
+            |i, text| self.predict(&models, i, text, k, threshold, pooling),
+            |i, (label, prob)| {
+                labels.row_mut(i).as_slice_mut().unwrap()[..label.len()].copy_from_slice(&label);
+                probs.row_mut(i).as_slice_mut().unwrap()[..prob.len()].copy_from_slice(&prob);
+            },
+        )?;
+        let outputs = [
+            self.label_dtype.to_pyarray(&labels, py),
+            probs.to_pyarray(py).to_object(py),
+        ];
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
+
+    /// fail if a model has been reloaded with other labels since the
+    /// ensemble was built.
+    fn check_labels(&self, models: &[&FastTextPy]) -> PyResult<()> {
+        for (i, (model, label_map)) in models.iter().zip(&self.label_maps).enumerate() {
+            let labels = model.predictor.reverse_label_dict();
+            let unchanged = labels.len() == label_map.len()
+                && labels.iter().all(|(&local, label)| {
+                    self.labels[label_map[local as usize] as usize] == *label
+                });
+            if !unchanged {
+                return Err(PyException::new_err(format!(
+                    "the labels of model {i} changed since the ensemble was built"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// pool the full label distributions of all models and take the top k.
+    fn predict(
+        &self,
+        models: &[&FastTextPy],
+        i: usize,
+        text: Option<Cow<str>>,
+        k: usize,
+        threshold: f32,
+        pooling: Pooling,
+    ) -> (Vec<i32>, Vec<f32>) {
+        let text = match text.map(|s| Preprocess::default().apply(s)) {
+            Some(Ok(text)) => text,
+            Some(Err(s)) => {
+                error!("Text {i} rejected by preprocessing, ignoring: {:?}", s);
+                return (vec![], vec![]);
+            }
+            None => return (vec![], vec![]),
+        };
+        trace!("text received: {:?}", text);
+        let mut pooled = vec![0.0f32; self.labels.len()];
+        for ((model, label_map), weight) in models.iter().zip(&self.label_maps).zip(&self.weights) {
+            // a vote only needs the top label of each model.
+            let candidates = if pooling == Pooling::Vote { 1 } else { -1 };
+            let predictions = match model.predictor.predict_text(&text, candidates, -1.0) {
+                Ok(predictions) => predictions,
+                Err(e) => {
+                    error!("Error making prediction, ignoring: {e}");
+                    continue;
+                }
+            };
+            for p in predictions {
+                let Some(&local) = model.predictor.label_dict().get(&p.label) else {
+                    continue;
+                };
+                let pooled = &mut pooled[label_map[local as usize] as usize];
+                match pooling {
+                    Pooling::Mean => *pooled += weight * p.prob,
+                    Pooling::Max => *pooled = pooled.max(p.prob),
+                    Pooling::Vote => *pooled += weight,
+                }
+            }
+        }
+        let mut ranked: Vec<(i32, f32)> = pooled
+            .into_iter()
+            .enumerate()
+            .map(|(id, prob)| (id as i32, prob))
+            .filter(|&(_, prob)| prob > 0.0 && prob >= threshold)
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(k);
+        ranked.into_iter().unzip()
+    }
+}
+
+/// check one non-negative weight per model, and scale them to sum to one.
+fn normalize_weights(weights: Vec<f32>, models: usize) -> PyResult<Vec<f32>> {
+    if weights.len() != models {
+        return Err(PyException::new_err(format!(
+            "got {} weights for {models} models",
+            weights.len()
+        )));
+    }
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+        return Err(PyException::new_err(
+            "weights must be finite and non-negative",
+        ));
+    }
+    let sum: f32 = weights.iter().sum();
+    if sum <= 0.0 {
+        return Err(PyException::new_err("weights must not all be zero"));
+    }
+    Ok(weights.into_iter().map(|w| w / sum).collect())
+}
+
+/// predict texts with several models at once, averaging their probabilities.
+///
+/// Each text is predicted by all models within the same worker task. Labels
+/// are aligned by name, and a model gives zero probability to labels it
+/// lacks. This is `Ensemble(models, labels="union", weights=weights).batch`.
+///
+/// Args:
+///     models: a non-empty list of models loaded by `load_model`
+///     texts: same as `FastText.batch`
+///     k: output k predictions per text
+///     weights: an optional non-negative weight per model
+///
+/// Returns:
+///     A label, probability pairs in np.ndarray(i16) and np.ndarray(f32)
+///     format. Label ids follow the label order of the first model, then the
+///     new labels of each following model, as in `Ensemble.get_labels`.
+#[pyfunction]
+#[pyo3(signature = (models, texts, k=1, weights=None))]
+pub(crate) fn batch_ensemble(
+    models: Vec<Py<FastTextPy>>,
+    texts: PyObject,
+    k: i32,
+    weights: Option<Vec<f32>>,
+    py: Python,
+) -> PyResult<PyObject> {
+    EnsemblePy::new(models, "union", "mean", weights, py)?.batch(texts, k, -1.0, py)
+}
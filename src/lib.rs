@@ -1,59 +1,281 @@
-use crossbeam::channel::{bounded, Receiver, Sender};
-use fasttext::FastText;
-use log::{debug, error};
-use ndarray::{Array2, Ix2};
-use numpy::ToPyArray;
+mod arrow;
+mod awaitable;
+mod batch_iter;
+mod compare;
+mod decode;
+mod ensemble;
+mod model_file;
+mod numpy_strings;
+mod reduce;
+
+use arrow::ArrowStrings;
+use batch_iter::BatchIter;
+use decode::{Decode, InvalidPolicy, Rejected};
+
+use ensemble::{batch_ensemble, EnsemblePy};
+use fasttext_parallel_core::exceptions::{
+    FastTextError, ModelLoadError, ModelTypeError, PredictionError,
+};
+use fasttext_parallel_core::{
+    thread_pool, AutotuneArgs, BatchStats, ChunkSender, EmptyPolicy, Error, ErrorKind, ErrorPolicy,
+    LabelId, NewlinePolicy, ParallelPredictor, PredictOptions, Predictions, Preprocess,
+    Probability, Thresholds, TrainArgs, UnicodeForm,
+};
+use log::{debug, error, trace, warn, LevelFilter};
+use model_file::{Matrix, ModelSource};
+use ndarray::{s, Array, Array1, Array2, Axis, Dimension, Ix2};
+use numpy::{Element, IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, ToPyArray};
+use numpy_strings::NumpyStrings;
 use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
-use pyo3::types::{IntoPyDict, PyDict, PyList, PyString};
+use pyo3::types::{IntoPyDict, PyBytes, PyDict, PyList, PyString, PyTuple};
+use pyo3_log::{Caching, Logger, ResetHandle};
 use rayon::prelude::*;
+use std::borrow::Cow;
 use std::cmp::max;
-use std::collections::BTreeMap;
-use std::thread::available_parallelism;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// which vector represents a text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VectorMode {
+    /// the word vector, built from subwords for out-of-vocabulary words.
+    Word,
+    /// the sentence vector.
+    Sentence,
+}
+
+impl VectorMode {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "word" => Ok(VectorMode::Word),
+            "sentence" => Ok(VectorMode::Sentence),
+            _ => Err(PyException::new_err(format!(
+                "unknown mode `{s}`, expected one of `word`, `sentence`"
+            ))),
+        }
+    }
+}
+
+/// how `batch` returns its predictions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// padded label id and probability arrays.
+    Arrays,
+    /// a list of dicts from label to probability.
+    Dicts,
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "arrays" => Ok(OutputFormat::Arrays),
+            "dicts" => Ok(OutputFormat::Dicts),
+            _ => Err(PyException::new_err(format!(
+                "unknown output `{s}`, expected one of `arrays`, `dicts`"
+            ))),
+        }
+    }
+}
+
+/// the numpy dtype of the probabilities returned by `batch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProbDtype {
+    F32,
+    F64,
+}
+
+impl ProbDtype {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "float32" => Ok(ProbDtype::F32),
+            "float64" => Ok(ProbDtype::F64),
+            _ => Err(PyException::new_err(format!(
+                "unknown dtype `{s}`, expected one of `float32`, `float64`"
+            ))),
+        }
+    }
+}
+
+/// the outputs `batch` returns besides the predictions.
+struct BatchReturns {
+    format: OutputFormat,
+    counts: bool,
+    mask: bool,
+    truncated: bool,
+    entropy: bool,
+    errors: bool,
+    stats: bool,
+}
+
+/// the numpy dtype of label ids, chosen when loading a model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelDtype {
+    /// up to 32768 labels, in half the memory.
+    #[default]
+    I16,
+    I32,
+}
+
+impl LabelDtype {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "i16" => Ok(LabelDtype::I16),
+            "i32" => Ok(LabelDtype::I32),
+            _ => Err(PyException::new_err(format!(
+                "unknown label_dtype `{s}`, expected one of `i16`, `i32`"
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LabelDtype::I16 => "i16",
+            LabelDtype::I32 => "i32",
+        }
+    }
+
+    /// whether the ids `0..num_labels` fit in this dtype.
+    fn fits(self, num_labels: usize) -> bool {
+        match self {
+            LabelDtype::I16 => num_labels <= i16::MAX as usize + 1,
+            LabelDtype::I32 => num_labels <= i32::MAX as usize + 1,
+        }
+    }
+
+    /// label ids as a numpy array of this dtype.
+    fn to_pyarray<D: Dimension>(self, labels: &Array<i32, D>, py: Python) -> PyObject {
+        match self {
+            LabelDtype::I16 => labels.mapv(|id| id as i16).to_pyarray(py).to_object(py),
+            LabelDtype::I32 => labels.to_pyarray(py).to_object(py),
+        }
+    }
+}
+
+/// a 2-D array of label ids given by the caller, of either label dtype.
+#[derive(FromPyObject)]
+enum LabelArray<'py> {
+    I16(&'py PyArray2<i16>),
+    I32(&'py PyArray2<i32>),
+}
 
-const CHANNEL_SIZE: usize = 128;
-const MIN_THREADS: usize = 3;
+/// the `threshold` argument, a float or a dict from label or label id to float.
+#[derive(FromPyObject)]
+enum ThresholdArg {
+    Global(f32),
+    PerLabel(Py<PyDict>),
+}
+
+/// the vocabulary with L2-normalized word vectors, for neighbor search.
+struct WordVectors {
+    words: Vec<String>,
+    vectors: Array2<f32>,
+}
 
 #[pyclass(name = "FastText")]
 struct FastTextPy {
-    model: FastText,
-    label_dict: BTreeMap<String, i16>,
-    reverse_label_dict: BTreeMap<i16, String>,
+    predictor: ParallelPredictor,
+    label_dtype: LabelDtype,
+    word_vectors: OnceLock<WordVectors>,
+    /// the file to read the matrices from.
+    source: ModelSource,
+    input_matrix: OnceLock<Array2<f32>>,
+    /// by label id for supervised models.
+    output_matrix: OnceLock<Array2<f32>>,
 }
 
 /// load model from path.
 ///
 /// Args:
 ///     path: file path of the model
-///     label_to_int: a mapping from fasttext label to a positive i16
+///     label_dtype: the dtype of label ids, `"i16"` or `"i32"` for models
+///         with more than 32768 labels, which raise `ModelLoadError` with
+///         `"i16"`
+///     label_to_int: a dict from label to label id, e.g. from
+///         `load_label_map`, to number the labels like another model rather
+///         than by their order in this one. It must give each label of the
+///         model a distinct id in `range(len(model))`, and may have labels
+///         the model does not.
+///     mmap: memory map the matrices of the model rather than reading them,
+///         so that they are read on first use and shared by the processes
+///         loading the same file. Predictions and vectors are identical, but
+///         the file must not change while the model is loaded, and quantized
+///         models raise `ModelLoadError`.
 #[pyfunction]
-fn load_model(path: &str) -> PyResult<FastTextPy> {
-    let mut model = FastText::new();
-    if let Err(e) = model.load_model(path) {
-        Err(PyException::new_err(e))
-    } else {
-        debug!("model loaded");
-        let labels = match model.get_labels() {
-            Ok((labels, _)) => labels,
-            Err(e) => return Err(PyException::new_err(e)),
-        };
-        let label_dict: BTreeMap<String, i16> = labels
-            .iter()
-            .into_iter()
-            .enumerate()
-            .map(|(i, lab)| (lab.clone(), i as i16))
-            .collect();
-        let reverse_label_dict: BTreeMap<i16, String> = labels
-            .into_iter()
-            .enumerate()
-            .map(|(i, lab)| (i as i16, lab))
-            .collect();
-        Ok(FastTextPy {
-            model,
-            label_dict,
-            reverse_label_dict,
-        })
+#[pyo3(signature = (path, label_dtype="i16", label_to_int=None, mmap=false))]
+fn load_model(
+    path: &str,
+    label_dtype: &str,
+    label_to_int: Option<BTreeMap<String, i32>>,
+    mmap: bool,
+) -> PyResult<FastTextPy> {
+    let label_dtype = LabelDtype::parse(label_dtype)?;
+    let mut predictor = match mmap {
+        true => ParallelPredictor::load_mapped(path)?,
+        false => ParallelPredictor::load(path)?,
+    };
+    if let Some(label_to_int) = &label_to_int {
+        predictor = predictor.with_label_ids(label_to_int)?;
     }
+    check_label_dtype(&predictor, label_dtype)?;
+    Ok(FastTextPy::new(
+        predictor,
+        label_dtype,
+        ModelSource::file(Path::new(path)),
+    ))
+}
+
+/// train a supervised model with the hyperparameters found by fasttext's
+/// autotune, without the GIL.
+///
+/// Trials with sampled hyperparameters are trained on `input_path` and
+/// scored on `validation_path` until `duration_secs` runs out, then the
+/// best ones are trained again, while fasttext prints its progress to
+/// stderr.
+///
+/// fasttext aborts the process if no trial finishes in time, so a training
+/// run with the default hyperparameters, the first trial, is timed before
+/// the search, and raises unless it took at most half of `duration_secs`.
+/// A metric label missing from `input_path` raises too.
+///
+/// Args:
+///     input_path: training file in the fasttext format, one labeled text
+///         per line
+///     validation_path: validation file in the same format
+///     duration_secs: the time budget of the search, in seconds
+///     metric: the objective, `"f1"`, `"f1:LABEL"`,
+///         `"precisionAtRecall:R[:LABEL]"` or `"recallAtPrecision:P[:LABEL]"`,
+///         where `LABEL` has the `__label__` prefix
+///     label_dtype: same as `load_model`
+#[pyfunction]
+#[pyo3(signature = (input_path, validation_path, duration_secs=300, metric="f1", label_dtype="i16"))]
+fn train_autotune(
+    input_path: &str,
+    validation_path: &str,
+    duration_secs: i32,
+    metric: &str,
+    label_dtype: &str,
+    py: Python,
+) -> PyResult<FastTextPy> {
+    let label_dtype = LabelDtype::parse(label_dtype)?;
+    let args = AutotuneArgs {
+        input: input_path.to_string(),
+        validation: validation_path.to_string(),
+        duration_secs,
+        metric: metric.to_string(),
+    };
+    let mut predictor = py.allow_threads(|| ParallelPredictor::autotune(&args))?;
+    check_label_dtype(&predictor, label_dtype)?;
+    let model = predictor.model_mut()?;
+    let source = py
+        .allow_threads(|| ModelSource::saved(model))
+        .map_err(FastTextError::new_err)?;
+    Ok(FastTextPy::new(predictor, label_dtype, source))
 }
 
 #[pymethods]
@@ -61,158 +283,2718 @@ impl FastTextPy {
     /// batch texts prediction using multithreading.
     ///
     /// Args:
-    ///     texts: a list of strings
+    ///     texts: a list of strings, a 1-D numpy str or object array, a pyarrow
+    ///         string array / chunked array, or a polars Utf8 series. Numpy str
+    ///         and arrow-backed inputs are read without converting their
+    ///         elements to `str`, and nulls give empty rows. Lists and object
+    ///         arrays may also hold `bytes`, decoded by `encoding`.
     ///     k: output k predictions per text
-    ///     threshold: the minimal accuracy
+    ///     threshold: the minimal accuracy, or a dict from label (str) or
+    ///         label id (int) to the minimal accuracy of that label.
+    ///     default_threshold: the minimal accuracy of labels missing from a
+    ///         `threshold` dict.
+    ///     allowed_labels: an optional list of label ids, other labels are
+    ///         removed before taking the top k. Thresholds apply to the
+    ///         original probabilities, which are not renormalized over the
+    ///         allowed labels.
+    ///     newline: fasttext only reads the first line of a text, so
+    ///         `"strip"` replaces newlines with spaces, `"keep"` leaves texts
+    ///         as they are to predict their first line only, and `"error"`
+    ///         raises an exception with the index of the first such text.
+    ///     preprocess: an optional callable applied to each text before
+    ///         prediction. It runs in the text sender thread holding the GIL,
+    ///         and any exception it raises aborts the batch.
+    ///     lowercase: lowercase texts in the worker threads, which is much
+    ///         cheaper than a Python `preprocess`. It follows the Unicode
+    ///         default case mapping like `str.lower`, so `"İ"` becomes `"i̇"`
+    ///         and `"I"` becomes `"i"`, never the Turkish dotless `"ı"`.
+    ///         Applied after `preprocess` and `normalize_unicode`.
+    ///     normalize: normalization steps joined by `+`, `"lower"` is the same
+    ///         as `lowercase`, and `"strip"` trims surrounding whitespace.
+    ///         Applied in the worker threads like `lowercase`.
+    ///     normalize_unicode: an optional unicode normal form, `"NFC"`,
+    ///         `"NFKC"`, `"NFD"` or `"NFKD"`, so that visually identical texts
+    ///         tokenize alike. Applied in the worker threads after `preprocess`
+    ///         and before the other steps, so texts are normalized first, then
+    ///         lowercased by `lowercase`. Texts already in the normal form are
+    ///         not copied.
+    ///     max_tokens: keep only the first `max_tokens` tokens of each text,
+    ///         split on whitespace like fasttext, to bound the cost of very
+    ///         long texts. Applied in the worker threads before `normalize`.
+    ///     on_empty: how to treat missing, empty or whitespace-only texts.
+    ///         `"zero"` predicts them as usual, where missing texts give empty
+    ///         rows, `"skip"` leaves them out of the output and appends an
+    ///         np.ndarray(i64) of the input indices of the remaining rows,
+    ///         and `"error"` raises an exception listing their indices.
+    ///     on_error: how to treat texts whose prediction fails. `"ignore"` logs
+    ///         the error and outputs an empty row, `"raise"` aborts the batch
+    ///         with an exception giving the row and the error, and `"report"`
+    ///         also appends an np.ndarray(i64) of the indices of failed rows.
+    ///     on_invalid: how to treat elements of `texts` that are neither text
+    ///         nor `None`, or are not `str` after `preprocess`. `"empty"`
+    ///         logs them and outputs empty rows, `"skip"` leaves them out of
+    ///         the output and appends the indices of the remaining rows as
+    ///         `on_empty="skip"` does, sharing them if both skip, and
+    ///         `"raise"` aborts the batch with the index of the first one.
+    ///     encoding: the encoding of `bytes` texts, decoded in the text sender
+    ///         thread before `preprocess`. UTF-8 is decoded without the GIL,
+    ///         other encodings by `bytes.decode`.
+    ///     errors: how to treat bytes that fail to decode. `"strict"` fails
+    ///         the text, which then follows `on_error` like a failed
+    ///         prediction, `"replace"` inserts U+FFFD and `"ignore"` drops
+    ///         them, as in `bytes.decode`.
+    ///     return_counts: also return the number of predictions in each row.
+    ///     return_mask: also return which cells are predictions, not padding.
+    ///     return_truncated: also return which rows hit the `k` cap, a hint
+    ///         to retry them with a larger `k`.
+    ///     return_entropy: also return the Shannon entropy of the label
+    ///         distribution of each row, high for uncertain predictions. It is
+    ///         computed over all labels, which are predicted for every text
+    ///         whatever `k`, so it costs more with many labels.
+    ///     renormalize: scale the returned probabilities of each row to sum
+    ///         to one. Thresholds apply before, and rows without predictions
+    ///         are left as they are.
+    ///     log_probs: return natural logs of the probabilities, computed in
+    ///         the worker threads after `renormalize`. Thresholds still apply
+    ///         to probabilities.
+    ///     timeout_secs: raise "batch timed out" if the batch takes longer,
+    ///         returning nothing. Texts being predicted or preprocessed are
+    ///         not interrupted, so the call returns once they finish.
+    ///         Likewise, Ctrl-C raises `KeyboardInterrupt` within about
+    ///         0.1 s, once the texts in flight finish, returning nothing.
+    ///     output: `"arrays"` returns label ids and probabilities as below,
+    ///         and `"dicts"` returns a list with a dict from label to
+    ///         probability for each row in their place, empty for rows
+    ///         without predictions. Labels are the strings of `get_labels`.
+    ///     collect_errors: also return the errors of texts whose prediction
+    ///         or decoding failed, which `on_error="ignore"` only logs.
+    ///     dtype: `"float32"` or `"float64"`, the dtype of the returned
+    ///         probabilities. fasttext computes them as float32, which the
+    ///         writer thread casts as rows arrive, so float64 doubles the
+    ///         memory of the probabilities without adding precision to them,
+    ///         only to later computations on them.
+    ///     strict_labels: raise `PredictionError` naming the label if the
+    ///         model predicts a label without an id, rather than giving it
+    ///         the id `-1`. fasttext only predicts the labels of its
+    ///         dictionary, which all have ids, with or without `label_to_int`,
+    ///         so this guards against inconsistent models.
+    ///     return_stats: also return a dict timing the stages of the batch,
+    ///         see below. The stages are only timed with it, so the batch is
+    ///         not slowed otherwise.
     ///
     /// Returns:
     ///     A label, probability pairs in np.ndarray(i16) and np.ndarray(f32)
-    ///     format. Where `-1` is used to represent label not found in label_to_int
-    #[pyo3(signature = (texts, k=1, threshold=-1.0))]
+    ///     format, labels in np.ndarray(i32) for models loaded with
+    ///     `label_dtype="i32"`, probabilities in np.ndarray(f64) with
+    ///     `dtype="float64"`. Where `-1` is used to represent label not found in label_to_int.
+    ///     Rows with fewer than k predictions are padded with zeros, or `-inf`
+    ///     probabilities with `log_probs`. With
+    ///     `return_counts`, a third np.ndarray(i32) gives the valid length of
+    ///     each row. With `return_mask`, a np.ndarray(bool) of the same shape
+    ///     as labels follows, true for predictions and false for padding.
+    ///     With `return_truncated`, a np.ndarray(bool) follows, true for rows
+    ///     with exactly k predictions. This is a proxy: such a row may have
+    ///     had more labels above the threshold, or exactly k of them, and the
+    ///     two cases are not told apart.
+    ///     With `return_entropy`, a np.ndarray(f32) follows, giving the entropy
+    ///     in nats of each row, or NaN for rows not predicted.
+    ///     With `collect_errors`, a list of `(index, error message)` tuples
+    ///     follows, sorted by the input index of the failed text.
+    ///     With `return_stats`, a dict follows last, with the `wall_secs` of
+    ///     the call from the texts to the outputs, the number of `rows` and
+    ///     `rows_per_sec`, the `sender_secs` spent reading texts, which
+    ///     holds the GIL for lists, object arrays and `preprocess`, the
+    ///     `predict_secs` summed over `worker_predict_secs`, the time each
+    ///     pool thread spent predicting, and the `writer_secs` spent writing
+    ///     rows. Waits between the stages are not counted in them.
+    ///     With `output="dicts"` and no other outputs, the list of dicts is
+    ///     returned alone rather than in a tuple.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0, allowed_labels=None, newline="strip", preprocess=None, lowercase=false, normalize=None, normalize_unicode=None, max_tokens=None, on_empty="zero", on_error="ignore", on_invalid="empty", encoding="utf-8", errors="strict", return_counts=false, return_mask=false, return_truncated=false, return_entropy=false, renormalize=false, log_probs=false, timeout_secs=None, output="arrays", collect_errors=false, dtype="float32", strict_labels=false, return_stats=false))]
+    #[allow(clippy::too_many_arguments)]
     fn batch(
         &self,
         texts: PyObject,
         k: i32,
-        threshold: f32,
-        py: Python,
-    ) -> PyResult<(PyObject, PyObject)> {
-        let counts = texts.as_ref(py).downcast::<PyList>()?.len();
-        let mut labels = Array2::<i16>::default(Ix2(counts, k as usize));
-        let mut probs = Array2::<f32>::default(Ix2(counts, k as usize));
-        let (text_sender, text_receiver) = bounded::<Option<String>>(CHANNEL_SIZE);
-        let (result_sender, result_receiver) = bounded(CHANNEL_SIZE);
-        py.allow_threads(|| {
-            rayon::scope(|s| {
-                // text sender
-                s.spawn(|_| {
-                    Python::with_gil(|py| {
-                        let texts = texts.as_ref(py).downcast::<PyList>().unwrap();
-                        send_text(texts, text_sender, py);
-                    });
-                    debug!("text sender thread finished");
-                });
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        allowed_labels: Option<Vec<i32>>,
+        newline: &str,
+        preprocess: Option<PyObject>,
+        lowercase: bool,
+        normalize: Option<&str>,
+        normalize_unicode: Option<&str>,
+        max_tokens: Option<usize>,
+        on_empty: &str,
+        on_error: &str,
+        on_invalid: &str,
+        encoding: &str,
+        errors: &str,
+        return_counts: bool,
+        return_mask: bool,
+        return_truncated: bool,
+        return_entropy: bool,
+        renormalize: bool,
+        log_probs: bool,
+        timeout_secs: Option<f64>,
+        output: &str,
+        collect_errors: bool,
+        dtype: &str,
+        strict_labels: bool,
+        return_stats: bool,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let output = OutputFormat::parse(output)?;
+        let dtype = ProbDtype::parse(dtype)?;
+        let options = PredictOptions {
+            k,
+            thresholds: self.thresholds(threshold, default_threshold, py)?,
+            allowed: allowed_labels.map(|l| self.label_set(l)).transpose()?,
+            transform: Preprocess {
+                unicode: normalize_unicode.map(UnicodeForm::parse).transpose()?,
+                newline: NewlinePolicy::parse(newline)?,
+                lowercase,
+                strip: false,
+                max_tokens,
+            }
+            .normalize(normalize)?,
+            on_empty: EmptyPolicy::parse(on_empty)?,
+            on_error: ErrorPolicy::parse(on_error)?,
+            renormalize,
+            log_probs,
+            entropy: return_entropy,
+            timeout: timeout_secs.map(timeout).transpose()?,
+            cancelled: None,
+            strict_labels,
+            stats: return_stats
+                .then(BatchStats::new)
+                .transpose()?
+                .map(Arc::new),
+        };
+        let decode = Decode::new(
+            encoding,
+            errors,
+            options.on_error,
+            InvalidPolicy::parse(on_invalid)?,
+            py,
+        )?;
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let preprocess = preprocess.as_ref().map(|f| f.as_ref(py));
+        let returns = BatchReturns {
+            format: output,
+            counts: return_counts,
+            mask: return_mask,
+            truncated: return_truncated,
+            entropy: return_entropy,
+            errors: collect_errors,
+            stats: return_stats,
+        };
+        match dtype {
+            ProbDtype::F32 => {
+                self.batch_as::<f32>(&texts, &options, preprocess, &decode, &returns, py)
+            }
+            ProbDtype::F64 => {
+                self.batch_as::<f64>(&texts, &options, preprocess, &decode, &returns, py)
+            }
+        }
+    }
 
-                // processor
-                s.spawn(|_| {
-                    predict_test(self, text_receiver, result_sender, k, threshold);
-                    debug!("processor thread finished");
-                });
+    /// batch prediction of pre-tokenized texts using multithreading.
+    ///
+    /// Tokens are joined by single spaces for fasttext to split again, so
+    /// they should not contain whitespace themselves.
+    ///
+    /// Args:
+    ///     token_lists: a list of lists of str, where an empty list gives an
+    ///         empty row
+    ///     k: output k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     the same as `batch`.
+    #[pyo3(signature = (token_lists, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn batch_tokens(
+        &self,
+        token_lists: &PyList,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::Tokens(token_lists.into());
+        let (predictions, _) = self.predict_rows::<f32>(
+            &texts,
+            0..texts.len(py),
+            &options,
+            None,
+            &Decode::default(),
+            py,
+        )?;
+        let outputs = [
+            self.label_dtype.to_pyarray(&predictions.labels, py),
+            predictions.probs.to_pyarray(py).to_object(py),
+        ];
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
 
-                // result writer
-                s.spawn(|_| {
-                    for (i, result) in result_receiver {
-                        debug!("result {i} received");
-                        let mut label = labels.row_mut(i);
-                        let mut prob = probs.row_mut(i);
-                        label.as_slice_mut().unwrap()[..result.0.len()].copy_from_slice(&result.0);
-                        prob.as_slice_mut().unwrap()[..result.1.len()].copy_from_slice(&result.1);
-                    }
-                });
-            });
-        });
-        let labels = Python::with_gil(|py| labels.to_pyarray(py).to_object(py));
-        let probs = Python::with_gil(|py| probs.to_pyarray(py).to_object(py));
-        Ok((labels, probs))
+    /// batch prediction of the best label of each text.
+    ///
+    /// Predicts as fast as `batch(texts, 1)`, which prediction itself
+    /// dominates, but returns flat arrays that need no `[:, 0]` indexing
+    /// nor a check of the counts.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     The best label id of each text in np.ndarray(i16) and its
+    ///     probability in np.ndarray(f32), `-1` and NaN for texts without
+    ///     a prediction.
+    #[pyo3(signature = (texts, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn predict_top1(
+        &self,
+        texts: PyObject,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(1, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let len = texts.len(py);
+        let (labels, probs) = py.allow_threads(|| {
+            self.predictor.predict_top1(len, &options, |text_sender| {
+                Python::with_gil(|py| {
+                    texts
+                        .send(0..len, text_sender, None, &Decode::default(), py)
+                        .map(drop)
+                })
+            })
+        })?;
+        let outputs = [
+            self.label_dtype.to_pyarray(&labels, py),
+            probs.to_pyarray(py).to_object(py),
+        ];
+        Ok(PyTuple::new(py, outputs).to_object(py))
     }
 
-    /// get the mapping from label index to label.
+    /// batch prediction as a dict from label to probability per text, e.g.
+    /// to serialize as JSON.
+    ///
+    /// Same as `batch(texts, k, threshold, output="dicts")`, whose arrays
+    /// are faster to process in bulk.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: predict k labels per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
     ///
     /// Returns:
-    ///     A dictionary mapping from integer to labels.
-    fn get_labels<'a>(&self, py: Python<'a>) -> &'a PyDict {
-        self.reverse_label_dict.clone().into_py_dict(py)
+    ///     a list with a dict from label to probability for each text, empty
+    ///     for texts without predictions.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn predict_dict(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let (predictions, _) = self.predict_rows::<f32>(
+            &texts,
+            0..texts.len(py),
+            &options,
+            None,
+            &Decode::default(),
+            py,
+        )?;
+        self.label_dicts(&predictions, py)
     }
 
-    /// get a label by the id
+    /// batch prediction of texts given with keys, such as the ids of their
+    /// rows in a database, returned by key.
     ///
     /// Args:
-    ///     id: the id of a label
+    ///     items: a list of `(key, text)` pairs, with distinct hashable keys
+    ///         and texts as in `batch`
+    ///     k: predict k labels per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
     ///
     /// Returns:
-    ///     the label corresponding to the given id.
-    fn get_label_by_id(&self, id: i16) -> Option<&String> {
-        self.reverse_label_dict.get(&id)
+    ///     a dict from each key to the label ids in np.ndarray(i16) and the
+    ///     probabilities in np.ndarray(f32) of its text, without padding, so
+    ///     both are empty for texts without predictions.
+    #[pyo3(signature = (items, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn batch_keyed(
+        &self,
+        items: Vec<(PyObject, PyObject)>,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let (keys, texts): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+        let rows = PyDict::new(py);
+        for (i, key) in keys.iter().enumerate() {
+            if rows.contains(key)? {
+                return Err(PyException::new_err(format!(
+                    "duplicate key {} at index {i}",
+                    key.as_ref(py).repr()?
+                )));
+            }
+            rows.set_item(key, py.None())?;
+        }
+        let texts = Texts::List(PyList::new(py, texts).into());
+        let (predictions, _) = self.predict_rows::<f32>(
+            &texts,
+            0..keys.len(),
+            &options,
+            None,
+            &Decode::default(),
+            py,
+        )?;
+        for (i, key) in keys.into_iter().enumerate() {
+            let count = predictions.counts[i] as usize;
+            let labels = predictions.labels.slice(s![i, ..count]);
+            let probs = predictions.probs.slice(s![i, ..count]);
+            let row = (
+                self.label_dtype.to_pyarray(&labels.to_owned(), py),
+                probs.to_pyarray(py),
+            );
+            rows.set_item(key, row)?;
+        }
+        Ok(rows.to_object(py))
     }
-}
 
-#[inline]
-fn send_text(texts: &PyList, text_sender: Sender<Option<String>>, py: Python) {
-    let texts_iter = texts.iter().map(|s| {
-        s.downcast::<PyString>()
-            .ok()
-            .and_then(|s| match s.to_str() {
-                Ok(s) => Some(s.to_string()),
-                Err(e) => {
-                    py.allow_threads(|| {
-                        error!("Non-string element encountered in input, ignoring: {e}");
-                    });
-                    None
+    /// batch prediction of each line of a document.
+    ///
+    /// `text` is split on `\n`, so a trailing newline ends with an empty
+    /// line, which has no predictions. The lines are predicted in parallel
+    /// like the texts of `batch`.
+    ///
+    /// Args:
+    ///     text: the document
+    ///     k: predict k labels per line
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     a list with a dict from label to probability for each line.
+    #[pyo3(signature = (text, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn predict_lines(
+        &self,
+        text: &str,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::List(PyList::new(py, text.split('\n').collect::<Vec<_>>()).into());
+        let (predictions, _) = self.predict_rows::<f32>(
+            &texts,
+            0..texts.len(py),
+            &options,
+            None,
+            &Decode::default(),
+            py,
+        )?;
+        self.label_dicts(&predictions, py)
+    }
+
+    /// batch prediction of languages, or any labels, as bare codes.
+    ///
+    /// Labels are returned without the label prefix of the model, such as
+    /// `"en"` for `__label__en` with `lid.176.bin`, stripped in Rust.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: predict k labels per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     a list with a list of `(code, probability)` for each text, of at
+    ///     most k pairs by decreasing probability.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn detect_language(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let (predictions, _) = self.predict_rows::<f32>(
+            &texts,
+            0..texts.len(py),
+            &options,
+            None,
+            &Decode::default(),
+            py,
+        )?;
+        let prefix = self.predictor.model().get_args().label().into_owned();
+        let codes: Vec<&PyString> = self
+            .predictor
+            .reverse_label_dict()
+            .values()
+            .map(|label| PyString::new(py, label.strip_prefix(&prefix).unwrap_or(label)))
+            .collect();
+        let rows = PyList::empty(py);
+        for ((row_labels, row_probs), &count) in predictions
+            .labels
+            .rows()
+            .into_iter()
+            .zip(predictions.probs.rows())
+            .zip(&predictions.counts)
+        {
+            let row = PyList::empty(py);
+            for (&id, &prob) in row_labels.iter().zip(row_probs).take(count as usize) {
+                if let Some(code) = usize::try_from(id).ok().and_then(|id| codes.get(id)) {
+                    row.append((code, prob))?;
                 }
+            }
+            rows.append(row)?;
+        }
+        Ok(rows.to_object(py))
+    }
+
+    /// evaluate predictions against gold labels, without returning them.
+    ///
+    /// A prediction is correct if it is among the gold labels of its text,
+    /// so texts may have several. Texts without gold labels are not counted,
+    /// like lines without labels in `test`.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     gold_labels: a list with the gold labels of each text, each a list
+    ///         of labels or label ids
+    ///     k: predict k labels per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     a dict of `precision` and `recall` at k, `micro_f1` over all
+    ///     predictions and `macro_f1`, the mean F1 of the labels that are
+    ///     predicted or gold.
+    #[pyo3(signature = (texts, gold_labels, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn evaluate<'py>(
+        &self,
+        texts: PyObject,
+        gold_labels: Vec<Vec<&PyAny>>,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python<'py>,
+    ) -> PyResult<&'py PyDict> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let len = texts.len(py);
+        if gold_labels.len() != len {
+            return Err(PyException::new_err(format!(
+                "got {} gold label lists for {len} texts",
+                gold_labels.len()
+            )));
+        }
+        let gold = gold_labels
+            .into_iter()
+            .map(|labels| {
+                labels
+                    .into_iter()
+                    .map(|key| {
+                        self.label_id(key).ok_or_else(|| {
+                            PyException::new_err(format!("unknown label {key} in gold_labels"))
+                        })
+                    })
+                    .collect::<PyResult<Vec<i32>>>()
             })
-    });
-    for text in texts_iter {
-        let send_result = py.allow_threads(|| {
-            debug!("text sent: {:?}", text);
-            text_sender.send(text)
-        });
-        if send_result.is_err() {
-            break;
-        };
+            .collect::<PyResult<Vec<_>>>()?;
+        let meter = py.allow_threads(|| {
+            self.predictor.evaluate(&gold, &options, |text_sender| {
+                Python::with_gil(|py| {
+                    texts
+                        .send(0..len, text_sender, None, &Decode::default(), py)
+                        .map(drop)
+                })
+            })
+        })?;
+        let scores = PyDict::new(py);
+        scores.set_item("precision", meter.precision())?;
+        scores.set_item("recall", meter.recall())?;
+        scores.set_item("micro_f1", meter.micro_f1())?;
+        scores.set_item("macro_f1", meter.macro_f1())?;
+        Ok(scores)
     }
-    drop(text_sender);
-}
 
-type ResultSender = Sender<(usize, (Vec<i16>, Vec<f32>))>;
+    /// count the best predicted label of each text against its gold label.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     gold_labels: the gold label or label id of each text, `None` or
+    ///         labels unknown to the model are counted as unknown
+    ///
+    /// Returns:
+    ///     The counts in np.ndarray(i64) of shape
+    ///     `(len(model) + 1, len(model) + 1)`, whose rows are gold labels and
+    ///     columns predicted labels, and the list of labels of the rows and
+    ///     columns, in label id order. The last row counts unknown gold
+    ///     labels, and the last column texts without prediction, and their
+    ///     label is `None`.
+    fn confusion_matrix(
+        &self,
+        texts: PyObject,
+        gold_labels: Vec<Option<&PyAny>>,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(1, Thresholds::global(-1.0));
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let len = texts.len(py);
+        if gold_labels.len() != len {
+            return Err(PyException::new_err(format!(
+                "got {} gold labels for {len} texts",
+                gold_labels.len()
+            )));
+        }
+        let gold: Vec<i32> = gold_labels
+            .into_iter()
+            .map(|key| key.and_then(|key| self.label_id(key)).unwrap_or(-1))
+            .collect();
+        let matrix = py.allow_threads(|| {
+            self.predictor
+                .confusion_matrix(&gold, &options, |text_sender| {
+                    Python::with_gil(|py| {
+                        texts
+                            .send(0..len, text_sender, None, &Decode::default(), py)
+                            .map(drop)
+                    })
+                })
+        })?;
+        let mut labels: Vec<PyObject> = self
+            .label_strings(py)
+            .into_iter()
+            .map(|label| label.into())
+            .collect();
+        labels.push(py.None());
+        let outputs = [matrix.to_pyarray(py).to_object(py), labels.into_py(py)];
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
 
-#[inline]
-fn predict_test(
-    model: &FastTextPy,
-    text_receiver: Receiver<Option<String>>,
-    result_sender: ResultSender,
-    k: i32,
-    threshold: f32,
-) {
-    text_receiver
-        .iter()
-        .enumerate()
-        .par_bridge()
-        .map(|(i, s)| {
-            let result = if let Some(s) = s {
-                debug!("text received: {:?}", s);
-                match model.model.predict(&s, k, threshold) {
-                    Ok(predictions) => predictions
-                        .into_iter()
-                        .map(|p| (model.label_dict.get(&p.label).unwrap_or(&-1), p.prob))
-                        .unzip(),
-                    Err(e) => {
-                        error!("Error making prediction, ignoring: {e}");
-                        (vec![], vec![])
-                    }
+    /// batch prediction of the probabilities of all labels, like
+    /// scikit-learn's `predict_proba`.
+    ///
+    /// The output takes `4 * len(texts) * len(model)` bytes, e.g. 4 GB for
+    /// a million texts and 1000 labels, or half of it as float16.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     dtype: `"float32"` or `"float16"`, which halves the memory at a
+    ///         precision of about 3 decimal digits
+    ///
+    /// Returns:
+    ///     The probabilities in np.ndarray of `dtype` and shape
+    ///     `(len(texts), len(model))`, whose columns are label ids as in
+    ///     `get_labels`. Missing texts give rows of zeros.
+    #[pyo3(signature = (texts, dtype="float32"))]
+    fn batch_proba(&self, texts: PyObject, dtype: &str, py: Python) -> PyResult<PyObject> {
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        match dtype {
+            "float32" => Ok(self.predict_proba::<f32>(&texts, py)?.to_object(py)),
+            // numpy reads the bits of half-precision floats as float16.
+            "float16" => Ok(self
+                .predict_proba::<u16>(&texts, py)?
+                .call_method1("view", ("float16",))?
+                .to_object(py)),
+            _ => Err(PyException::new_err(format!(
+                "unknown dtype `{dtype}`, expected one of `float32`, `float16`"
+            ))),
+        }
+    }
+
+    /// batch prediction returning only the predictions, in coordinate format.
+    ///
+    /// With a large `k` and a high threshold most cells of `batch` are
+    /// padding, while here memory grows with the number of predictions. The
+    /// result feeds `scipy.sparse.coo_matrix((probs, (rows, labels)),
+    /// shape=(len(texts), len(model)))`.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: output at most k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     The text indices in np.ndarray(i64), label ids in np.ndarray(i16)
+    ///     and probabilities in np.ndarray(f32) of every prediction, sorted by
+    ///     text index, then by descending probability.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn batch_sparse(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let len = texts.len(py);
+        let sparse = py.allow_threads(|| {
+            self.predictor.predict_sparse(&options, |text_sender| {
+                Python::with_gil(|py| {
+                    texts
+                        .send(0..len, text_sender, None, &Decode::default(), py)
+                        .map(drop)
+                })
+            })
+        })?;
+        let outputs = [
+            sparse.rows.to_pyarray(py).to_object(py),
+            self.label_dtype.to_pyarray(&sparse.labels, py),
+            sparse.probs.to_pyarray(py).to_object(py),
+        ];
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
+
+    /// batch prediction written into arrays given by the caller.
+    ///
+    /// The arrays may be `np.memmap`s, so that an output too large for
+    /// memory goes straight to disk. Rows are written whole, padding
+    /// included, so the arrays need not be initialized.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     out_labels: a writeable np.ndarray(i16) or np.ndarray(i32) of shape
+    ///         `(len(texts), k)`
+    ///     out_probs: a writeable np.ndarray(f32) of shape `(len(texts), k)`
+    ///     k: output k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     the number of predictions of each row in np.ndarray(i32).
+    #[pyo3(signature = (texts, out_labels, out_probs, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    #[allow(clippy::too_many_arguments)]
+    fn batch_into(
+        &self,
+        texts: PyObject,
+        out_labels: &PyAny,
+        out_probs: &PyAny,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let out_labels: LabelArray = out_labels.extract().map_err(|_| {
+            PyException::new_err(format!(
+                "out_labels must be a 2-D np.ndarray(i16) or np.ndarray(i32), got {}",
+                describe_array(out_labels)
+            ))
+        })?;
+        let out_probs: &PyArray2<f32> = out_probs.extract().map_err(|_| {
+            PyException::new_err(format!(
+                "out_probs must be a 2-D np.ndarray(f32), got {}",
+                describe_array(out_probs)
+            ))
+        })?;
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let counts = match out_labels {
+            LabelArray::I16(out_labels) => {
+                let num_labels = self.predictor.num_labels();
+                if !LabelDtype::I16.fits(num_labels) {
+                    return Err(PyException::new_err(format!(
+                        "{num_labels} labels do not fit in out_labels of dtype i16"
+                    )));
                 }
-            } else {
-                (vec![], vec![])
-            };
-            if result_sender.send((i, result)).is_err() {
-                None
-            } else {
-                Some(())
+                self.predict_into(&texts, out_labels, out_probs, &options, py)?
             }
-        })
-        .while_some()
-        .for_each(|_| {});
-    drop(result_sender);
+            LabelArray::I32(out_labels) => {
+                self.predict_into(&texts, out_labels, out_probs, &options, py)?
+            }
+        };
+        Ok(counts.to_pyarray(py).to_object(py))
+    }
+
+    /// predict each line of a text file using multithreading.
+    ///
+    /// Lines are read by Rust without creating Python strings. The file is
+    /// memory mapped, and lines are predicted in place rather than copied
+    /// one by one. Files starting with the gzip magic bytes are
+    /// decompressed by Rust while their lines are predicted.
+    ///
+    /// Args:
+    ///     path: a UTF-8 text file with one text per line, ending with `\n`
+    ///         or `\r\n`, possibly gzip-compressed. Lines that are not valid
+    ///         UTF-8 are logged and give empty rows.
+    ///     k: output k predictions per line
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     the same as `batch`, with a row per line.
+    #[pyo3(signature = (path, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn batch_file(
+        &self,
+        path: &str,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let predictions = py.allow_threads(|| self.predictor.predict_file(path, &options))?;
+        let outputs = [
+            self.label_dtype.to_pyarray(&predictions.labels, py),
+            predictions.probs.to_pyarray(py).to_object(py),
+        ];
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
+
+    /// evaluate the model on a labeled file, like `fasttext test`.
+    ///
+    /// Each line holds words and labels, the labels starting with the label
+    /// prefix of the model, e.g. `__label__`. Lines without labels known to
+    /// the model or without words are not counted, and FastTextError is
+    /// raised if no line is.
+    /// Gzipped files are decompressed.
+    ///
+    /// Args:
+    ///     path: the path of the labeled file
+    ///     k: predict k labels per line
+    ///     threshold: ignore predictions with probability below it
+    ///
+    /// Returns:
+    ///     (number of examples, precision at k, recall at k)
+    #[pyo3(signature = (path, k=1, threshold=0.0))]
+    fn test(&self, path: &str, k: i32, threshold: f32, py: Python) -> PyResult<(usize, f64, f64)> {
+        let options = PredictOptions::new(k, Thresholds::global(threshold));
+        let meter = py.allow_threads(|| self.predictor.test_file(path, &options))?;
+        Ok((meter.examples, meter.precision(), meter.recall()))
+    }
+
+    /// batch prediction in chunks, so that the output is never fully in memory.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: output k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///     chunk_size: the number of texts predicted at a time
+    ///     callback: called as `callback(start, labels, probs)` for each chunk,
+    ///         where `start` is the index of the chunk's first text in `texts`,
+    ///         and `labels`, `probs` are as returned by `batch`.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0, chunk_size=65536, callback=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn batch_chunked(
+        &self,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        chunk_size: usize,
+        callback: Option<PyObject>,
+        py: Python,
+    ) -> PyResult<()> {
+        let callback =
+            callback.ok_or_else(|| PyException::new_err("batch_chunked requires a callback"))?;
+        if chunk_size == 0 {
+            return Err(PyException::new_err("chunk_size must be positive"));
+        }
+        let options = PredictOptions::new(k, self.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let counts = texts.len(py);
+        for start in (0..counts).step_by(chunk_size) {
+            let end = counts.min(start + chunk_size);
+            let (predictions, _) = self.predict_rows::<f32>(
+                &texts,
+                start..end,
+                &options,
+                None,
+                &Decode::default(),
+                py,
+            )?;
+            callback.call1(
+                py,
+                (
+                    start,
+                    self.label_dtype.to_pyarray(&predictions.labels, py),
+                    predictions.probs.to_pyarray(py),
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// iterate over predictions of texts.
+    ///
+    /// Texts are predicted in background threads, which stop when the
+    /// iterator is dropped. With `ordered`, rows completed ahead of the next
+    /// one in input order are held back, and texts are sent to the threads
+    /// at most `window` rows ahead of the last row yielded, so memory is
+    /// bounded by `window` rows rather than `len(texts)`. A row delayed
+    /// beyond the window blocks the threads until it completes.
+    ///
+    /// Without `ordered`, each row is yielded as soon as it completes, in no
+    /// particular order, and the pipeline runs at most a fixed number of
+    /// rows ahead of the consumer.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: output k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///     window: the number of rows predicted ahead of the consumer, if
+    ///         `ordered`
+    ///     ordered: yield rows in input order rather than completion order
+    ///
+    /// Returns:
+    ///     an iterator of `(index, labels, probs)`, where `labels` and `probs`
+    ///     are np.ndarray(i16) and np.ndarray(f32) of the predictions of
+    ///     `texts[index]`, without padding.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0, window=65536, ordered=true))]
+    #[allow(clippy::too_many_arguments)]
+    fn batch_iter(
+        slf: PyRef<'_, Self>,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        window: usize,
+        ordered: bool,
+        py: Python,
+    ) -> PyResult<BatchIter> {
+        if window == 0 {
+            return Err(PyException::new_err("window must be positive"));
+        }
+        slf.predictor.check_supervised()?;
+        let options = PredictOptions::new(k, slf.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let window = ordered.then_some(window);
+        Ok(BatchIter::stream(slf.into(), texts, options, window, py))
+    }
+
+    /// iterate over predictions of texts as they complete.
+    ///
+    /// All texts are predicted in background threads, and `__next__` waits
+    /// for the next row, so early rows can be handled while later ones are
+    /// predicted. The pipeline runs at most a fixed number of rows ahead of
+    /// the consumer, and stops when the iterator is dropped, e.g. on `break`.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: output k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///     window: the most rows held back if `ordered`, as in `batch_iter`
+    ///     ordered: yield rows in input order, holding back rows completed
+    ///         early, rather than in completion order, which is cheaper
+    ///
+    /// Returns:
+    ///     the same iterator as `batch_iter`.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0, window=65536, ordered=true))]
+    #[allow(clippy::too_many_arguments)]
+    fn batch_stream(
+        slf: PyRef<'_, Self>,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        window: usize,
+        ordered: bool,
+        py: Python,
+    ) -> PyResult<BatchIter> {
+        Self::batch_iter(
+            slf,
+            texts,
+            k,
+            threshold,
+            default_threshold,
+            window,
+            ordered,
+            py,
+        )
+    }
+
+    /// predict texts in background threads, without blocking the event loop.
+    ///
+    /// Must be called from a coroutine, e.g. `await model.batch_async(texts)`.
+    /// Cancelling the awaiting task stops the prediction.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     k: output k predictions per text
+    ///     threshold: same as `batch`
+    ///     default_threshold: same as `batch`
+    ///
+    /// Returns:
+    ///     an asyncio future of the `(labels, probs)` of `batch`.
+    #[pyo3(signature = (texts, k=1, threshold=ThresholdArg::Global(-1.0), default_threshold=-1.0))]
+    fn batch_async(
+        slf: PyRef<'_, Self>,
+        texts: PyObject,
+        k: i32,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let options = PredictOptions::new(k, slf.thresholds(threshold, default_threshold, py)?);
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        awaitable::batch_async(slf.into(), texts, options, py)
+    }
+
+    /// tokenize texts with the fasttext tokenizer using multithreading.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///
+    /// Returns:
+    ///     a list of token lists in input order. Newlines produce the `</s>`
+    ///     token, and invalid elements give empty lists.
+    fn tokenize(&self, texts: PyObject, py: Python) -> PyResult<PyObject> {
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let mut tokens = vec![Vec::new(); texts.len(py)];
+        run_pipeline(
+            py,
+            |text_sender, py| {
+                texts
+                    .send(0..texts.len(py), text_sender, None, &Decode::default(), py)
+                    .map(drop)
+            },
+            |_, text| match text {
+                Some(text) => self.predictor.model().tokenize(&text).unwrap_or_else(|e| {
+                    error!("Error tokenizing text, ignoring: {e}");
+                    vec![]
+                }),
+                None => vec![],
+            },
+            |i, text_tokens| tokens[i] = text_tokens,
+        )?;
+        Ok(tokens.into_py(py))
+    }
+
+    /// count the tokens of each text missing from the vocabulary of the
+    /// model, using multithreading.
+    ///
+    /// Texts are tokenized like `tokenize`, and tokens without a word id are
+    /// out of vocabulary. Their vectors are built from subwords only, if the
+    /// model has any.
+    ///
+    /// Args:
+    ///     texts: same as `batch`
+    ///     top_n: the number of most frequent out-of-vocabulary tokens to
+    ///         report
+    ///
+    /// Returns:
+    ///     `(oov_counts, token_counts, stats)`: the numbers of
+    ///     out-of-vocabulary tokens and of tokens of each text as
+    ///     np.ndarray(i32), zero for invalid elements, and a dict with the
+    ///     totals `oov_tokens` and `tokens`, their ratio `oov_rate`, and
+    ///     `top_oov`, a list of `(token, count)` by decreasing count.
+    #[pyo3(signature = (texts, top_n=10))]
+    fn oov_stats(&self, texts: PyObject, top_n: usize, py: Python) -> PyResult<PyObject> {
+        let texts = Texts::from_py(texts.as_ref(py))?;
+        let mut oov_counts = Array1::<i32>::zeros(texts.len(py));
+        let mut token_counts = Array1::<i32>::zeros(texts.len(py));
+        let mut oov_tokens = HashMap::<String, u64>::new();
+        let model = self.predictor.model();
+        run_pipeline(
+            py,
+            |text_sender, py| {
+                texts
+                    .send(0..texts.len(py), text_sender, None, &Decode::default(), py)
+                    .map(drop)
+            },
+            |_, text| {
+                let tokens = match text.map(|text| model.tokenize(&text)) {
+                    Some(Ok(tokens)) => tokens,
+                    Some(Err(e)) => {
+                        error!("Error tokenizing text, ignoring: {e}");
+                        vec![]
+                    }
+                    None => vec![],
+                };
+                let count = tokens.len();
+                let oov: Vec<String> = tokens
+                    .into_iter()
+                    .filter(|token| model.get_word_id(token).map_or(true, |id| id < 0))
+                    .collect();
+                (count, oov)
+            },
+            |i, (count, oov)| {
+                token_counts[i] = count as i32;
+                oov_counts[i] = oov.len() as i32;
+                for token in oov {
+                    *oov_tokens.entry(token).or_default() += 1;
+                }
+            },
+        )?;
+        let total_oov: u64 = oov_tokens.values().sum();
+        let total: i64 = token_counts.iter().map(|&n| i64::from(n)).sum();
+        let mut top_oov: Vec<(String, u64)> = oov_tokens.into_iter().collect();
+        top_oov.sort_unstable_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+        top_oov.truncate(top_n);
+        let stats = PyDict::new(py);
+        stats.set_item("oov_tokens", total_oov)?;
+        stats.set_item("tokens", total)?;
+        stats.set_item("oov_rate", total_oov as f64 / total.max(1) as f64)?;
+        stats.set_item("top_oov", top_oov)?;
+        let outputs = [
+            oov_counts.into_pyarray(py).to_object(py),
+            token_counts.into_pyarray(py).to_object(py),
+            stats.to_object(py),
+        ];
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
+
+    /// tokenize a single text with the fasttext tokenizer.
+    ///
+    /// Args:
+    ///     text: the text to tokenize
+    ///
+    /// Returns:
+    ///     the list of tokens.
+    fn tokenize_one(&self, text: &str) -> PyResult<Vec<String>> {
+        self.predictor
+            .model()
+            .tokenize(text)
+            .map_err(PyException::new_err)
+    }
+
+    /// get the row of a subword, e.g. the character n-gram `<wh`, in the
+    /// input matrix.
+    ///
+    /// Subwords are hashed into the model's `bucket` rows following the
+    /// `nwords` rows of words, so the id is `nwords + hash(subword) % bucket`,
+    /// and unrelated subwords may collide in the same row.
+    ///
+    /// Args:
+    ///     subword: the subword
+    ///
+    /// Returns:
+    ///     the row index in the input matrix.
+    fn get_subword_id(&self, subword: &str) -> PyResult<i32> {
+        let model = self.predictor.model();
+        if model.get_args().bucket() == 0 {
+            return Err(PyException::new_err(
+                "the model has no subwords, it was trained with bucket=0",
+            ));
+        }
+        let id = model
+            .get_subword_id(subword)
+            .map_err(PyException::new_err)?;
+        Ok(id as i32)
+    }
+
+    /// get the word vector of a word.
+    ///
+    /// Args:
+    ///     word: the word, out-of-vocabulary words are built from subwords
+    ///     lowercase: lowercase the word first, same as in `batch`
+    ///
+    /// Returns:
+    ///     the word vector in np.ndarray(f32) format.
+    #[pyo3(signature = (word, lowercase=false))]
+    fn get_word_vector(&self, word: &str, lowercase: bool, py: Python) -> PyResult<PyObject> {
+        let vector = self.vector(word, VectorMode::Word, lowercase)?;
+        Ok(vector.to_pyarray(py).to_object(py))
+    }
+
+    /// get the sentence vector of a text.
+    ///
+    /// Args:
+    ///     text: the text to embed
+    ///     lowercase: lowercase the text first, same as in `batch`
+    ///
+    /// Returns:
+    ///     the sentence vector in np.ndarray(f32) format.
+    #[pyo3(signature = (text, lowercase=false))]
+    fn get_sentence_vector(&self, text: &str, lowercase: bool, py: Python) -> PyResult<PyObject> {
+        let vector = self.vector(text, VectorMode::Sentence, lowercase)?;
+        Ok(vector.to_pyarray(py).to_object(py))
+    }
+
+    /// get the average of the word vectors of tokens, weighted by `weights`,
+    /// e.g. tf-idf scores, rather than uniformly as in `get_sentence_vector`.
+    ///
+    /// Args:
+    ///     tokens: the words to embed, out-of-vocabulary words are built from
+    ///         subwords
+    ///     weights: the weight of each token
+    ///
+    /// Returns:
+    ///     the weighted average in np.ndarray(f32) format, zeros if the
+    ///     weights sum to zero.
+    fn get_weighted_vector(
+        &self,
+        tokens: Vec<&str>,
+        weights: Vec<f32>,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        if tokens.len() != weights.len() {
+            return Err(PyException::new_err(format!(
+                "got {} weights for {} tokens",
+                weights.len(),
+                tokens.len()
+            )));
+        }
+        let dim = self.predictor.model().get_dimension() as usize;
+        let mut sum = Array1::<f32>::zeros(dim);
+        for (token, &weight) in tokens.iter().zip(&weights) {
+            let vector = self.vector(token, VectorMode::Word, false)?;
+            sum.scaled_add(weight, &Array1::from_vec(vector));
+        }
+        let total: f32 = weights.iter().sum();
+        if total != 0.0 {
+            sum /= total;
+        }
+        Ok(sum.to_pyarray(py).to_object(py))
+    }
+
+    /// cosine similarity between the sentence vectors of two texts.
+    ///
+    /// Args:
+    ///     a: the first text
+    ///     b: the second text
+    ///     lowercase: lowercase both texts first, same as in `batch`
+    ///
+    /// Returns:
+    ///     the cosine similarity, or `0.0` if either vector is zero.
+    #[pyo3(signature = (a, b, lowercase=false))]
+    fn text_similarity(&self, a: &str, b: &str, lowercase: bool) -> PyResult<f32> {
+        Ok(cosine_similarity(
+            &self.vector(a, VectorMode::Sentence, lowercase)?,
+            &self.vector(b, VectorMode::Sentence, lowercase)?,
+        )
+        .unwrap_or(0.0))
+    }
+
+    /// batch cosine similarity of text pairs using multithreading.
+    ///
+    /// Args:
+    ///     pairs: a list of `(str, str)` tuples
+    ///     lowercase: lowercase the texts in the worker threads, same as in
+    ///         `batch`
+    ///
+    /// Returns:
+    ///     the cosine similarities in np.ndarray(f32) format. Zero vectors and
+    ///     invalid pairs have similarity `0.0`.
+    #[pyo3(signature = (pairs, lowercase=false))]
+    fn batch_similarity(&self, pairs: PyObject, lowercase: bool, py: Python) -> PyResult<PyObject> {
+        let counts = pairs.as_ref(py).downcast::<PyList>()?.len();
+        self.pair_similarities(
+            counts,
+            VectorMode::Sentence,
+            lowercase,
+            py,
+            |pair_sender, py| {
+                let pairs = pairs.as_ref(py).downcast::<PyList>()?;
+                let pairs = pairs.iter().map(|pair| {
+                    pair.downcast::<PyTuple>()
+                        .ok()
+                        .and_then(|pair| pair.extract().ok())
+                });
+                send_pairs(pairs, pair_sender, py)
+            },
+        )
+    }
+
+    /// cosine similarity between two words or texts, or paired lists of them.
+    ///
+    /// Given lists, the similarities are computed using multithreading,
+    /// which avoids materializing the vectors of both sides in Python.
+    ///
+    /// Args:
+    ///     a: a string, or a list of strings
+    ///     b: a string, or a list of strings of the same length
+    ///     mode: `"sentence"` compares sentence vectors, `"word"` compares
+    ///         word vectors.
+    ///     lowercase: lowercase all strings first, in the worker threads for
+    ///         lists, same as in `batch`
+    ///
+    /// Returns:
+    ///     the cosine similarity as a float for two strings, or the cosine
+    ///     similarity of `a[i]` and `b[i]` in np.ndarray(f32) format for two
+    ///     lists. Zero vectors, e.g. empty texts, have similarity `0.0`.
+    #[pyo3(signature = (a, b, mode="sentence", lowercase=false))]
+    fn similarity(
+        &self,
+        a: PyObject,
+        b: PyObject,
+        mode: &str,
+        lowercase: bool,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let mode = VectorMode::parse(mode)?;
+        if let (Ok(a), Ok(b)) = (a.extract::<&str>(py), b.extract::<&str>(py)) {
+            let (a, b) = (
+                self.vector(a, mode, lowercase)?,
+                self.vector(b, mode, lowercase)?,
+            );
+            let similarity = cosine_similarity(&a, &b).unwrap_or_else(|| {
+                warn!("zero vector encountered in similarity, returning 0.0");
+                0.0
+            });
+            return Ok(similarity.into_py(py));
+        }
+        let counts = a.as_ref(py).downcast::<PyList>()?.len();
+        let counts_b = b.as_ref(py).downcast::<PyList>()?.len();
+        if counts != counts_b {
+            return Err(PyException::new_err(format!(
+                "a and b have different lengths, {counts} and {counts_b}"
+            )));
+        }
+        self.pair_similarities(counts, mode, lowercase, py, |pair_sender, py| {
+            let a = a.as_ref(py).downcast::<PyList>()?;
+            let b = b.as_ref(py).downcast::<PyList>()?;
+            let pairs = a
+                .iter()
+                .zip(b.iter())
+                .map(|(a, b)| Some((a.extract().ok()?, b.extract().ok()?)));
+            send_pairs(pairs, pair_sender, py)
+        })
+    }
+
+    /// get the output layer vector of a label.
+    ///
+    /// The vector is the row of `get_output_matrix`, read and cached on the
+    /// first call. It is not supported for quantized models.
+    ///
+    /// Args:
+    ///     label_id: the id of a label
+    ///
+    /// Returns:
+    ///     the output vector in np.ndarray(f32) format.
+    fn get_output_vector(&self, label_id: i32, py: Python) -> PyResult<PyObject> {
+        let id = label_id;
+        if !self.predictor.reverse_label_dict().contains_key(&id) {
+            return Err(PyException::new_err(format!("unknown label id {label_id}")));
+        }
+        if self.predictor.model().is_quant() {
+            return Err(ModelTypeError::new_err(
+                "output vectors are not supported for quantized models",
+            ));
+        }
+        if self.predictor.model_type() != "supervised" {
+            return Err(ModelTypeError::new_err(
+                "output vectors are only available for supervised models",
+            ));
+        }
+        let matrix = self.matrix(Matrix::Output, py)?;
+        Ok(matrix.row(id as usize).to_pyarray(py).to_object(py))
+    }
+
+    /// get a copy of the input matrix, whose rows are the embeddings of the
+    /// words, then of the subwords and word n-grams hashed into `bucket`
+    /// rows, as numbered by `get_subword_id`.
+    ///
+    /// The matrix is read from the model file and cached on the first call,
+    /// and is not available for quantized models.
+    ///
+    /// Returns:
+    ///     the matrix in np.ndarray(f32) format, of shape
+    ///     `(nwords + bucket, dim)`.
+    fn get_input_matrix(&self, py: Python) -> PyResult<PyObject> {
+        let matrix = self.matrix(Matrix::Input, py)?;
+        Ok(matrix.to_pyarray(py).to_object(py))
+    }
+
+    /// get a copy of the output matrix, whose rows are the vectors of
+    /// `get_output_vector`, by label id for supervised models.
+    ///
+    /// The matrix is read from the model file and cached on the first call,
+    /// and is not available for quantized models.
+    ///
+    /// Returns:
+    ///     the matrix in np.ndarray(f32) format, of shape `(nlabels, dim)`
+    ///     for supervised models and `(nwords, dim)` for the others.
+    fn get_output_matrix(&self, py: Python) -> PyResult<PyObject> {
+        let matrix = self.matrix(Matrix::Output, py)?;
+        Ok(matrix.to_pyarray(py).to_object(py))
+    }
+
+    /// find the k nearest words to a vector by cosine similarity.
+    ///
+    /// The normalized vectors of the whole vocabulary are computed
+    /// and cached on the first call.
+    ///
+    /// Args:
+    ///     vector: a query vector of the model dimension
+    ///     k: the number of neighbors
+    ///
+    /// Returns:
+    ///     a list of `(word, similarity)` tuples, most similar first.
+    #[pyo3(signature = (vector, k=10))]
+    fn nearest_neighbors_by_vector(
+        &self,
+        vector: PyReadonlyArray1<f32>,
+        k: i32,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let dim = self.predictor.model().get_dimension() as usize;
+        if vector.len() != dim {
+            return Err(PyException::new_err(format!(
+                "vector length {} does not match model dimension {dim}",
+                vector.len()
+            )));
+        }
+        let vector = vector.as_array().to_vec();
+        let pool = thread_pool()?;
+        let neighbors = py.allow_threads(|| {
+            self.word_vectors().map(|word_vectors| {
+                pool.install(|| word_vectors.nearest(&vector, max(k, 0) as usize))
+            })
+        })?;
+        Ok(neighbors.into_py(py))
+    }
+
+    /// find the k nearest words to each word by cosine similarity, using
+    /// multithreading across words.
+    ///
+    /// Like `get_nearest_neighbors` of fasttext, a word is not its own
+    /// neighbor. The normalized vectors of the whole vocabulary are computed
+    /// and cached on the first call.
+    ///
+    /// Args:
+    ///     words: the query words, same as `texts` of `batch`
+    ///     k: the number of neighbors of each word
+    ///
+    /// Returns:
+    ///     a list in input order of lists of `(word, similarity)` tuples,
+    ///     most similar first. Invalid elements give empty lists.
+    #[pyo3(signature = (words, k=10))]
+    fn batch_nearest_neighbors(&self, words: PyObject, k: i32, py: Python) -> PyResult<PyObject> {
+        let words = Texts::from_py(words.as_ref(py))?;
+        let word_vectors = py.allow_threads(|| self.word_vectors())?;
+        let k = max(k, 0) as usize;
+        let mut neighbors = vec![Vec::new(); words.len(py)];
+        run_pipeline(
+            py,
+            |word_sender, py| {
+                words
+                    .send(0..words.len(py), word_sender, None, &Decode::default(), py)
+                    .map(drop)
+            },
+            |_, word| {
+                let word = word?;
+                match self.predictor.word_vector(&word) {
+                    Ok(vector) => Some(
+                        word_vectors
+                            .nearest(&vector, k + 1)
+                            .into_iter()
+                            .filter(|(neighbor, _)| *neighbor != word)
+                            .take(k)
+                            .collect(),
+                    ),
+                    Err(e) => {
+                        error!("Error getting word vector, ignoring: {e}");
+                        None
+                    }
+                }
+            },
+            |i, word_neighbors| {
+                if let Some(word_neighbors) = word_neighbors {
+                    neighbors[i] = word_neighbors;
+                }
+            },
+        )?;
+        Ok(neighbors.into_py(py))
+    }
+
+    /// the architecture of the model: `"supervised"`, `"cbow"` or
+    /// `"skipgram"`. Only supervised models predict labels, and the batch
+    /// methods raise `ModelTypeError` on the others.
+    #[getter]
+    fn model_type(&self) -> &'static str {
+        self.predictor.model_type()
+    }
+
+    /// the dtype of label ids, `"i16"` or `"i32"`, as given to `load_model`.
+    #[getter]
+    fn label_dtype(&self) -> &'static str {
+        self.label_dtype.as_str()
+    }
+
+    /// the dimension of the word vectors.
+    fn get_dimension(&self) -> isize {
+        self.predictor.model().get_dimension()
+    }
+
+    /// get the mapping from label index to label.
+    ///
+    /// Args:
+    ///     include_freq: also return the number of occurrences of each label
+    ///         in the training data, like `get_labels` of `fasttext`
+    ///
+    /// Returns:
+    ///     A dictionary mapping from integer to labels, or with
+    ///     `include_freq`, `(labels, freqs)`: the labels as a list and their
+    ///     frequencies as an int64 array, both indexed by label id.
+    #[pyo3(signature = (include_freq=false))]
+    fn get_labels(&self, include_freq: bool, py: Python) -> PyObject {
+        if include_freq {
+            let freqs = PyArray1::from_slice(py, self.predictor.label_counts());
+            (self.get_labels_list(py), freqs).to_object(py)
+        } else {
+            self.predictor
+                .reverse_label_dict()
+                .clone()
+                .into_py_dict(py)
+                .to_object(py)
+        }
+    }
+
+    /// get the labels as a list indexed by label id.
+    ///
+    /// Returns:
+    ///     A list where `labels[i]` is the label of id `i`, e.g. for building
+    ///     a numpy lookup `np.array(labels)[ids]`.
+    fn get_labels_list<'a>(&self, py: Python<'a>) -> &'a PyList {
+        PyList::new(py, self.predictor.reverse_label_dict().values())
+    }
+
+    /// write the mapping from label to label id as a JSON object, to number
+    /// the labels of another model the same way with `load_label_map` and
+    /// `load_model(..., label_to_int=...)`.
+    ///
+    /// Args:
+    ///     path: the path of the JSON file
+    fn dump_label_map(&self, path: &str) -> PyResult<()> {
+        let file = File::create(path)
+            .map_err(|e| PyException::new_err(format!("failed to write {path}, {e}")))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self.predictor.label_dict())
+            .map_err(|e| PyException::new_err(format!("failed to write {path}, {e}")))?;
+        writer
+            .flush()
+            .map_err(|e| PyException::new_err(format!("failed to write {path}, {e}")))
+    }
+
+    /// get the number of occurrences of each label in the training data.
+    ///
+    /// Returns:
+    ///     A dictionary mapping from label id to its frequency.
+    fn label_counts<'a>(&self, py: Python<'a>) -> &'a PyDict {
+        self.predictor
+            .label_counts()
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (i as i32, count))
+            .into_py_dict(py)
+    }
+
+    /// get the number of occurrences of each label in the training data.
+    ///
+    /// Returns:
+    ///     A dictionary mapping from label to its frequency, the same counts
+    ///     as `label_counts` keyed by label rather than label id.
+    fn get_label_counts<'a>(&self, py: Python<'a>) -> &'a PyDict {
+        self.predictor
+            .reverse_label_dict()
+            .values()
+            .zip(self.predictor.label_counts())
+            .into_py_dict(py)
+    }
+
+    /// get the number of occurrences of each word in the training data.
+    ///
+    /// Only the words kept in the vocabulary are counted, which excludes
+    /// words below the `minCount` of training and pruned words of quantized
+    /// models.
+    ///
+    /// Returns:
+    ///     A dictionary mapping from word to its frequency.
+    fn get_word_counts<'a>(&self, py: Python<'a>) -> PyResult<&'a PyDict> {
+        let (words, counts) = self
+            .predictor
+            .model()
+            .get_vocab()
+            .map_err(PyException::new_err)?;
+        Ok(words.into_iter().zip(counts).into_py_dict(py))
+    }
+
+    /// get a label by the id
+    ///
+    /// Args:
+    ///     id: the id of a label
+    ///
+    /// Returns:
+    ///     the label corresponding to the given id.
+    fn get_label_by_id(&self, id: i32) -> Option<&String> {
+        self.predictor.reverse_label_dict().get(&id)
+    }
+
+    /// map an array of label ids, such as the labels of `batch`, to labels.
+    ///
+    /// Each label is converted to a `str` once and shared by all its cells.
+    /// Note that padding cells of `batch` hold id `0`, so use the counts of
+    /// `return_counts` to drop them.
+    ///
+    /// Args:
+    ///     ids: a 2-D np.ndarray(i16) or np.ndarray(i32) of label ids
+    ///     placeholder: the value of `-1` and any other unknown id
+    ///
+    /// Returns:
+    ///     A list of lists of labels, of the same shape as `ids`.
+    #[pyo3(signature = (ids, placeholder=""))]
+    fn ids_to_labels(&self, ids: LabelArray, placeholder: &str, py: Python) -> PyResult<PyObject> {
+        let labels = self.label_strings(py);
+        let placeholder = PyString::new(py, placeholder);
+        let label = |id: i32| {
+            usize::try_from(id)
+                .ok()
+                .and_then(|id| labels.get(id))
+                .copied()
+                .unwrap_or(placeholder)
+        };
+        let rows: Vec<&PyList> = match ids {
+            LabelArray::I16(ids) => {
+                let ids = ids.readonly();
+                let ids = ids.as_array();
+                ids.rows()
+                    .into_iter()
+                    .map(|row| PyList::new(py, row.iter().map(|&id| label(id.into()))))
+                    .collect()
+            }
+            LabelArray::I32(ids) => {
+                let ids = ids.readonly();
+                let ids = ids.as_array();
+                ids.rows()
+                    .into_iter()
+                    .map(|row| PyList::new(py, row.iter().map(|&id| label(id))))
+                    .collect()
+            }
+        };
+        Ok(PyList::new(py, rows).to_object(py))
+    }
+
+    /// write the word vectors of the vocabulary to a `.vec` text file.
+    ///
+    /// The file starts with a `num_words dim` line, followed by a line of
+    /// each word and its space-separated vector, as read by most tools that
+    /// cannot read `.bin` models. Vectors are computed using multithreading
+    /// without the GIL.
+    ///
+    /// Args:
+    ///     path: the file to write, replaced if it exists
+    fn export_vectors(&self, path: &str, py: Python) -> PyResult<()> {
+        Ok(py.allow_threads(|| self.predictor.export_vectors(path))?)
+    }
+
+    /// run `n` predictions covering the vocabulary, without the GIL, so that
+    /// the model is in memory before the first request.
+    ///
+    /// It changes no result, only the latency of the first predictions after
+    /// loading, which otherwise fault in the pages of the model.
+    ///
+    /// Args:
+    ///     n: the number of warmup texts, spread over the worker threads
+    #[pyo3(signature = (n=1000))]
+    fn warmup(&self, n: usize, py: Python) -> PyResult<()> {
+        Ok(py.allow_threads(|| self.predictor.warmup(n))?)
+    }
+
+    /// replace the model with the one at `path`, keeping this object.
+    ///
+    /// The new model is loaded without holding the GIL, then swapped in with
+    /// its labels, and the cached word vectors and matrices are dropped. On
+    /// failure the current model is left intact.
+    ///
+    /// The swap raises if the model is in use by another thread, such as a
+    /// `batch` in flight, which is never affected. Label ids follow the new
+    /// model, so rebuild any `Ensemble` containing it, and do not reload in
+    /// the middle of a `batch_iter`. Models loaded with `mmap=True` map the
+    /// new model too.
+    ///
+    /// Args:
+    ///     path: file path of the new model
+    fn reload(slf: &PyCell<Self>, path: &str, py: Python) -> PyResult<()> {
+        let mapped = slf.borrow().predictor.is_mapped();
+        let predictor = py.allow_threads(|| match mapped {
+            true => ParallelPredictor::load_mapped(path),
+            false => ParallelPredictor::load(path),
+        })?;
+        let mut model = slf
+            .try_borrow_mut()
+            .map_err(|_| PyException::new_err("cannot reload a model in use by another thread"))?;
+        check_label_dtype(&predictor, model.label_dtype)?;
+        *model = FastTextPy::new(
+            predictor,
+            model.label_dtype,
+            ModelSource::file(Path::new(path)),
+        );
+        Ok(())
+    }
+
+    /// reduce the dimension of the model to `target_dim` in place, like
+    /// `fasttext.util.reduce_model`.
+    ///
+    /// The input and output matrices are projected on the `target_dim`
+    /// principal components of the input matrix, computed in Rust, so
+    /// predictions mostly agree with those before the reduction. The model
+    /// is then swapped in like `reload`, keeping its labels.
+    ///
+    /// Args:
+    ///     target_dim: the new dimension, at most `get_dimension()`
+    ///
+    /// Returns:
+    ///     this model
+    fn reduce_model(slf: &PyCell<Self>, target_dim: usize, py: Python) -> PyResult<Py<Self>> {
+        let mut model = slf
+            .try_borrow_mut()
+            .map_err(|_| PyException::new_err("cannot reduce a model in use by another thread"))?;
+        if model.predictor.model().is_quant() {
+            return Err(ModelTypeError::new_err(
+                "reduce_model is unavailable for quantized models",
+            ));
+        }
+        let label_ids = model.predictor.label_dict().clone();
+        let source = &model.source;
+        let reduced = model_file::temp_path();
+        let predictor = py.allow_threads(|| {
+            let predictor = source
+                .path()
+                .and_then(|path| reduce::reduce_model(path, &reduced, target_dim))
+                .and_then(|_| {
+                    ParallelPredictor::load(&reduced.to_string_lossy())
+                        .and_then(|predictor| predictor.with_label_ids(&label_ids))
+                        .map_err(|e| e.message)
+                });
+            if predictor.is_err() {
+                let _ = std::fs::remove_file(&reduced);
+            }
+            predictor.map_err(PyException::new_err)
+        })?;
+        *model = FastTextPy::new(
+            predictor,
+            model.label_dtype,
+            ModelSource::temporary(reduced),
+        );
+        Ok(slf.into())
+    }
+
+    /// continue training on `input_path`, replacing the model with the
+    /// result.
+    ///
+    /// fasttext does not support online updates: this trains a new model
+    /// with the same hyperparameters, whose word embeddings start from the
+    /// word vectors of the current one, like `-pretrainedVectors`. Words of
+    /// both vocabularies are kept, but subword and word n-gram rows and the
+    /// output layer are trained from scratch, so the result depends mostly
+    /// on the new data, and labels not in `input_path` are dropped.
+    ///
+    /// Training runs without the GIL on a temporary copy of the vectors,
+    /// then the model is swapped in like `reload`, so label ids may change.
+    ///
+    /// Args:
+    ///     input_path: training file in the fasttext format, one labeled
+    ///         text per line
+    ///     epoch: number of epochs
+    ///     lr: learning rate
+    #[pyo3(signature = (input_path, epoch=5, lr=0.1))]
+    fn train_continue(
+        slf: &PyCell<Self>,
+        input_path: &str,
+        epoch: i32,
+        lr: f64,
+        py: Python,
+    ) -> PyResult<()> {
+        if !Path::new(input_path).is_file() {
+            return Err(FastTextError::new_err(format!(
+                "training file {input_path} does not exist"
+            )));
+        }
+        let args = TrainArgs {
+            input: input_path.to_string(),
+            epoch,
+            lr,
+        };
+        let (predictor, source) = {
+            let model = slf.borrow();
+            let model: &Self = &model;
+            py.allow_threads(|| {
+                let mut predictor = model.predictor.train_continue(&args)?;
+                let source =
+                    ModelSource::saved(predictor.model_mut()?).map_err(FastTextError::new_err)?;
+                Ok::<_, PyErr>((predictor, source))
+            })?
+        };
+        let mut model = slf
+            .try_borrow_mut()
+            .map_err(|_| PyException::new_err("cannot replace a model in use by another thread"))?;
+        check_label_dtype(&predictor, model.label_dtype)?;
+        *model = FastTextPy::new(predictor, model.label_dtype, source);
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "FastText(labels={}, dim={}, quantized={})",
+            self.predictor.label_dict().len(),
+            self.predictor.model().get_dimension(),
+            self.predictor.model().is_quant()
+        )
+    }
+
+    /// the number of labels.
+    fn __len__(&self) -> usize {
+        self.predictor.label_dict().len()
+    }
+}
+
+impl FastTextPy {
+    fn new(predictor: ParallelPredictor, label_dtype: LabelDtype, source: ModelSource) -> Self {
+        FastTextPy {
+            predictor,
+            label_dtype,
+            word_vectors: OnceLock::new(),
+            source,
+            input_matrix: OnceLock::new(),
+            output_matrix: OnceLock::new(),
+        }
+    }
+
+    /// resolve a `threshold` argument against the labels of the model.
+    fn thresholds(
+        &self,
+        threshold: ThresholdArg,
+        default_threshold: f32,
+        py: Python,
+    ) -> PyResult<Thresholds> {
+        let dict = match threshold {
+            ThresholdArg::Global(threshold) => return Ok(Thresholds::global(threshold)),
+            ThresholdArg::PerLabel(dict) => dict,
+        };
+        let mut labels = BTreeMap::new();
+        for (key, value) in dict.as_ref(py).iter() {
+            let id = self
+                .label_id(key)
+                .ok_or_else(|| PyException::new_err(format!("unknown label {key} in threshold")))?;
+            let value = value.extract::<f32>().map_err(|_| {
+                PyException::new_err(format!(
+                    "threshold of label {key} must be a float, got {value}"
+                ))
+            })?;
+            labels.insert(id, value);
+        }
+        Ok(Thresholds {
+            default: default_threshold,
+            labels,
+        })
+    }
+
+    /// the id of a label given by name or id, if the model has it.
+    fn label_id(&self, key: &PyAny) -> Option<i32> {
+        if let Ok(id) = key.extract::<i32>() {
+            self.predictor
+                .reverse_label_dict()
+                .contains_key(&id)
+                .then_some(id)
+        } else if let Ok(label) = key.extract::<&str>() {
+            self.predictor.label_dict().get(label).copied()
+        } else {
+            None
+        }
+    }
+
+    /// validate label ids.
+    fn label_set(&self, labels: Vec<i32>) -> PyResult<BTreeSet<i32>> {
+        labels
+            .into_iter()
+            .map(|id| {
+                if self.predictor.reverse_label_dict().contains_key(&id) {
+                    Ok(id)
+                } else {
+                    Err(PyException::new_err(format!("unknown label id {id}")))
+                }
+            })
+            .collect()
+    }
+
+    /// cosine similarities of the vectors of `counts` pairs.
+    fn pair_similarities<S>(
+        &self,
+        counts: usize,
+        mode: VectorMode,
+        lowercase: bool,
+        py: Python,
+        send: S,
+    ) -> PyResult<PyObject>
+    where
+        S: FnOnce(ChunkSender<Option<(String, String)>>, Python) -> PyResult<()> + Send,
+    {
+        let mut similarities = Array1::<f32>::zeros(counts);
+        run_pipeline(
+            py,
+            send,
+            |_, pair| match pair {
+                Some((a, b)) => match (
+                    self.vector(&a, mode, lowercase),
+                    self.vector(&b, mode, lowercase),
+                ) {
+                    (Ok(a), Ok(b)) => cosine_similarity(&a, &b).unwrap_or(0.0),
+                    (Err(e), _) | (_, Err(e)) => {
+                        error!("Error computing vector, ignoring: {e}");
+                        0.0
+                    }
+                },
+                None => 0.0,
+            },
+            |i, similarity| similarities[i] = similarity,
+        )?;
+        Ok(similarities.to_pyarray(py).to_object(py))
+    }
+
+    /// the labels as `str`, indexed by label id.
+    fn label_strings<'a>(&self, py: Python<'a>) -> Vec<&'a PyString> {
+        self.predictor
+            .reverse_label_dict()
+            .values()
+            .map(|label| PyString::new(py, label))
+            .collect()
+    }
+
+    /// the body of `batch`, with probabilities written as `P`.
+    fn batch_as<P: Probability + Element + ToPyObject>(
+        &self,
+        texts: &Texts,
+        options: &PredictOptions,
+        preprocess: Option<&PyAny>,
+        decode: &Decode,
+        returns: &BatchReturns,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let start = Instant::now();
+        let (mut predictions, invalid) =
+            self.predict_rows::<P>(texts, 0..texts.len(py), options, preprocess, decode, py)?;
+        let skip_invalid = decode.on_invalid == InvalidPolicy::Skip;
+        if skip_invalid {
+            // skipped as invalid rather than empty.
+            predictions
+                .empty
+                .retain(|i| invalid.binary_search(i).is_err());
+        }
+        if options.on_empty == EmptyPolicy::Error && !predictions.empty.is_empty() {
+            return Err(PyException::new_err(format!(
+                "empty texts at indices {}",
+                format_indices(&predictions.empty)
+            )));
+        }
+        let indices = if options.on_empty == EmptyPolicy::Skip || skip_invalid {
+            let mut skipped = match options.on_empty {
+                EmptyPolicy::Skip => std::mem::take(&mut predictions.empty),
+                _ => Vec::new(),
+            };
+            if skip_invalid {
+                skipped.extend(invalid);
+                skipped.sort_unstable();
+            }
+            Some(predictions.skip(&skipped))
+        } else {
+            None
+        };
+        let mut outputs = match returns.format {
+            OutputFormat::Arrays => vec![
+                self.label_dtype.to_pyarray(&predictions.labels, py),
+                predictions.probs.to_pyarray(py).to_object(py),
+            ],
+            OutputFormat::Dicts => vec![self.label_dicts(&predictions, py)?],
+        };
+        if returns.counts {
+            outputs.push(predictions.counts.to_pyarray(py).to_object(py));
+        }
+        if returns.mask {
+            outputs.push(predictions.mask().to_pyarray(py).to_object(py));
+        }
+        if returns.truncated {
+            outputs.push(predictions.truncated().to_pyarray(py).to_object(py));
+        }
+        if returns.entropy {
+            outputs.push(predictions.entropy.to_pyarray(py).to_object(py));
+        }
+        if let Some(indices) = indices {
+            outputs.push(indices.to_pyarray(py).to_object(py));
+        }
+        if options.on_error == ErrorPolicy::Report {
+            let errors: Array1<i64> = predictions.errors.iter().map(|&(i, _)| i as i64).collect();
+            outputs.push(errors.to_pyarray(py).to_object(py));
+        }
+        if returns.errors {
+            outputs.push(predictions.errors.into_py(py));
+        }
+        if let Some(stats) = options.stats.as_ref().filter(|_| returns.stats) {
+            outputs.push(batch_stats(stats, texts.len(py), start, py)?);
+        }
+        if outputs.len() == 1 {
+            return Ok(outputs.remove(0));
+        }
+        Ok(PyTuple::new(py, outputs).to_object(py))
+    }
+
+    /// a dict from label to probability for each row of `predictions`.
+    fn label_dicts<P: ToPyObject>(
+        &self,
+        predictions: &Predictions<P>,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        let labels = self.label_strings(py);
+        let rows = PyList::empty(py);
+        for ((row_labels, row_probs), &count) in predictions
+            .labels
+            .rows()
+            .into_iter()
+            .zip(predictions.probs.rows())
+            .zip(&predictions.counts)
+        {
+            let dict = PyDict::new(py);
+            for (&id, prob) in row_labels.iter().zip(row_probs).take(count as usize) {
+                if let Some(label) = usize::try_from(id).ok().and_then(|id| labels.get(id)) {
+                    dict.set_item(label, prob)?;
+                }
+            }
+            rows.append(dict)?;
+        }
+        Ok(rows.to_object(py))
+    }
+
+    /// predict `texts` into the caller's arrays, which need a row per text.
+    fn predict_into<L: LabelId + Element>(
+        &self,
+        texts: &Texts,
+        out_labels: &PyArray2<L>,
+        out_probs: &PyArray2<f32>,
+        options: &PredictOptions,
+        py: Python,
+    ) -> PyResult<Array1<i32>> {
+        let len = texts.len(py);
+        if out_labels.shape()[0] != len || out_probs.shape()[0] != len {
+            return Err(PyException::new_err(format!(
+                "expected output arrays of {len} rows, got {} and {}",
+                out_labels.shape()[0],
+                out_probs.shape()[0]
+            )));
+        }
+        let borrow_error = |e| PyException::new_err(format!("output array not writeable, {e}"));
+        let mut out_labels = out_labels.try_readwrite().map_err(borrow_error)?;
+        let mut out_probs = out_probs.try_readwrite().map_err(borrow_error)?;
+        let labels = out_labels.as_array_mut();
+        let probs = out_probs.as_array_mut();
+        let counts = py.allow_threads(|| {
+            self.predictor
+                .predict_into(options, labels, probs, |text_sender| {
+                    Python::with_gil(|py| {
+                        texts
+                            .send(0..len, text_sender, None, &Decode::default(), py)
+                            .map(drop)
+                    })
+                })
+        })?;
+        Ok(counts)
+    }
+
+    /// predict the probabilities of all labels for `texts` as `P`, moved into
+    /// numpy without a copy.
+    fn predict_proba<'py, P: Probability + Element>(
+        &self,
+        texts: &Texts,
+        py: Python<'py>,
+    ) -> PyResult<&'py PyArray2<P>> {
+        let k = self.predictor.num_labels() as i32;
+        let options = PredictOptions::new(k, Thresholds::global(-1.0));
+        let len = texts.len(py);
+        let probs = py.allow_threads(|| {
+            self.predictor.predict_proba(len, &options, |text_sender| {
+                Python::with_gil(|py| {
+                    texts
+                        .send(0..len, text_sender, None, &Decode::default(), py)
+                        .map(drop)
+                })
+            })
+        })?;
+        Ok(probs.into_pyarray(py))
+    }
+
+    /// predict `texts[range]` into padded rows.
+    ///
+    /// Also returns the sorted input indices of the invalid elements, whose
+    /// rows are empty.
+    fn predict_rows<P: Probability>(
+        &self,
+        texts: &Texts,
+        range: Range<usize>,
+        options: &PredictOptions,
+        preprocess: Option<&PyAny>,
+        decode: &Decode,
+        py: Python,
+    ) -> PyResult<(Predictions<P>, Vec<usize>)> {
+        let preprocess = preprocess.map(|f| f.into_py(py));
+        let mut rejected = Rejected::default();
+        let mut predictions = py.allow_threads(|| {
+            self.predictor
+                .predict_rows(range.len(), range.start, options, |mut text_sender| {
+                    Python::with_gil(|py| {
+                        let start = Instant::now();
+                        let preprocess = preprocess.as_ref().map(|f| f.as_ref(py));
+                        rejected =
+                            texts.send_to(range, &mut text_sender, preprocess, decode, py)?;
+                        if let Some(stats) = &options.stats {
+                            stats.add_sender(start.elapsed().saturating_sub(text_sender.waited()));
+                        }
+                        Ok::<_, PyErr>(())
+                    })
+                })
+        })?;
+        if !rejected.undecodable.is_empty() {
+            predictions.errors.extend(rejected.undecodable);
+            predictions.errors.sort_unstable_by_key(|&(i, _)| i);
+        }
+        Ok((predictions, rejected.invalid))
+    }
+
+    /// the vector of `text`, lowercased first if `lowercase`.
+    fn vector(&self, text: &str, mode: VectorMode, lowercase: bool) -> PyResult<Vec<f32>> {
+        let lowered;
+        let text = if lowercase {
+            lowered = text.to_lowercase();
+            &lowered
+        } else {
+            text
+        };
+        match mode {
+            VectorMode::Word => self.predictor.word_vector(text),
+            VectorMode::Sentence => self.predictor.sentence_vector(text),
+        }
+        .map_err(PyException::new_err)
+    }
+
+    /// `matrix`, read from the model file and cached on the first call.
+    fn matrix(&self, matrix: Matrix, py: Python) -> PyResult<&Array2<f32>> {
+        if self.predictor.model().is_quant() {
+            return Err(ModelTypeError::new_err(
+                "matrices unavailable for quantized models",
+            ));
+        }
+        let cache = match matrix {
+            Matrix::Input => &self.input_matrix,
+            Matrix::Output => &self.output_matrix,
+        };
+        if let Some(matrix) = cache.get() {
+            return Ok(matrix);
+        }
+        let mut read = py
+            .allow_threads(|| self.source.read_matrix(matrix))
+            .map_err(PyException::new_err)?;
+        if matrix == Matrix::Output && self.predictor.model_type() == "supervised" {
+            // rows by label id, as the labels may be renumbered.
+            read = read.select(Axis(0), &self.predictor.label_indices()?);
+        }
+        let _ = cache.set(read);
+        Ok(cache.get().unwrap())
+    }
+
+    fn word_vectors(&self) -> PyResult<&WordVectors> {
+        if let Some(word_vectors) = self.word_vectors.get() {
+            return Ok(word_vectors);
+        }
+        let pool = thread_pool()?;
+        let (words, _) = self
+            .predictor
+            .model()
+            .get_vocab()
+            .map_err(PyException::new_err)?;
+        let dim = self.predictor.model().get_dimension() as usize;
+        let mut vectors = Array2::<f32>::zeros(Ix2(words.len(), dim));
+        pool.install(|| {
+            vectors
+                .as_slice_mut()
+                .unwrap()
+                .par_chunks_mut(dim)
+                .zip(words.par_iter())
+                .try_for_each(|(row, word)| {
+                    let vector = self.predictor.word_vector(word)?;
+                    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+                    if norm > 0.0 {
+                        row.iter_mut().zip(vector).for_each(|(r, v)| *r = v / norm);
+                    }
+                    Ok::<_, String>(())
+                })
+        })
+        .map_err(PyException::new_err)?;
+        // a concurrent caller may have won the race, either result is identical.
+        let _ = self.word_vectors.set(WordVectors { words, vectors });
+        Ok(self.word_vectors.get().unwrap())
+    }
+}
+
+impl WordVectors {
+    /// the k words most cosine-similar to `vector`.
+    fn nearest(&self, vector: &[f32], k: usize) -> Vec<(String, f32)> {
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm = if norm > 0.0 { norm } else { 1.0 };
+        let mut similarities: Vec<(usize, f32)> = self
+            .vectors
+            .as_slice()
+            .unwrap()
+            .par_chunks(self.vectors.ncols())
+            .map(|row| row.iter().zip(vector).map(|(r, v)| r * v).sum::<f32>() / norm)
+            .enumerate()
+            .collect();
+        let k = k.min(similarities.len());
+        if k < similarities.len() {
+            similarities.select_nth_unstable_by(k, |a, b| b.1.total_cmp(&a.1));
+            similarities.truncate(k);
+        }
+        similarities.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        similarities
+            .into_iter()
+            .map(|(i, similarity)| (self.words[i].clone(), similarity))
+            .collect()
+    }
+}
+
+/// the `texts` argument accepted by the batch methods.
+///
+/// Lists and object arrays are copied into a list of their elements that
+/// only `Texts` refers to, so that their `str` can be sent to the workers
+/// without copying their text.
+enum Texts {
+    List(Py<PyList>),
+    NumpyUnicode(NumpyStrings),
+    Arrow(ArrowStrings),
+    /// a list of token lists, each joined by single spaces.
+    Tokens(Py<PyList>),
+}
+
+impl Texts {
+    fn from_py(texts: &PyAny) -> PyResult<Self> {
+        if let Ok(list) = texts.downcast::<PyList>() {
+            return Ok(Texts::List(list.get_slice(0, list.len()).into()));
+        }
+        if let Some(arrow) = ArrowStrings::from_py(texts)? {
+            return Ok(Texts::Arrow(arrow));
+        }
+        if let Ok(array) = texts.downcast::<PyArray1<PyObject>>() {
+            let array = array.readonly();
+            let array = array.as_array();
+            let elements = array.iter().map(|s| s.as_ref(texts.py()));
+            return Ok(Texts::List(PyList::new(texts.py(), elements).into()));
+        }
+        if let Some(array) = NumpyStrings::from_py(texts)? {
+            return Ok(Texts::NumpyUnicode(array));
+        }
+        Err(PyException::new_err(format!(
+            "unsupported texts type `{}`, expected a list of str, a 1-D numpy str or \
+             object array, a pyarrow string array or a polars Utf8 Series",
+            texts.get_type().name()?
+        )))
+    }
+
+    fn len(&self, py: Python) -> usize {
+        match self {
+            Texts::List(texts) => texts.as_ref(py).len(),
+            Texts::NumpyUnicode(texts) => texts.len(),
+            Texts::Arrow(texts) => texts.len(),
+            Texts::Tokens(texts) => texts.as_ref(py).len(),
+        }
+    }
+
+    /// send `texts[range]`, reading `bytes` and invalid elements by `decode`.
+    ///
+    /// Returns the input indices of the elements that could not be read.
+    fn send<'t>(
+        &'t self,
+        range: Range<usize>,
+        mut text_sender: ChunkSender<Option<Cow<'t, str>>>,
+        preprocess: Option<&PyAny>,
+        decode: &Decode,
+        py: Python,
+    ) -> PyResult<Rejected> {
+        self.send_to(range, &mut text_sender, preprocess, decode, py)
+    }
+
+    /// `send` that leaves the sender open for further texts.
+    fn send_to<'t>(
+        &'t self,
+        range: Range<usize>,
+        text_sender: &mut ChunkSender<Option<Cow<'t, str>>>,
+        preprocess: Option<&PyAny>,
+        decode: &Decode,
+        py: Python,
+    ) -> PyResult<Rejected> {
+        let offset = range.start;
+        match self {
+            Texts::List(texts) => {
+                let texts = texts.as_ref(py).get_slice(range.start, range.end);
+                // SAFETY: the elements are those of the list of `self`.
+                unsafe { send_text(texts.iter(), offset, text_sender, preprocess, decode, py) }
+            }
+            Texts::NumpyUnicode(texts) => send_native(
+                texts.iter_range(range),
+                offset,
+                text_sender,
+                preprocess,
+                decode,
+                py,
+            ),
+            Texts::Arrow(texts) => send_native(
+                texts.iter_range(range),
+                offset,
+                text_sender,
+                preprocess,
+                decode,
+                py,
+            ),
+            Texts::Tokens(texts) => {
+                let texts = texts.as_ref(py).get_slice(range.start, range.end);
+                send_tokens(texts.iter(), text_sender, py)?;
+                Ok(Rejected::default())
+            }
+        }
+    }
+}
+
+/// send texts, the first of which is at input index `offset`, returning the
+/// input indices of the elements that could not be read.
+///
+/// `str` elements are sent borrowed, and texts built from them, decoded or
+/// returned by `preprocess`, owned.
+///
+/// # Safety
+///
+/// `texts` must be elements of a list that lives for `'t` and that Python
+/// code cannot mutate, such as the list of `Texts`: `str` objects are
+/// immutable and their UTF-8 text lives as long as they do.
+#[inline]
+unsafe fn send_text<'a, 't>(
+    texts: impl Iterator<Item = &'a PyAny>,
+    offset: usize,
+    text_sender: &mut ChunkSender<Option<Cow<'t, str>>>,
+    preprocess: Option<&PyAny>,
+    decode: &Decode,
+    py: Python,
+) -> PyResult<Rejected> {
+    let mut rejected = Rejected::default();
+    for (i, s) in (offset..).zip(texts) {
+        let owned = |text: Option<&str>| text.map(|text| Cow::Owned(text.to_string()));
+        let text = match s.downcast::<PyBytes>() {
+            // decoded before `preprocess`, so that it only sees str.
+            Ok(bytes) => {
+                let text = match decode.decode(i, bytes)? {
+                    Ok(text) => Some(text),
+                    Err(e) => {
+                        rejected.undecodable.push((i, e));
+                        None
+                    }
+                };
+                match (text, preprocess) {
+                    (Some(text), Some(f)) => {
+                        owned(extract_text(i, f.call1((text,))?, decode, &mut rejected)?)
+                    }
+                    (text, _) => text.map(Cow::Owned),
+                }
+            }
+            Err(_) => match preprocess {
+                Some(f) => owned(extract_text(i, f.call1((s,))?, decode, &mut rejected)?),
+                None => extract_text(i, s, decode, &mut rejected)?
+                    .map(|text| Cow::Borrowed(&*(text as *const str))),
+            },
+        };
+        let send_result = py.allow_threads(|| {
+            trace!("text sent: {:?}", text);
+            text_sender.send(text)
+        });
+        if send_result.is_err() {
+            break;
+        };
+    }
+    let _ = py.allow_threads(|| text_sender.flush());
+    Ok(rejected)
+}
+
+/// the text of the `i`-th element, `None` if it is `None` or invalid.
+#[inline]
+fn extract_text<'py>(
+    i: usize,
+    s: &'py PyAny,
+    decode: &Decode,
+    rejected: &mut Rejected,
+) -> PyResult<Option<&'py str>> {
+    if s.is_none() {
+        return Ok(None);
+    }
+    let Ok(s) = s.downcast::<PyString>() else {
+        decode.invalid(i, s)?;
+        rejected.invalid.push(i);
+        return Ok(None);
+    };
+    match s.to_str() {
+        Ok(s) => Ok(Some(s)),
+        Err(e) => {
+            s.py().allow_threads(|| {
+                error!("Non-string element encountered in input, ignoring: {e}");
+            });
+            Ok(None)
+        }
+    }
+}
+
+/// send texts read from a native buffer rather than python objects,
+/// borrowed if the buffer holds UTF-8, the first of which is at input index
+/// `offset`.
+///
+/// Returns the input indices of the texts for which `preprocess` returned
+/// neither text nor `None`, which are treated by `decode` as invalid
+/// elements.
+#[inline]
+fn send_native<'t, T>(
+    texts: impl Iterator<Item = Option<T>> + Send,
+    offset: usize,
+    text_sender: &mut ChunkSender<Option<Cow<'t, str>>>,
+    preprocess: Option<&PyAny>,
+    decode: &Decode,
+    py: Python,
+) -> PyResult<Rejected>
+where
+    T: AsRef<str> + Into<Cow<'t, str>>,
+{
+    let mut rejected = Rejected::default();
+    match preprocess {
+        // without a callback the GIL is not needed at all.
+        None => py.allow_threads(|| {
+            for text in texts {
+                if text_sender.send(text.map(Into::into)).is_err() {
+                    break;
+                }
+            }
+        }),
+        Some(f) => {
+            for (i, text) in (offset..).zip(texts) {
+                let text = match text {
+                    Some(s) => extract_text(i, f.call1((s.as_ref(),))?, decode, &mut rejected)?
+                        .map(|s| Cow::Owned(s.to_string())),
+                    None => None,
+                };
+                if py.allow_threads(|| text_sender.send(text)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(rejected)
+}
+
+/// send token lists joined by single spaces, or `None` for empty or
+/// invalid token lists.
+#[inline]
+fn send_tokens<'a, 't>(
+    token_lists: impl Iterator<Item = &'a PyAny>,
+    text_sender: &mut ChunkSender<Option<Cow<'t, str>>>,
+    py: Python,
+) -> PyResult<()> {
+    for tokens in token_lists {
+        let text = match tokens.extract::<Vec<&str>>() {
+            Ok(tokens) if tokens.is_empty() => None,
+            Ok(tokens) => Some(Cow::Owned(tokens.join(" "))),
+            Err(e) => {
+                py.allow_threads(|| {
+                    error!("Non-token-list element encountered in input, ignoring: {e}");
+                });
+                None
+            }
+        };
+        if py.allow_threads(|| text_sender.send(text)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn send_pairs(
+    pairs: impl Iterator<Item = Option<(String, String)>>,
+    mut pair_sender: ChunkSender<Option<(String, String)>>,
+    py: Python,
+) -> PyResult<()> {
+    for pair in pairs {
+        if pair.is_none() {
+            py.allow_threads(|| {
+                error!("Non-string element encountered in input pair, ignoring");
+            });
+        }
+        if py.allow_threads(|| pair_sender.send(pair)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// run the pipeline of `fasttext_parallel_core::run_pipeline` without the
+/// GIL, except in `send`, which feeds items holding it.
+fn run_pipeline<T, R, S, P, W>(py: Python, send: S, process: P, mut write: W) -> PyResult<()>
+where
+    T: Send,
+    R: Send,
+    S: FnOnce(ChunkSender<T>, Python) -> PyResult<()> + Send,
+    P: Fn(usize, T) -> R + Sync,
+    W: FnMut(usize, R) + Send,
+{
+    let process = &process;
+    py.allow_threads(|| {
+        fasttext_parallel_core::run_pipeline(
+            |item_sender| Python::with_gil(|py| send(item_sender, py)),
+            process,
+            |i, result| {
+                write(i, result);
+                Ok(())
+            },
+        )
+    })
+}
+
+/// fail with `ModelLoadError` unless the label ids of `predictor` fit in
+/// `label_dtype`.
+fn check_label_dtype(predictor: &ParallelPredictor, label_dtype: LabelDtype) -> PyResult<()> {
+    let num_labels = predictor.num_labels();
+    if label_dtype.fits(num_labels) {
+        Ok(())
+    } else {
+        Err(ModelLoadError::new_err(format!(
+            "{num_labels} labels do not fit in label_dtype=\"{}\", use label_dtype=\"i32\"",
+            label_dtype.as_str()
+        )))
+    }
+}
+
+/// the dimensions and dtype of a numpy array, or the type of anything else,
+/// for an error message.
+fn describe_array(obj: &PyAny) -> String {
+    let ndim = obj.getattr("ndim").and_then(|n| n.extract::<usize>());
+    let dtype = obj
+        .getattr("dtype")
+        .and_then(|d| d.str().map(|s| s.to_string()));
+    match (ndim, dtype) {
+        (Ok(ndim), Ok(dtype)) => format!("a {ndim}-D array of dtype {dtype}"),
+        _ => match obj.get_type().name() {
+            Ok(name) => format!("`{name}`"),
+            Err(_) => "an unknown type".to_string(),
+        },
+    }
+}
+
+/// the `return_stats` dict of a batch of `rows` texts started at `start`.
+fn batch_stats(stats: &BatchStats, rows: usize, start: Instant, py: Python) -> PyResult<PyObject> {
+    let wall_secs = start.elapsed().as_secs_f64();
+    let worker_secs = stats.worker_secs();
+    let dict = PyDict::new(py);
+    dict.set_item("wall_secs", wall_secs)?;
+    dict.set_item("rows", rows)?;
+    dict.set_item("rows_per_sec", rows as f64 / wall_secs)?;
+    dict.set_item("sender_secs", stats.sender_secs())?;
+    dict.set_item("predict_secs", worker_secs.iter().sum::<f64>())?;
+    dict.set_item("worker_predict_secs", worker_secs)?;
+    dict.set_item("writer_secs", stats.writer_secs())?;
+    Ok(dict.to_object(py))
+}
+
+/// format indices for an error message, eliding all but the first few.
+fn format_indices(indices: &[usize]) -> String {
+    const SHOWN: usize = 10;
+    let shown: Vec<String> = indices.iter().take(SHOWN).map(|i| i.to_string()).collect();
+    if indices.len() > SHOWN {
+        format!("[{}, ...] ({} in total)", shown.join(", "), indices.len())
+    } else {
+        format!("[{}]", shown.join(", "))
+    }
+}
+
+/// run the Python signal handlers, so that Ctrl-C stops a batch.
+///
+/// The handlers only run on the main thread, elsewhere this does nothing.
+fn check_signals() -> Result<(), Error> {
+    Python::with_gil(|py| py.check_signals()).map_err(|e| {
+        debug!("batch interrupted by a signal handler: {e}");
+        Error::new(ErrorKind::Interrupted, "batch interrupted")
+    })
+}
+
+/// a timeout in seconds, which must be positive.
+fn timeout(secs: f64) -> PyResult<Duration> {
+    if secs > 0.0 {
+        Duration::try_from_secs_f64(secs)
+            .map_err(|e| PyException::new_err(format!("invalid timeout_secs, {e}")))
+    } else {
+        Err(PyException::new_err(format!(
+            "timeout_secs must be positive, got {secs}"
+        )))
+    }
+}
+
+/// cosine similarity, `None` if either vector has zero norm.
+#[inline]
+fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        None
+    } else {
+        Some(dot / (norm_a * norm_b))
+    }
+}
+
+/// read a mapping from label to label id written by `dump_label_map`.
+///
+/// Args:
+///     path: the path of the JSON file
+///
+/// Returns:
+///     a dict from label to label id, for the `label_to_int` argument of
+///     `load_model`
+#[pyfunction]
+fn load_label_map(path: &str, py: Python) -> PyResult<PyObject> {
+    let file = File::open(path)
+        .map_err(|e| FastTextError::new_err(format!("failed to read {path}, {e}")))?;
+    let label_map: BTreeMap<String, i32> = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| PyException::new_err(format!("invalid label map {path}, {e}")))?;
+    Ok(label_map.into_py_dict(py).to_object(py))
+}
+
+/// configure the thread pool used for prediction.
+///
+/// The pool belongs to this module, apart from the rayon global pool of
+/// other extensions. Must be called before the first `batch`, otherwise the
+/// pool is built on first use with `FASTTEXT_PARALLEL_THREADS` threads if
+/// set, or else `max(3, available_parallelism())`, the CPUs the process may
+/// run on, within its affinity mask and cgroup quota.
+///
+/// The pool gets `max(min_threads, num_threads)` threads, so
+/// `configure(num_threads=1, min_threads=1)` runs on a single thread, e.g.
+/// in a 1-core container.
+///
+/// Args:
+///     num_threads: the number of threads, defaults to
+///         `FASTTEXT_PARALLEL_THREADS` or the available parallelism
+///     min_threads: a floor on the number of threads, at least 1 with
+///         `num_threads`, defaults to 3
+#[pyfunction]
+#[pyo3(signature = (num_threads=None, min_threads=None))]
+fn configure(num_threads: Option<usize>, min_threads: Option<usize>) -> PyResult<()> {
+    Ok(fasttext_parallel_core::configure(num_threads, min_threads)?)
+}
+
+/// build the thread pool used for prediction with exactly `num_threads`
+/// threads.
+///
+/// Must be called before the first `batch`, and raises afterwards. It takes
+/// precedence over `FASTTEXT_PARALLEL_THREADS`, see `configure`.
+///
+/// Args:
+///     num_threads: the number of threads, at least 1
+#[pyfunction]
+fn init_threads(num_threads: usize) -> PyResult<()> {
+    Ok(fasttext_parallel_core::init_threads(num_threads)?)
+}
+
+/// set the number of threads used for prediction.
+///
+/// Unlike `configure`, this may be called at any time: it replaces the pool
+/// of the module. Calls in progress finish on the previous pool, later calls
+/// run on the new one.
+///
+/// Args:
+///     num_threads: the number of threads, at least 1
+#[pyfunction]
+fn set_num_threads(num_threads: usize) -> PyResult<()> {
+    Ok(fasttext_parallel_core::set_num_threads(num_threads)?)
+}
+
+/// set the number of texts predicted together by one worker task.
+///
+/// Larger chunks cut the scheduling overhead on short texts, smaller ones
+/// balance the load better on long texts. Takes effect on the next call.
+///
+/// Args:
+///     chunk_size: the number of texts per chunk, defaults to 64
+#[pyfunction]
+#[pyo3(signature = (chunk_size=fasttext_parallel_core::DEFAULT_CHUNK_SIZE))]
+fn set_chunk_size(chunk_size: usize) -> PyResult<()> {
+    Ok(fasttext_parallel_core::set_chunk_size(chunk_size)?)
+}
+
+/// clears the levels of the Python loggers cached by `pyo3_log`.
+static LOG_RESET: OnceLock<ResetHandle> = OnceLock::new();
+
+/// set the most verbose level of the messages logged by this module, without
+/// changing Python logging.
+///
+/// Messages above the level are dropped in Rust, before taking the GIL, so
+/// an application may log at DEBUG while `"trace"`, the default `"debug"`,
+/// keeps out the messages logged for each text. Messages within the level
+/// are passed to the Python loggers named after their Rust modules, such as
+/// `fasttext_parallel_core.pipeline`, whose levels are cached and read again
+/// by this call.
+///
+/// Args:
+///     level: `"trace"`, `"debug"`, `"info"`, `"warning"`, `"error"` or
+///         `"off"`, in any case
+#[pyfunction]
+fn set_log_level(level: &str) -> PyResult<()> {
+    let level = match level.to_ascii_lowercase().as_str() {
+        "trace" => LevelFilter::Trace,
+        "debug" => LevelFilter::Debug,
+        "info" => LevelFilter::Info,
+        "warning" => LevelFilter::Warn,
+        "error" => LevelFilter::Error,
+        "off" => LevelFilter::Off,
+        _ => {
+            return Err(PyException::new_err(format!(
+                "unknown log level `{level}`, expected one of `trace`, `debug`, `info`, `warning`, `error`, `off`"
+            )))
+        }
+    };
+    log::set_max_level(level);
+    if let Some(reset) = LOG_RESET.get() {
+        reset.reset();
+    }
+    Ok(())
 }
 
 #[pymodule]
-fn fasttext_parallel(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
-    pyo3_log::init();
-    let num_parallelism = available_parallelism()
-        .map_err(|e| PyException::new_err(format!("failed to initialize rayon crate, {e}")))?;
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(max(MIN_THREADS, num_parallelism.get()))
-        .build_global()
-        .map_err(|e| PyException::new_err(format!("failed to initialize rayon crate, {e}")))?;
+fn fasttext_parallel(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    // installed up to trace, and filtered by `set_log_level` in Rust.
+    let reset = Logger::new(py, Caching::LoggersAndLevels)?
+        .filter(LevelFilter::Trace)
+        .install()
+        .map_err(|e| PyException::new_err(e.to_string()))?;
+    log::set_max_level(LevelFilter::Debug);
+    let _ = LOG_RESET.set(reset);
+    fasttext_parallel_core::set_interrupt_check(check_signals);
     m.add_function(wrap_pyfunction!(load_model, m)?)?;
+    m.add_function(wrap_pyfunction!(train_autotune, m)?)?;
+    m.add_function(wrap_pyfunction!(configure, m)?)?;
+    m.add_function(wrap_pyfunction!(load_label_map, m)?)?;
+    m.add_function(wrap_pyfunction!(init_threads, m)?)?;
+    m.add_function(wrap_pyfunction!(set_num_threads, m)?)?;
+    m.add_function(wrap_pyfunction!(set_chunk_size, m)?)?;
+    m.add_function(wrap_pyfunction!(set_log_level, m)?)?;
+    m.add_function(wrap_pyfunction!(batch_ensemble, m)?)?;
+    m.add_function(wrap_pyfunction!(compare::compare, m)?)?;
     m.add_class::<FastTextPy>()?;
+    m.add_class::<EnsemblePy>()?;
+    m.add_class::<BatchIter>()?;
+    m.add("FastTextError", py.get_type::<FastTextError>())?;
+    m.add("ModelLoadError", py.get_type::<ModelLoadError>())?;
+    m.add("PredictionError", py.get_type::<PredictionError>())?;
+    m.add("ModelTypeError", py.get_type::<ModelTypeError>())?;
     Ok(())
 }
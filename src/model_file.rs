@@ -0,0 +1,267 @@
+//! read and write the dense matrices of a model in its binary file, as
+//! written by fasttext's `save_model`.
+
+use fasttext::FastText;
+use ndarray::{Array2, Ix2};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// the magic number starting fasttext model files.
+const FASTTEXT_FILEFORMAT_MAGIC_INT32: i32 = 793712314;
+
+/// the number of bytes of the saved arguments: 12 i32 and the f64 `t`.
+const ARGS_SIZE: i64 = 12 * 4 + 8;
+
+/// the offset of `dim`, the first argument, after the magic and version.
+const DIM_OFFSET: usize = 8;
+
+/// a matrix of a model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Matrix {
+    /// the word and subword embeddings, `(nwords + bucket, dim)`.
+    Input,
+    /// the output layer, `(nlabels, dim)` for supervised models.
+    Output,
+}
+
+/// read `matrix` from the model file at `path`, which must not be quantized.
+pub(crate) fn read_matrix(path: &Path, matrix: Matrix) -> Result<Array2<f32>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to read {}, {e}", path.display()))?;
+    let mut reader = ModelReader(BufReader::new(file));
+    reader
+        .read(matrix)
+        .map_err(|e| format!("invalid model file, {e}"))
+}
+
+/// the file a model was loaded from, whose matrices are read on demand, as
+/// fasttext only gives them away by saving the whole model.
+pub(crate) struct ModelSource {
+    path: PathBuf,
+    /// the length and modification time of the file when the model was
+    /// loaded, to detect a file replaced since.
+    stamp: Option<(u64, SystemTime)>,
+    /// saved by this crate, and removed on drop.
+    temporary: bool,
+}
+
+impl ModelSource {
+    /// the file at `path`, that a model was just loaded from.
+    pub(crate) fn file(path: &Path) -> Self {
+        ModelSource {
+            path: path.to_path_buf(),
+            stamp: stamp(path),
+            temporary: false,
+        }
+    }
+
+    /// a temporary file at `path` written by this crate, which is removed
+    /// on drop.
+    pub(crate) fn temporary(path: PathBuf) -> Self {
+        ModelSource {
+            stamp: stamp(&path),
+            path,
+            temporary: true,
+        }
+    }
+
+    /// a temporary file `model` is saved to, for models without a file.
+    pub(crate) fn saved(model: &mut FastText) -> Result<Self, String> {
+        let path = temp_path();
+        let saved = model.save_model(&path.to_string_lossy());
+        let source = ModelSource::temporary(path);
+        saved.map(|_| source)
+    }
+
+    /// the path of the file, which fails if it was replaced since the
+    /// model was loaded.
+    pub(crate) fn path(&self) -> Result<&Path, String> {
+        if self.stamp.is_some() && stamp(&self.path) == self.stamp {
+            Ok(&self.path)
+        } else {
+            Err(format!(
+                "model file {} was changed or removed since the model was loaded",
+                self.path.display()
+            ))
+        }
+    }
+
+    pub(crate) fn read_matrix(&self, matrix: Matrix) -> Result<Array2<f32>, String> {
+        read_matrix(self.path()?, matrix)
+    }
+}
+
+impl Drop for ModelSource {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// a new path in the temporary directory for a model file.
+pub(crate) fn temp_path() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "fasttext-parallel-model-{}-{}.bin",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+fn stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// a model file with its matrices, which must not be quantized.
+pub(crate) struct ModelFile {
+    /// the magic, version, arguments and dictionary, as saved.
+    header: Vec<u8>,
+    pub(crate) input: Array2<f32>,
+    pub(crate) output: Array2<f32>,
+}
+
+impl ModelFile {
+    pub(crate) fn read(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("failed to read {}, {e}", path.display()))?;
+        let mut reader = ModelReader(BufReader::new(file));
+        reader
+            .read_file()
+            .map_err(|e| format!("invalid model file, {e}"))
+    }
+
+    /// write the model with its matrices, whose columns set the dimension.
+    pub(crate) fn write(&self, path: &Path) -> Result<(), String> {
+        let io_error = |e: std::io::Error| format!("failed to write {}, {e}", path.display());
+        let dim = i32::try_from(self.input.ncols()).map_err(|e| e.to_string())?;
+        let mut header = self.header.clone();
+        header[DIM_OFFSET..DIM_OFFSET + 4].copy_from_slice(&dim.to_le_bytes());
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        writer.write_all(&header).map_err(io_error)?;
+        for matrix in [&self.input, &self.output] {
+            // not quantized.
+            writer.write_all(&[0]).map_err(io_error)?;
+            write_matrix(&mut writer, matrix).map_err(io_error)?;
+        }
+        writer.flush().map_err(io_error)
+    }
+}
+
+fn write_matrix(writer: &mut impl Write, matrix: &Array2<f32>) -> std::io::Result<()> {
+    writer.write_all(&(matrix.nrows() as i64).to_le_bytes())?;
+    writer.write_all(&(matrix.ncols() as i64).to_le_bytes())?;
+    for value in matrix.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+struct ModelReader(BufReader<File>);
+
+impl ModelReader {
+    fn read(&mut self, matrix: Matrix) -> std::io::Result<Array2<f32>> {
+        if self.i32()? != FASTTEXT_FILEFORMAT_MAGIC_INT32 {
+            return Err(invalid("not a fasttext model"));
+        }
+        // the version, then the arguments.
+        self.skip(4 + ARGS_SIZE)?;
+        self.skip_dictionary()?;
+        if self.u8()? != 0 {
+            return Err(invalid("the input matrix is quantized"));
+        }
+        if matrix == Matrix::Input {
+            return self.matrix();
+        }
+        let (rows, cols) = (self.i64()?, self.i64()?);
+        self.skip(rows * cols * 4)?;
+        if self.u8()? != 0 {
+            return Err(invalid("the output matrix is quantized"));
+        }
+        self.matrix()
+    }
+
+    fn read_file(&mut self) -> std::io::Result<ModelFile> {
+        if self.i32()? != FASTTEXT_FILEFORMAT_MAGIC_INT32 {
+            return Err(invalid("not a fasttext model"));
+        }
+        self.skip(4 + ARGS_SIZE)?;
+        self.skip_dictionary()?;
+        let mut header = vec![0u8; self.0.stream_position()? as usize];
+        self.0.seek(SeekFrom::Start(0))?;
+        self.0.read_exact(&mut header)?;
+        let input = self.dense_matrix()?;
+        let output = self.dense_matrix()?;
+        Ok(ModelFile {
+            header,
+            input,
+            output,
+        })
+    }
+
+    /// a matrix after its quantization flag, which must be unset.
+    fn dense_matrix(&mut self) -> std::io::Result<Array2<f32>> {
+        if self.u8()? != 0 {
+            return Err(invalid("the model is quantized"));
+        }
+        self.matrix()
+    }
+
+    fn skip_dictionary(&mut self) -> std::io::Result<()> {
+        let size = self.i32()?;
+        // nwords, nlabels and ntokens.
+        self.skip(4 + 4 + 8)?;
+        let pruneidx_size = self.i64()?;
+        for _ in 0..size {
+            // the word, terminated by a null byte.
+            while self.u8()? != 0 {}
+            // its count and type.
+            self.skip(8 + 1)?;
+        }
+        self.skip(pruneidx_size.max(0) * 8)
+    }
+
+    fn matrix(&mut self) -> std::io::Result<Array2<f32>> {
+        let rows = usize::try_from(self.i64()?).map_err(|_| invalid("negative rows"))?;
+        let cols = usize::try_from(self.i64()?).map_err(|_| invalid("negative columns"))?;
+        let mut matrix = Array2::<f32>::zeros(Ix2(rows, cols));
+        let mut buf = [0u8; 4096];
+        for chunk in matrix.as_slice_mut().unwrap().chunks_mut(buf.len() / 4) {
+            let bytes = &mut buf[..chunk.len() * 4];
+            self.0.read_exact(bytes)?;
+            for (value, bytes) in chunk.iter_mut().zip(bytes.chunks_exact(4)) {
+                *value = f32::from_le_bytes(bytes.try_into().unwrap());
+            }
+        }
+        Ok(matrix)
+    }
+
+    fn skip(&mut self, bytes: i64) -> std::io::Result<()> {
+        self.0.seek_relative(bytes)
+    }
+
+    fn u8(&mut self) -> std::io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.0.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn i32(&mut self) -> std::io::Result<i32> {
+        let mut buf = [0u8; 4];
+        self.0.read_exact(&mut buf)?;
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn i64(&mut self) -> std::io::Result<i64> {
+        let mut buf = [0u8; 8];
+        self.0.read_exact(&mut buf)?;
+        Ok(i64::from_le_bytes(buf))
+    }
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,107 @@
+//! read numpy fixed-width unicode (`<U`) arrays without creating `str` objects.
+
+use log::error;
+use numpy::npyffi::PyArray_Check;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::AsPyPointer;
+use std::ops::Range;
+
+/// borrowed view of a 1-D numpy `<U` array, whose elements are
+/// `width` UCS4 code points padded with trailing NULs.
+pub(crate) struct NumpyStrings {
+    // keeps the buffer alive.
+    _array: PyObject,
+    data: *const u8,
+    len: usize,
+    stride: isize,
+    width: usize,
+    swap: bool,
+}
+
+// the buffer is only read, and kept alive by `_array`.
+unsafe impl Send for NumpyStrings {}
+unsafe impl Sync for NumpyStrings {}
+
+impl NumpyStrings {
+    /// view `array` if it is a numpy array of dtype kind `U`,
+    /// returns `None` for any other object.
+    pub(crate) fn from_py(array: &PyAny) -> PyResult<Option<Self>> {
+        if unsafe { PyArray_Check(array.py(), array.as_ptr()) } == 0
+            || array.getattr("dtype")?.getattr("kind")?.extract::<&str>()? != "U"
+        {
+            return Ok(None);
+        }
+        let interface = array.getattr("__array_interface__")?;
+        let interface = interface.downcast::<PyDict>()?;
+        let get = |key: &str| {
+            interface
+                .get_item(key)
+                .ok_or_else(|| PyException::new_err(format!("missing `{key}` in array interface")))
+        };
+        let shape: Vec<usize> = get("shape")?.extract()?;
+        let &[len] = shape.as_slice() else {
+            return Err(PyException::new_err(format!(
+                "expected a 1-D array of str, got {} dimensions",
+                shape.len()
+            )));
+        };
+        let typestr: String = get("typestr")?.extract()?;
+        let width: usize = typestr[2..]
+            .parse()
+            .map_err(|_| PyException::new_err(format!("unexpected typestr `{typestr}`")))?;
+        let swap = match &typestr[..1] {
+            "<" => cfg!(target_endian = "big"),
+            ">" => cfg!(target_endian = "little"),
+            _ => false,
+        };
+        let (data, _readonly): (usize, bool) = get("data")?.extract()?;
+        let stride = match get("strides")?.extract::<Option<Vec<isize>>>()? {
+            Some(strides) => strides[0],
+            None => (width * 4) as isize,
+        };
+        Ok(Some(NumpyStrings {
+            _array: array.into(),
+            data: data as *const u8,
+            len,
+            stride,
+            width,
+            swap,
+        }))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// iterate `texts[range]`, yielding `None` for invalid code points.
+    pub(crate) fn iter_range(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = Option<String>> + '_ {
+        range.map(|i| self.get(i))
+    }
+
+    fn get(&self, i: usize) -> Option<String> {
+        let item = unsafe { self.data.offset(i as isize * self.stride) };
+        let mut text = String::with_capacity(self.width);
+        for j in 0..self.width {
+            let mut bytes = [0u8; 4];
+            unsafe { std::ptr::copy_nonoverlapping(item.add(j * 4), bytes.as_mut_ptr(), 4) };
+            let code = u32::from_ne_bytes(bytes);
+            let code = if self.swap { code.swap_bytes() } else { code };
+            if code == 0 {
+                break;
+            }
+            match char::from_u32(code) {
+                Some(c) => text.push(c),
+                None => {
+                    error!("Invalid code point encountered in numpy input, ignoring: {code:#x}");
+                    return None;
+                }
+            }
+        }
+        Some(text)
+    }
+}
@@ -0,0 +1,105 @@
+//! reduce the dimension of a model by PCA, like `fasttext.util.reduce_model`.
+
+use crate::model_file::ModelFile;
+use fasttext_parallel_core::thread_pool;
+use ndarray::{s, Array1, Array2, Axis};
+use rayon::prelude::*;
+use std::path::Path;
+
+/// the rows of the input matrix per task of the covariance.
+const ROWS_PER_TASK: usize = 4096;
+
+/// the maximum number of Jacobi sweeps, which converge in about ten.
+const MAX_SWEEPS: usize = 100;
+
+/// write the model at `input` to `output` with its input and output
+/// matrices projected on the top `dim` principal components of the input
+/// matrix.
+pub(crate) fn reduce_model(input: &Path, output: &Path, dim: usize) -> Result<(), String> {
+    let mut model = ModelFile::read(input)?;
+    let components = principal_components(&model.input, dim)?;
+    model.input = model.input.dot(&components);
+    model.output = model.output.dot(&components);
+    model.write(output)
+}
+
+/// the top `dim` eigenvectors of the covariance of the rows of `matrix`, as
+/// columns ordered by decreasing eigenvalue.
+fn principal_components(matrix: &Array2<f32>, dim: usize) -> Result<Array2<f32>, String> {
+    let cols = matrix.ncols();
+    if dim == 0 || dim > cols {
+        return Err(format!("target dimension must be in 1..={cols}, got {dim}"));
+    }
+    let pool = thread_pool().map_err(|e| e.message)?;
+    let blocks: Vec<_> = matrix.axis_chunks_iter(Axis(0), ROWS_PER_TASK).collect();
+    let blocks = || blocks.par_iter();
+    let mean = pool.install(|| {
+        blocks()
+            .map(|block| block.mapv(f64::from).sum_axis(Axis(0)))
+            .reduce(|| Array1::zeros(cols), |a, b| a + b)
+    }) / matrix.nrows().max(1) as f64;
+    let covariance = pool.install(|| {
+        blocks()
+            .map(|block| {
+                let centered = block.mapv(f64::from) - &mean;
+                centered.t().dot(&centered)
+            })
+            .reduce(|| Array2::zeros((cols, cols)), |a, b| a + b)
+    });
+    let (values, vectors) = symmetric_eigen(covariance);
+    let mut order: Vec<usize> = (0..cols).collect();
+    order.sort_by(|&i, &j| values[j].total_cmp(&values[i]));
+    let mut components = Array2::zeros((cols, dim));
+    for (k, &i) in order[..dim].iter().enumerate() {
+        components
+            .slice_mut(s![.., k])
+            .assign(&vectors.slice(s![.., i]).mapv(|v| v as f32));
+    }
+    Ok(components)
+}
+
+/// the eigenvalues and eigenvectors, as columns, of a symmetric matrix by
+/// the cyclic Jacobi method.
+fn symmetric_eigen(mut a: Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = a.nrows();
+    let mut v = Array2::eye(n);
+    let total: f64 = a.iter().map(|x| x * x).sum();
+    for _ in 0..MAX_SWEEPS {
+        let off: f64 = a
+            .indexed_iter()
+            .filter(|((i, j), _)| i != j)
+            .map(|(_, x)| x * x)
+            .sum();
+        if off <= total * f64::EPSILON * f64::EPSILON {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[[p, q]];
+                if apq == 0.0 {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                rotate_columns(&mut a, p, q, c, s);
+                rotate_columns(&mut v, p, q, c, s);
+                for k in 0..n {
+                    let (akp, akq) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * akp - s * akq;
+                    a[[q, k]] = s * akp + c * akq;
+                }
+            }
+        }
+    }
+    (a.diag().to_owned(), v)
+}
+
+fn rotate_columns(m: &mut Array2<f64>, p: usize, q: usize, c: f64, s: f64) {
+    for k in 0..m.nrows() {
+        let (mkp, mkq) = (m[[k, p]], m[[k, q]]);
+        m[[k, p]] = c * mkp - s * mkq;
+        m[[k, q]] = s * mkp + c * mkq;
+    }
+}
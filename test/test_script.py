@@ -1,9 +1,23 @@
+import _thread
+import asyncio
+import gc
+import gzip
+import os
+import tempfile
+import threading
+import time
 import timeit
 import unittest
 import fasttext_parallel as ft
 import fasttext as ft_ref
+import pyarrow as pa
+import polars as pl
 import logging
+import numpy as np
 import csv
+import random
+import subprocess
+import sys
 
 logging.basicConfig(level=logging.ERROR)
 ft_ref.FastText.eprint = lambda x: None
@@ -30,6 +44,130 @@ class TestFastText(unittest.TestCase):
             set(self.model_ref.get_labels())
         )
 
+    def test_label_map(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, "labels.json")
+            self.model.dump_label_map(path)
+            label_map = ft.load_label_map(path)
+            self.assertEqual(label_map, {label: i for i, label in self.model.get_labels().items()})
+            reversed_map = {label: len(label_map) - 1 - i for label, i in label_map.items()}
+            model = ft.load_model(MODEL_PATH, label_to_int=reversed_map)
+            self.assertEqual(model.get_labels(), {i: label for label, i in reversed_map.items()})
+            texts = ["hello", "你好", "how are you"]
+            self.assertEqual(model.batch(texts, 2, output="dicts"), self.model.batch(texts, 2, output="dicts"))
+            labels, freqs = model.get_labels(include_freq=True)
+            self.assertEqual(dict(zip(labels, freqs)), self.model.get_label_counts())
+            vector = self.model.get_output_vector(label_map[labels[0]])
+            self.assertTrue((model.get_output_matrix()[0] == vector).all())
+            self.assertTrue((model.get_output_vector(0) == vector).all())
+            with self.assertRaises(Exception):
+                ft.load_model(MODEL_PATH, label_to_int={})
+            with self.assertRaises(Exception):
+                ft.load_model(MODEL_PATH, label_to_int=dict.fromkeys(label_map, 0))
+            with open(path, "w") as f:
+                f.write("not json")
+            with self.assertRaises(Exception):
+                ft.load_label_map(path)
+            with self.assertRaises(ft.FastTextError):
+                ft.load_label_map(os.path.join(tmp, "missing.json"))
+
+    def test_return_stats(self):
+        texts = text_iter()[:2000]
+        labels, probs, stats = self.model.batch(texts, 2, return_stats=True)
+        expected = self.model.batch(texts, 2)
+        np.testing.assert_array_equal(labels, expected[0])
+        np.testing.assert_array_equal(probs, expected[1])
+        self.assertEqual(stats["rows"], len(texts))
+        self.assertGreater(stats["wall_secs"], 0.0)
+        self.assertAlmostEqual(stats["rows_per_sec"], len(texts) / stats["wall_secs"])
+        self.assertAlmostEqual(stats["predict_secs"], sum(stats["worker_predict_secs"]))
+        self.assertGreater(stats["predict_secs"], 0.0)
+        for key in ["sender_secs", "writer_secs"]:
+            self.assertTrue(0.0 <= stats[key] <= stats["wall_secs"])
+        dicts, stats = self.model.batch(texts, output="dicts", return_stats=True)
+        self.assertEqual(len(dicts), stats["rows"])
+
+    def test_strict_labels(self):
+        texts = text_iter()[:500]
+        labels, probs = self.model.batch(texts, 3)
+        strict_labels, strict_probs = self.model.batch(texts, 3, strict_labels=True)
+        self.assertTrue((strict_labels >= 0).all())
+        np.testing.assert_array_equal(strict_labels, labels)
+        np.testing.assert_array_equal(strict_probs, probs)
+
+    def test_get_labels_freq(self):
+        labels, freqs = self.model.get_labels(include_freq=True)
+        self.assertEqual(labels, self.model.get_labels_list())
+        self.assertEqual(freqs.dtype, np.int64)
+        self.assertEqual(list(freqs), [self.model.label_counts()[i] for i in range(len(labels))])
+        labels_ref, freqs_ref = self.model_ref.get_labels(include_freq=True)
+        self.assertEqual(dict(zip(labels, freqs)), dict(zip(labels_ref, freqs_ref)))
+
+    def test_get_labels_list(self):
+        labels = self.model.get_labels_list()
+        self.assertEqual(labels, [self.model.get_labels()[i] for i in range(len(self.model))])
+        ids, _ = self.model.batch(["hello", "你好"])
+        self.assertEqual(list(np.array(labels)[ids[:, 0]]), [self.model.get_label_by_id(i) for i in ids[:, 0]])
+
+    def test_output_dicts(self):
+        texts = ["你好", None, "hello", "how are you"]
+        labels, probs, counts = self.model.batch(texts, 3, 0.1, return_counts=True)
+        dicts = self.model.batch(texts, 3, 0.1, output="dicts")
+        self.assertEqual(len(dicts), len(texts))
+        self.assertEqual(dicts[1], {})
+        names = self.model.get_labels()
+        for row, n, d in zip(range(len(texts)), counts, dicts):
+            self.assertEqual(d, {names[labels[row, j]]: float(probs[row, j]) for j in range(n)})
+        dicts_counts, counts_out = self.model.batch(texts, 3, 0.1, output="dicts", return_counts=True)
+        self.assertEqual(dicts_counts, dicts)
+        self.assertTrue((counts_out == counts).all())
+        with self.assertRaises(Exception):
+            self.model.batch(texts, output="json")
+
+    def test_predict_dict(self):
+        texts = ["你好", None, "hello", "how are you"]
+        self.assertEqual(self.model.predict_dict(texts, 3, 0.1), self.model.batch(texts, 3, 0.1, output="dicts"))
+        self.assertEqual(self.model.predict_dict(texts)[1], {})
+        self.assertEqual(self.model.predict_dict([]), [])
+
+    def test_batch_keyed(self):
+        texts = ["你好", None, "hello", "how are you"]
+        keys = [17, "a", (1, 2), None]
+        labels, probs, counts = self.model.batch(texts, 3, 0.1, return_counts=True)
+        rows = self.model.batch_keyed(list(zip(keys, texts)), 3, 0.1)
+        self.assertEqual(list(rows), keys)
+        for i, key in enumerate(keys):
+            row_labels, row_probs = rows[key]
+            self.assertTrue((row_labels == labels[i, :counts[i]]).all())
+            self.assertTrue((row_probs == probs[i, :counts[i]]).all())
+        self.assertEqual(len(rows["a"][0]), 0)
+        self.assertEqual(self.model.batch_keyed([]), {})
+        with self.assertRaises(Exception):
+            self.model.batch_keyed([(1, "hello"), (1, "world")])
+
+    def test_ids_to_labels(self):
+        ids, _ = self.model.batch(["hello", "你好", "how are you"], 2)
+        labels = self.model.ids_to_labels(ids)
+        self.assertEqual(labels, [[self.model.get_label_by_id(i) for i in row] for row in ids])
+        ids = np.array([[0, -1], [-1, 1000]], dtype=np.int16)
+        labels = self.model.ids_to_labels(ids, placeholder="<none>")
+        self.assertEqual(labels, [[self.model.get_label_by_id(0), "<none>"], ["<none>", "<none>"]])
+        self.assertEqual(self.model.ids_to_labels(np.zeros((2, 0), dtype=np.int16)), [[], []])
+
+    def test_label_counts(self):
+        labels_ref, counts_ref = self.model_ref.get_labels(include_freq=True)
+        counts = self.model.label_counts()
+        self.assertEqual(set(counts), set(self.model.get_labels()))
+        for label, count in zip(labels_ref, counts_ref):
+            label_id = [i for i, l in self.model.get_labels().items() if l == label][0]
+            self.assertEqual(counts[label_id], count)
+
+    def test_get_counts(self):
+        labels_ref, counts_ref = self.model_ref.get_labels(include_freq=True)
+        self.assertEqual(self.model.get_label_counts(), dict(zip(labels_ref, counts_ref)))
+        words_ref, counts_ref = self.model_ref.get_words(include_freq=True)
+        self.assertEqual(self.model.get_word_counts(), dict(zip(words_ref, counts_ref)))
+
     def test_simple(self):
         k = 2
         test_text = ["你好", "春天在哪里", "吃了吗", "hello", "how are you"]
@@ -40,6 +178,1076 @@ class TestFastText(unittest.TestCase):
                 self.assertEqual(self.model.get_label_by_id(labels[i][j]), labels_ref[i][j])
                 self.assertAlmostEqual(probs[i][j], probs_ref[i][j], 1)
 
+    def test_newline(self):
+        labels, probs = self.model.batch(["foo\nbar", "foo bar"], 1, -1.0)
+        self.assertEqual(labels[0][0], labels[1][0])
+        self.assertAlmostEqual(probs[0][0], probs[1][0], 5)
+        # fasttext ignores what follows the first newline.
+        labels, probs = self.model.batch(["foo\nbar", "foo\n你好"], 1, -1.0, newline="keep")
+        self.assertEqual(labels[0][0], labels[1][0])
+        self.assertEqual(probs[0][0], probs[1][0])
+        with self.assertRaisesRegex(Exception, "text 1 contains a newline"):
+            self.model.batch(["foo bar", "foo\nbar"], 1, -1.0, newline="error")
+        with self.assertRaises(Exception):
+            self.model.batch(["foo"], newline="split")
+
+    def test_normalize_unicode(self):
+        import unicodedata
+        texts = ["cafe\u0301 au lait", "ｆｕｌｌ ｗｉｄｔｈ", None]
+        for form in ["NFC", "NFKC", "NFD", "NFKD"]:
+            labels, probs = self.model.batch(texts, 2, normalize_unicode=form)
+            normalized = [unicodedata.normalize(form, t) if t else t for t in texts]
+            labels_ref, probs_ref = self.model.batch(normalized, 2)
+            self.assertTrue((labels == labels_ref).all())
+            self.assertTrue((probs == probs_ref).all())
+            encoded = [t.encode() if t else t for t in texts]
+            labels, probs = self.model.batch(encoded, 2, normalize_unicode=form)
+            self.assertTrue((labels == labels_ref).all())
+            self.assertTrue((probs == probs_ref).all())
+        labels, _ = self.model.batch(texts, 2, preprocess=str.upper, normalize_unicode="NFKC")
+        labels_ref, _ = self.model.batch([unicodedata.normalize("NFKC", t.upper()) for t in texts[:2]] + [None], 2)
+        self.assertTrue((labels == labels_ref).all())
+        with self.assertRaises(Exception):
+            self.model.batch(texts, normalize_unicode="nfc")
+
+    def test_bytes(self):
+        texts = ["你好", "hello", "how are you"]
+        labels_ref, probs_ref = self.model.batch(texts, 2)
+        mixed = [texts[0].encode(), texts[1], texts[2].encode()]
+        labels, probs = self.model.batch(mixed, 2)
+        self.assertTrue((labels == labels_ref).all())
+        self.assertTrue((probs == probs_ref).all())
+        labels, _ = self.model.batch([t.encode("utf-16") for t in texts], 2, encoding="utf-16")
+        self.assertTrue((labels == labels_ref).all())
+        invalid = [b"hello", b"caf\xe9 \xff\xfeau lait"]
+        for errors in ["replace", "ignore"]:
+            labels, probs = self.model.batch(invalid, 2, errors=errors)
+            labels_ref, probs_ref = self.model.batch([t.decode("utf-8", errors) for t in invalid], 2)
+            self.assertTrue((labels == labels_ref).all())
+            self.assertTrue((probs == probs_ref).all())
+        _, _, errors = self.model.batch(invalid, 2, on_error="report")
+        self.assertEqual(list(errors), [1])
+        _, _, errors, collected = self.model.batch(invalid + ["nul\0byte"], 2, on_error="report", collect_errors=True)
+        self.assertEqual(list(errors), [1, 2])
+        self.assertEqual([i for i, _ in collected], [1, 2])
+        self.assertIn("decoding", collected[0][1])
+        _, _, collected = self.model.batch(texts, 2, collect_errors=True)
+        self.assertEqual(collected, [])
+        with self.assertRaisesRegex(Exception, "text 1"):
+            self.model.batch(invalid, 2, on_error="raise")
+        with self.assertRaises(LookupError):
+            self.model.batch(invalid, encoding="no-such-encoding")
+
+    def test_dtype(self):
+        texts = ["hello world", None, "你好", ""]
+        labels, probs = self.model.batch(texts, 3, 0.01)
+        labels_64, probs_64 = self.model.batch(texts, 3, 0.01, dtype="float64")
+        self.assertEqual(probs_64.dtype, np.float64)
+        self.assertTrue((labels_64 == labels).all())
+        self.assertTrue((probs_64 == probs.astype(np.float64)).all())
+        _, probs_64 = self.model.batch(texts, 3, 0.01, dtype="float64", log_probs=True)
+        self.assertTrue(np.isneginf(probs_64[1]).all())
+        dicts = self.model.batch(texts, 3, 0.01, output="dicts")
+        self.assertEqual(self.model.batch(texts, 3, 0.01, output="dicts", dtype="float64"), dicts)
+        with self.assertRaises(Exception):
+            self.model.batch(texts, dtype="float16")
+
+    def test_max_tokens(self):
+        texts = ["how are you doing today", "  hello \t  world  again ", "你好", ""]
+        labels, probs = self.model.batch(texts, 2, max_tokens=2)
+        labels_ref, probs_ref = self.model.batch(["how are ", "  hello \t  world  ", "你好", ""], 2)
+        np.testing.assert_array_equal(labels, labels_ref)
+        np.testing.assert_array_equal(probs, probs_ref)
+        labels, _ = self.model.batch(texts, 2, max_tokens=0)
+        np.testing.assert_array_equal(labels, self.model.batch([""] * len(texts), 2)[0])
+
+    def test_lowercase(self):
+        texts = ["İSTANBUL", "DİYARBAKIR", "Iğdır", "HELLO WORLD"]
+        labels, probs = self.model.batch(texts, 2, lowercase=True)
+        labels_ref, probs_ref = self.model.batch([t.lower() for t in texts], 2)
+        self.assertTrue((labels == labels_ref).all())
+        self.assertTrue((probs == probs_ref).all())
+        labels, _ = self.model.batch(["I\u0307STANBUL"], 2, normalize_unicode="NFC", lowercase=True)
+        self.assertTrue((labels == self.model.batch(["İstanbul".lower()], 2)[0]).all())
+        np.testing.assert_array_equal(
+            self.model.get_sentence_vector("İSTANBUL", lowercase=True),
+            self.model.get_sentence_vector("İSTANBUL".lower()))
+        np.testing.assert_array_equal(
+            self.model.get_word_vector("Iğdır", lowercase=True),
+            self.model.get_word_vector("iğdır"))
+        self.assertAlmostEqual(self.model.text_similarity("HELLO", "hello", lowercase=True), 1.0, 5)
+        similarities = self.model.similarity(["HELLO", "İ"], ["hello", "i̇"], lowercase=True)
+        np.testing.assert_allclose(similarities, [1.0, 1.0], rtol=1e-5)
+        np.testing.assert_allclose(self.model.batch_similarity([("HELLO", "hello")], lowercase=True), [1.0], rtol=1e-5)
+
+    def test_preprocess(self):
+        labels, probs = self.model.batch(["HELLO", "hello"], preprocess=str.lower)
+        labels_fast, probs_fast = self.model.batch(["HELLO", "hello"], lowercase=True)
+        for i in range(2):
+            self.assertEqual(labels[i][0], labels[1][0])
+            self.assertEqual(labels_fast[i][0], labels[i][0])
+            self.assertAlmostEqual(probs_fast[i][0], probs[i][0], 5)
+
+    def test_exceptions(self):
+        for error in [ft.ModelLoadError, ft.PredictionError, ft.ModelTypeError]:
+            self.assertTrue(issubclass(error, ft.FastTextError))
+        with self.assertRaises(ft.ModelLoadError):
+            ft.load_model("no-such-model.bin")
+        with self.assertRaisesRegex(ft.PredictionError, "text 1"):
+            self.model.batch(["hello", "a\nb"], newline="error")
+        with self.assertRaisesRegex(ft.PredictionError, "text 0"):
+            self.model.batch([b"\xff"], on_error="raise")
+        with self.assertRaises(ft.FastTextError):
+            self.model.batch(["hello"], on_error="warn")
+
+    def test_duplicate_labels(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            corpus = os.path.join(tmp, "corpus.txt")
+            with open(corpus, "w") as f:
+                f.writelines(f"__label__l{i % 2} word{i}\n" for i in range(100))
+            path = os.path.join(tmp, "duplicate.bin")
+            ft_ref.train_supervised(corpus, dim=4, epoch=1, minCount=1).save_model(path)
+            with open(path, "rb") as f:
+                model = f.read()
+            # a corrupt dictionary with the second label renamed to the first.
+            self.assertEqual(model.count(b"__label__l1\0"), 1)
+            with open(path, "wb") as f:
+                f.write(model.replace(b"__label__l1\0", b"__label__l0\0"))
+            with self.assertRaisesRegex(ft.ModelLoadError, "duplicate label __label__l0"):
+                ft.load_model(path)
+
+    def test_preprocess_error(self):
+        def fail(_):
+            raise ValueError("bad text")
+
+        with self.assertRaises(ValueError):
+            self.model.batch(["hello", "world"], preprocess=fail)
+
+    def test_similarity(self):
+        self.assertAlmostEqual(self.model.text_similarity("hello", "hello"), 1.0, 5)
+        self.assertEqual(self.model.text_similarity("", "hello"), 0.0)
+        pairs = [("hello", "hello"), ("", "hello"), ("你好", "how are you")]
+        similarities = self.model.batch_similarity(pairs)
+        self.assertEqual(len(similarities), len(pairs))
+        for (a, b), similarity in zip(pairs, similarities):
+            self.assertAlmostEqual(similarity, self.model.text_similarity(a, b), 5)
+        texts_a, texts_b = zip(*pairs)
+        np.testing.assert_array_equal(self.model.similarity(list(texts_a), list(texts_b)), similarities)
+        with self.assertRaises(Exception):
+            self.model.similarity(["hello"], [])
+
+    def test_arrow(self):
+        texts = ["你好", None, "hello", "", "how are you"]
+        labels, probs = self.model.batch(texts, 2)
+        for array in [pa.array(texts), pa.array(texts, pa.large_string()),
+                      pa.chunked_array([texts[:2], texts[2:]]), pa.array(["x"] + texts).slice(1)]:
+            labels_arrow, probs_arrow = self.model.batch(array, 2)
+            np.testing.assert_array_equal(labels_arrow, labels)
+            np.testing.assert_array_equal(probs_arrow, probs)
+
+    def test_numpy(self):
+        texts = ["你好", "hello", "", "how are you"]
+        labels, probs = self.model.batch(texts, 2)
+        for array in [np.array(texts), np.array(texts, dtype=object),
+                      np.repeat(texts, 2)[::2], np.array(texts).astype(">U11")]:
+            labels_np, probs_np = self.model.batch(array, 2)
+            np.testing.assert_array_equal(labels_np, labels)
+            np.testing.assert_array_equal(probs_np, probs)
+        with self.assertRaises(Exception):
+            self.model.batch(np.zeros(3), 2)
+
+    def test_polars(self):
+        df = pl.DataFrame({"text": ["你好", None, "hello", "how are you"]})
+        labels, probs = self.model.batch(df["text"], k=3)
+        labels_ref, probs_ref = self.model.batch(df["text"].to_list(), k=3)
+        np.testing.assert_array_equal(labels, labels_ref)
+        np.testing.assert_array_equal(probs, probs_ref)
+        self.assertTrue((probs[1] == 0.0).all())
+
+    def test_similarity_mode(self):
+        self.assertAlmostEqual(self.model.similarity("hello", "hello", mode="word"), 1.0, 5)
+        self.assertAlmostEqual(self.model.similarity("hello", "world"),
+                               self.model.text_similarity("hello", "world"), 5)
+        with self.assertLogs("fasttext_parallel", level="WARNING") as logs:
+            self.assertEqual(self.model.similarity("", "hello"), 0.0)
+        self.assertTrue(any("zero vector" in line for line in logs.output))
+        records = []
+        handler = logging.Handler(level="WARNING")
+        handler.emit = records.append
+        logger = logging.getLogger("fasttext_parallel")
+        logger.addHandler(handler)
+        try:
+            for a, b in [("hello", "world"), ("hello", "hello")]:
+                self.assertNotEqual(self.model.similarity(a, b, mode="word"), 0.0)
+        finally:
+            logger.removeHandler(handler)
+        self.assertEqual(records, [])
+        similarities = self.model.similarity(["hello", "你好"], ["world", "你好"], mode="word")
+        self.assertAlmostEqual(similarities[0], self.model.similarity("hello", "world", "word"), 5)
+        self.assertAlmostEqual(similarities[1], 1.0, 5)
+        with self.assertRaises(Exception):
+            self.model.similarity("hello", "world", mode="char")
+
+    def test_label_dtype(self):
+        self.assertEqual(self.model.label_dtype, "i16")
+        with tempfile.TemporaryDirectory() as tmp:
+            corpus = os.path.join(tmp, "corpus.txt")
+            with open(corpus, "w") as f:
+                f.writelines(f"__label__l{i} word{i} common\n" for i in range(40000))
+            path = os.path.join(tmp, "many.bin")
+            ft_ref.train_supervised(corpus, dim=4, epoch=1, minCount=1, loss="hs").save_model(path)
+            with self.assertRaises(ft.ModelLoadError):
+                ft.load_model(path)
+            model = ft.load_model(path, label_dtype="i32")
+        self.assertEqual(model.label_dtype, "i32")
+        self.assertEqual(len(model), 40000)
+        texts = [f"word{i}" for i in range(0, 40000, 1000)]
+        labels, probs = model.batch(texts, 3)
+        self.assertEqual(labels.dtype, np.int32)
+        labels_top1, _ = model.predict_top1(texts)
+        np.testing.assert_array_equal(labels_top1, labels[:, 0])
+        self.assertEqual(model.ids_to_labels(labels), [[model.get_label_by_id(i) for i in row] for row in labels])
+        out_labels = np.zeros((len(texts), 3), np.int32)
+        model.batch_into(texts, out_labels, np.zeros((len(texts), 3), np.float32), 3)
+        np.testing.assert_array_equal(out_labels, labels)
+        with self.assertRaises(Exception):
+            model.batch_into(texts, np.zeros((len(texts), 3), np.int16), np.zeros((len(texts), 3), np.float32), 3)
+
+    def test_reduce_model(self):
+        texts = text_iter()[:2000]
+        model = ft.load_model(MODEL_PATH)
+        labels, _ = model.batch(texts)
+        self.assertIs(model.reduce_model(12), model)
+        self.assertEqual(model.get_dimension(), 12)
+        self.assertEqual(model.get_input_matrix().shape[1], 12)
+        self.assertEqual(model.get_labels(), self.model.get_labels())
+        labels_reduced, _ = model.batch(texts)
+        self.assertGreater(np.mean(labels_reduced[:, 0] == labels[:, 0]), 0.8)
+        with self.assertRaises(Exception):
+            model.reduce_model(13)
+
+    def test_train_autotune(self):
+        words = {"a": ["apple", "banana", "cherry"], "b": ["dog", "cat", "mouse"], "c": ["red", "green", "blue"]}
+        rng = random.Random(1)
+        with tempfile.TemporaryDirectory() as tmp:
+            paths = {}
+            for name, n in [("train", 300), ("valid", 60)]:
+                paths[name] = os.path.join(tmp, f"{name}.txt")
+                with open(paths[name], "w") as f:
+                    for _ in range(n):
+                        label = rng.choice("abc")
+                        f.write(f"__label__{label} {' '.join(rng.sample(words[label], 2))}\n")
+            for kwargs in [{"metric": "auc"}, {"metric": "f1:"}, {"metric": "f1:__label__typo"},
+                           {"metric": "recallAtPrecision:0.5:__label__d"}, {"duration_secs": 0},
+                           {"validation_path": os.path.join(tmp, "missing.txt")}]:
+                with self.assertRaises(Exception):
+                    ft.train_autotune(**{"input_path": paths["train"], "validation_path": paths["valid"], **kwargs})
+            model = ft.train_autotune(paths["train"], paths["valid"], duration_secs=5, metric="f1:__label__a")
+        self.assertEqual(sorted(model.get_labels_list()), ["__label__a", "__label__b", "__label__c"])
+        self.assertEqual(model.batch(["dog cat"], output="dicts")[0].keys(), {"__label__b"})
+
+    def test_train_continue(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            corpus = os.path.join(tmp, "corpus.txt")
+            with open(corpus, "w") as f:
+                f.writelines(f"__label__{i % 2} old{i % 7} common\n" for i in range(200))
+            path = os.path.join(tmp, "model.bin")
+            ft_ref.train_supervised(corpus, dim=8, epoch=1, minCount=1).save_model(path)
+            model = ft.load_model(path)
+            update = os.path.join(tmp, "update.txt")
+            with open(update, "w") as f:
+                f.writelines(f"__label__{i % 3} new{i % 5} common\n" for i in range(200))
+            model.train_continue(update, epoch=2, lr=0.5)
+            with self.assertRaises(ft.FastTextError):
+                model.train_continue(os.path.join(tmp, "missing.txt"))
+        self.assertEqual(set(model.get_labels().values()), {"__label__0", "__label__1", "__label__2"})
+        self.assertTrue({"old0", "new0", "common"} <= set(model.get_word_counts()))
+        labels, _ = model.batch(["new1 common"], 3)
+        self.assertEqual(sorted(labels[0]), [0, 1, 2])
+
+    def test_model_type(self):
+        self.assertEqual(self.model.model_type, "supervised")
+        with tempfile.TemporaryDirectory() as tmp:
+            corpus = os.path.join(tmp, "corpus.txt")
+            with open(corpus, "w") as f:
+                f.write("\n".join(text_iter()[:1000]))
+            path = os.path.join(tmp, "skipgram.bin")
+            ft_ref.train_unsupervised(corpus, model="skipgram", dim=8, epoch=1, minCount=1).save_model(path)
+            model = ft.load_model(path)
+        self.assertEqual(model.model_type, "skipgram")
+        with self.assertRaises(ft.ModelTypeError):
+            model.batch(["hello"])
+        with self.assertRaises(ft.ModelTypeError):
+            model.batch_stream(["hello"])
+        with self.assertRaises(ft.ModelTypeError):
+            ft.Ensemble([self.model, model], labels="union")
+
+    def test_get_weighted_vector(self):
+        tokens = ["hello", "world", "你好"]
+        weights = [0.5, 2.0, 1.5]
+        vectors = [self.model.get_word_vector(t) for t in tokens]
+        expected = sum(w * v for w, v in zip(weights, vectors)) / sum(weights)
+        np.testing.assert_allclose(self.model.get_weighted_vector(tokens, weights), expected, rtol=1e-5)
+        self.assertFalse(self.model.get_weighted_vector([], []).any())
+        with self.assertRaises(Exception):
+            self.model.get_weighted_vector(tokens, weights[:2])
+
+    def test_nearest_neighbors_by_vector(self):
+        vector = self.model.get_sentence_vector("hello")
+        neighbors = self.model.nearest_neighbors_by_vector(vector, 5)
+        self.assertEqual(len(neighbors), 5)
+        similarities = [similarity for _, similarity in neighbors]
+        self.assertEqual(similarities, sorted(similarities, reverse=True))
+        with self.assertRaises(Exception):
+            self.model.nearest_neighbors_by_vector(np.zeros(3, dtype=np.float32), 5)
+
+    def test_batch_nearest_neighbors(self):
+        words = ["hello", "world", None, "你好"]
+        neighbors = self.model.batch_nearest_neighbors(words, 5)
+        self.assertEqual(len(neighbors), len(words))
+        self.assertEqual(neighbors[2], [])
+        for word, word_neighbors in zip(words, neighbors):
+            if word is None:
+                continue
+            self.assertEqual(len(word_neighbors), 5)
+            self.assertNotIn(word, [neighbor for neighbor, _ in word_neighbors])
+            reference = self.model_ref.get_nearest_neighbors(word, 5)
+            for (_, similarity), (similarity_ref, _) in zip(word_neighbors, reference):
+                self.assertAlmostEqual(similarity, similarity_ref, places=4)
+
+    def test_configure_after_batch(self):
+        self.model.batch(["hello"])
+        with self.assertRaises(Exception):
+            ft.configure(num_threads=4)
+
+    @unittest.skipUnless(os.path.isdir("/proc/self/task"), "counts threads in /proc")
+    def test_init_threads(self):
+        # the pool of this process is built already, so count the workers of
+        # fresh processes, named `fasttext-parallel-{i}`.
+        script = (
+            "import os, fasttext_parallel as ft\n"
+            "{init}"
+            f"ft.load_model({MODEL_PATH!r}).batch(['hello'] * 10000)\n"
+            "names = [open(f'/proc/self/task/{{t}}/comm').read() for t in os.listdir('/proc/self/task')]\n"
+            "print(sum(name.startswith('fasttext-parall') for name in names))\n"
+        )
+
+        def count_workers(init, env):
+            result = subprocess.run([sys.executable, "-c", script.format(init=init)], env={**os.environ, **env},
+                                    capture_output=True, text=True, check=True)
+            return int(result.stdout)
+
+        self.assertEqual(count_workers("ft.init_threads(2)\n", {}), 2)
+        self.assertEqual(count_workers("", {"FASTTEXT_PARALLEL_THREADS": "5"}), 5)
+        self.assertEqual(count_workers("ft.init_threads(2)\n", {"FASTTEXT_PARALLEL_THREADS": "5"}), 2)
+        self.assertEqual(count_workers("ft.configure(num_threads=1, min_threads=1)\n", {}), 1)
+        with self.assertRaises(subprocess.CalledProcessError):
+            count_workers("ft.configure(num_threads=0, min_threads=0)\n", {})
+        with self.assertRaises(subprocess.CalledProcessError):
+            count_workers("", {"FASTTEXT_PARALLEL_THREADS": "many"})
+        self.model.batch(["hello"])
+        with self.assertRaises(Exception):
+            ft.init_threads(2)
+        with self.assertRaises(Exception):
+            ft.init_threads(0)
+
+    def test_set_num_threads(self):
+        texts = ["hello", "how are you"] * 100
+        expected, _ = self.model.batch(texts)
+        try:
+            for num_threads in (1, 2):
+                ft.set_num_threads(num_threads)
+                labels, _ = self.model.batch(texts)
+                np.testing.assert_array_equal(labels, expected)
+            with self.assertRaises(Exception):
+                ft.set_num_threads(0)
+        finally:
+            ft.set_num_threads(max(3, os.cpu_count() or 1))
+
+    def test_global_pool_built_elsewhere(self):
+        # another rayon extension owning the global pool must not matter.
+        import polars
+        polars.Series(["a", "b"]).str.to_uppercase()
+        labels, _ = self.model.batch(["hello", "how are you"])
+        self.assertEqual(labels.shape, (2, 1))
+
+    def test_batch_proba(self):
+        texts = ["你好", "hello", None, "how are you"]
+        labels, probs, counts = self.model.batch(texts, len(self.model), return_counts=True)
+        proba = self.model.batch_proba(texts)
+        self.assertEqual(proba.shape, (len(texts), len(self.model)))
+        self.assertEqual(proba.dtype, np.float32)
+        self.assertTrue((proba[2] == 0.0).all())
+        for i in range(len(texts)):
+            np.testing.assert_array_equal(proba[i][labels[i][:counts[i]]], probs[i][:counts[i]])
+        proba_half = self.model.batch_proba(texts, dtype="float16")
+        self.assertEqual(proba_half.dtype, np.float16)
+        self.assertEqual(proba_half.nbytes * 2, proba.nbytes)
+        np.testing.assert_array_equal(proba_half, proba.astype(np.float16))
+        with self.assertRaises(Exception):
+            self.model.batch_proba(texts, dtype="float64")
+
+    def test_batch_sparse(self):
+        texts = ["你好", None, "hello", "how are you"] * 50
+        labels, probs, counts = self.model.batch(texts, 5, 0.05, return_counts=True)
+        rows, labels_sparse, probs_sparse = self.model.batch_sparse(texts, 5, 0.05)
+        self.assertEqual(rows.dtype, np.int64)
+        self.assertEqual(labels_sparse.dtype, np.int16)
+        self.assertEqual(len(rows), counts.sum())
+        mask = np.arange(labels.shape[1]) < counts[:, None]
+        self.assertTrue((rows == np.nonzero(mask)[0]).all())
+        self.assertTrue((labels_sparse == labels[mask]).all())
+        self.assertTrue((probs_sparse == probs[mask]).all())
+
+    def test_batch_into(self):
+        texts = ["你好", None, "hello", "how are you"] * 50
+        labels, probs, counts = self.model.batch(texts, 3, 0.1, return_counts=True)
+        with tempfile.TemporaryDirectory() as tmp:
+            out_labels = np.memmap(os.path.join(tmp, "labels"), np.int16, "w+", shape=(len(texts), 3))
+            out_probs = np.memmap(os.path.join(tmp, "probs"), np.float32, "w+", shape=(len(texts), 3))
+            out_labels[:] = 7
+            out_probs[:] = 7.0
+            counts_out = self.model.batch_into(texts, out_labels, out_probs, 3, 0.1)
+            self.assertTrue((counts_out == counts).all())
+            self.assertTrue((out_labels == labels).all())
+            self.assertTrue((out_probs == probs).all())
+            del out_labels, out_probs
+        with self.assertRaises(Exception):
+            self.model.batch_into(texts, np.zeros((len(texts), 2), np.int16), np.zeros((len(texts), 3), np.float32), 3)
+        with self.assertRaises(Exception):
+            self.model.batch_into(texts[1:], np.zeros((len(texts), 3), np.int16), np.zeros((len(texts), 3), np.float32), 3)
+        with self.assertRaisesRegex(Exception, "out_labels must be .* got a 2-D array of dtype int64"):
+            self.model.batch_into(texts, np.zeros((len(texts), 3), np.int64), np.zeros((len(texts), 3), np.float32), 3)
+        with self.assertRaisesRegex(Exception, "out_probs must be .* got a 1-D array of dtype float32"):
+            self.model.batch_into(texts, np.zeros((len(texts), 3), np.int16), np.zeros(len(texts), np.float32), 3)
+        read_only = np.zeros((len(texts), 3), np.int16)
+        read_only.flags.writeable = False
+        with self.assertRaises(Exception):
+            self.model.batch_into(texts, read_only, np.zeros((len(texts), 3), np.float32), 3)
+
+    def test_batch_tokens(self):
+        texts = ["hello world", "how are you", ""]
+        token_lists = [t.split() for t in texts] + [[], None]
+        labels, probs = self.model.batch(texts[:2] + [None, None, None], 2)
+        labels_out, probs_out = self.model.batch_tokens(token_lists, 2)
+        self.assertTrue((labels_out == labels).all())
+        self.assertTrue((probs_out == probs).all())
+        self.assertFalse(probs_out[2:].any())
+
+    def test_batch_file(self):
+        texts = ["hello world", "how are you", "", "你好"]
+        with tempfile.NamedTemporaryFile("wb", suffix=".txt", delete=False) as f:
+            f.write("\n".join(texts[:3]).encode() + b"\r\n\xff\xfe\n" + texts[3].encode() + b"\n")
+        try:
+            labels, probs = self.model.batch_file(f.name, 2)
+        finally:
+            os.remove(f.name)
+        labels_ref, probs_ref = self.model.batch(texts[:3] + [None] + texts[3:], 2)
+        self.assertTrue((labels == labels_ref).all())
+        self.assertTrue((probs == probs_ref).all())
+        with tempfile.NamedTemporaryFile("wb", suffix=".txt", delete=False) as f:
+            pass
+        try:
+            labels, probs = self.model.batch_file(f.name, 2)
+        finally:
+            os.remove(f.name)
+        self.assertEqual(labels.shape, (0, 2))
+        self.assertEqual(probs.shape, (0, 2))
+        with self.assertRaises(Exception):
+            self.model.batch_file("no/such/file.txt")
+
+    def test_batch_file_gzip(self):
+        texts = ["hello world", "how are you", "你好"]
+        content = "\r\n".join(texts).encode()
+        with tempfile.NamedTemporaryFile("wb", suffix=".txt.gz", delete=False) as f:
+            # two gzip members, split within a line.
+            f.write(gzip.compress(content[:8]) + gzip.compress(content[8:]))
+        try:
+            labels, probs = self.model.batch_file(f.name, 2)
+        finally:
+            os.remove(f.name)
+        labels_ref, probs_ref = self.model.batch(texts, 2)
+        self.assertTrue((labels == labels_ref).all())
+        self.assertTrue((probs == probs_ref).all())
+        with tempfile.NamedTemporaryFile("wb", suffix=".txt.gz", delete=False) as f:
+            f.write(gzip.compress(content)[:-12])
+        try:
+            with self.assertRaises(ft.FastTextError):
+                self.model.batch_file(f.name, 2)
+        finally:
+            os.remove(f.name)
+
+    def test_test(self):
+        labels = self.model.get_labels_list()
+        lines = [f"{labels[i % len(labels)]} {text}" for i, text in enumerate(text_iter()[:500])]
+        lines += ["", "no labels here", f"{labels[0]} {labels[1]}\tboth labels", "__label__unknown text"]
+        with tempfile.NamedTemporaryFile("w", suffix=".txt", delete=False, encoding="utf-8") as f:
+            f.write("\n".join(lines) + "\n")
+        try:
+            for k, threshold in [(1, 0.0), (3, 0.0), (2, 0.5)]:
+                n, precision, recall = self.model.test(f.name, k, threshold)
+                n_ref, precision_ref, recall_ref = self.model_ref.test(f.name, k, threshold)
+                self.assertEqual(n, n_ref)
+                self.assertAlmostEqual(precision, precision_ref, places=6)
+                self.assertAlmostEqual(recall, recall_ref, places=6)
+            result = self.model.test(f.name, 2)
+        finally:
+            os.remove(f.name)
+        # lines with labels but no words are not counted.
+        with tempfile.NamedTemporaryFile("w", suffix=".txt", delete=False, encoding="utf-8") as f:
+            f.write("\n".join(lines + [labels[0], f"{labels[0]} {labels[1]}"]) + "\n")
+        try:
+            self.assertEqual(self.model.test(f.name, 2), result)
+        finally:
+            os.remove(f.name)
+        with tempfile.NamedTemporaryFile("w", suffix=".txt", delete=False, encoding="utf-8") as f:
+            f.write("no labels here\n__label__unknown text\n")
+        try:
+            with self.assertRaises(ft.FastTextError):
+                self.model.test(f.name)
+        finally:
+            os.remove(f.name)
+        with self.assertRaises(ft.FastTextError):
+            self.model.test("no/such/file.txt")
+
+    def test_evaluate(self):
+        labels = self.model.get_labels_list()
+        texts = text_iter()[:500]
+        gold = [[labels[i % len(labels)]] for i in range(len(texts))]
+        gold[0] = [labels[0], 1]
+        gold[1] = []
+        names = [[labels[x] if isinstance(x, int) else x for x in g] for g in gold]
+        lines = [" ".join(n + [t.replace("\n", " ")]) for n, t in zip(names, texts)]
+        with tempfile.NamedTemporaryFile("w", suffix=".txt", delete=False, encoding="utf-8") as f:
+            f.write("\n".join(lines) + "\n")
+        try:
+            for k in [1, 3]:
+                scores = self.model.evaluate(texts, gold, k)
+                _, precision, recall = self.model.test(f.name, k, -1.0)
+                self.assertAlmostEqual(scores["precision"], precision)
+                self.assertAlmostEqual(scores["recall"], recall)
+                self.assertAlmostEqual(scores["micro_f1"], 2 * precision * recall / (precision + recall))
+                self.assertTrue(0.0 <= scores["macro_f1"] <= 1.0)
+        finally:
+            os.remove(f.name)
+        labels_out, _ = self.model.batch(texts, 1)
+        self.assertEqual(self.model.evaluate(texts, [[i] for i in labels_out[:, 0]])["macro_f1"], 1.0)
+        with self.assertRaises(Exception):
+            self.model.evaluate(texts, gold[:-1])
+        with self.assertRaises(Exception):
+            self.model.evaluate(texts[:1], [["__label__no_such_label"]])
+
+    def test_confusion_matrix(self):
+        texts = text_iter()[:500] + ["", None]
+        names = self.model.get_labels_list()
+        top1, _ = self.model.predict_top1(texts)
+        gold = [names[i % len(names)] for i in range(len(texts) - 3)] + [0, "__label__no_such_label", None]
+        matrix, labels = self.model.confusion_matrix(texts, gold)
+        n = len(self.model)
+        self.assertEqual(matrix.shape, (n + 1, n + 1))
+        self.assertEqual(matrix.dtype, np.int64)
+        self.assertEqual(labels, names + [None])
+        expected = np.zeros((n + 1, n + 1), dtype=np.int64)
+        gold_ids = [i % n for i in range(len(texts) - 3)] + [0, n, n]
+        for g, p in zip(gold_ids, top1):
+            expected[g, p if p >= 0 else n] += 1
+        np.testing.assert_array_equal(matrix, expected)
+        with self.assertRaises(Exception):
+            self.model.confusion_matrix(texts, gold[:-1])
+
+    def test_batch_chunked(self):
+        texts = text_iter()[:1000]
+        labels, probs = self.model.batch(texts, 2)
+        chunks = []
+        self.model.batch_chunked(texts, 2, chunk_size=300,
+                                 callback=lambda start, l, p: chunks.append((start, l, p)))
+        self.assertEqual([start for start, _, _ in chunks], [0, 300, 600, 900])
+        for start, labels_chunk, probs_chunk in chunks:
+            np.testing.assert_array_equal(labels_chunk, labels[start:start + len(labels_chunk)])
+            np.testing.assert_array_equal(probs_chunk, probs[start:start + len(probs_chunk)])
+
+    def test_return_counts(self):
+        texts = ["hello", None, "how are you"]
+        labels, probs, counts = self.model.batch(texts, 5, 0.1, return_counts=True)
+        self.assertEqual(counts.dtype, np.int32)
+        self.assertEqual(counts[1], 0)
+        for i in range(len(texts)):
+            self.assertTrue((probs[i][:counts[i]] >= 0.1).all())
+            self.assertTrue((probs[i][counts[i]:] == 0.0).all())
+
+    def test_label_thresholds(self):
+        texts = ["hello", "你好", "how are you"]
+        labels = {v: k for k, v in self.model.get_labels().items()}
+        labels_ref, probs_ref, counts_ref = self.model.batch(texts, 5, return_counts=True)
+        thresholds = {"__label__en": 2.0, labels["__label__zh"]: 0.0}
+        labels_out, probs_out, counts = self.model.batch(
+            texts, 5, thresholds, default_threshold=0.05, return_counts=True)
+        for i in range(len(texts)):
+            expected = [(l, p) for l, p in zip(labels_ref[i][:counts_ref[i]], probs_ref[i][:counts_ref[i]])
+                        if l != labels["__label__en"] and (p >= 0.05 or l == labels["__label__zh"])]
+            self.assertEqual(counts[i], len(expected))
+            self.assertEqual(list(labels_out[i][:counts[i]]), [l for l, _ in expected])
+            self.assertTrue((probs_out[i][counts[i]:] == 0.0).all())
+        with self.assertRaises(Exception):
+            self.model.batch(texts, 5, {"__label__unknown": 0.1})
+        with self.assertRaises(Exception):
+            self.model.batch(texts, 5, {"__label__en": "high"})
+
+    def test_allowed_labels(self):
+        texts = ["hello", "你好", "how are you"]
+        labels = {v: k for k, v in self.model.get_labels().items()}
+        allowed = [labels["__label__de"], labels["__label__fr"]]
+        labels_all, probs_all = self.model.batch(texts, len(labels))
+        labels_out, probs_out, counts = self.model.batch(
+            texts, 2, allowed_labels=allowed, return_counts=True)
+        for i in range(len(texts)):
+            expected = [(l, p) for l, p in zip(labels_all[i], probs_all[i]) if l in allowed]
+            self.assertEqual(counts[i], 2)
+            self.assertEqual(list(labels_out[i]), [l for l, _ in expected])
+            self.assertEqual(list(probs_out[i]), [p for _, p in expected])
+        with self.assertRaises(Exception):
+            self.model.batch(texts, 2, allowed_labels=[len(labels)])
+
+    def test_export_vectors(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, "model.vec")
+            self.model.export_vectors(path)
+            with open(path, encoding="utf-8") as f:
+                num_words, dim = map(int, f.readline().split())
+                lines = f.readlines()
+        words = self.model_ref.get_words()
+        self.assertEqual(num_words, len(words))
+        self.assertEqual(dim, self.model_ref.get_dimension())
+        self.assertEqual(len(lines), num_words)
+        for line in lines[:100]:
+            word, *vector = line.rstrip("\n").split(" ")
+            np.testing.assert_allclose(np.array(vector, dtype=np.float32), self.model_ref.get_word_vector(word), rtol=1e-5)
+
+    def test_output_vector(self):
+        output = self.model_ref.get_output_matrix()
+        labels_ref = self.model_ref.get_labels()
+        for label_id, label in self.model.get_labels().items():
+            vector = self.model.get_output_vector(label_id)
+            self.assertEqual(vector.dtype, np.float32)
+            np.testing.assert_array_equal(vector, output[labels_ref.index(label)])
+        with self.assertRaises(Exception):
+            self.model.get_output_vector(len(self.model))
+
+    def test_repr(self):
+        self.assertEqual(len(self.model), len(self.model.get_labels()))
+        self.assertEqual(repr(self.model),
+                         f"FastText(labels={len(self.model)}, dim={len(self.model.get_sentence_vector(''))}, quantized=false)")
+
+    def test_return_mask(self):
+        texts = ["hello", None, "how are you"]
+        labels, probs, counts, mask = self.model.batch(texts, 5, 0.1, return_counts=True, return_mask=True)
+        self.assertEqual(mask.dtype, np.bool_)
+        self.assertEqual(mask.shape, labels.shape)
+        self.assertTrue((mask.sum(axis=1) == counts).all())
+        self.assertFalse(mask[1].any())
+        _, _, mask_only = self.model.batch(texts, 5, 0.1, return_mask=True)
+        self.assertTrue((mask_only == mask).all())
+
+    def test_return_truncated(self):
+        texts = ["hello", None, "how are you"]
+        _, _, counts, truncated = self.model.batch(texts, 2, -1.0, return_counts=True, return_truncated=True)
+        self.assertEqual(truncated.dtype, np.bool_)
+        self.assertTrue((truncated == (counts == 2)).all())
+        self.assertFalse(truncated[1])
+        _, _, truncated = self.model.batch(texts, 2, 0.99, return_truncated=True)
+        self.assertFalse(truncated.any())
+
+    def test_return_entropy(self):
+        texts = ["hello", None, "how are you"]
+        labels, probs, entropy = self.model.batch(texts, 1, return_entropy=True)
+        self.assertEqual(entropy.dtype, np.float32)
+        self.assertEqual(entropy.shape, (3,))
+        self.assertTrue(np.isnan(entropy[1]))
+        ref_labels, ref_probs = self.model_ref.predict(["hello", "how are you"], k=-1, threshold=0.0)
+        for row, ref in zip([0, 2], ref_probs):
+            ref = ref[ref > 0]
+            self.assertAlmostEqual(entropy[row], -(ref * np.log(ref)).sum(), places=3)
+        # the top k are unaffected by computing the entropy.
+        labels_plain, probs_plain = self.model.batch(texts, 1)
+        self.assertTrue((labels == labels_plain).all())
+        self.assertTrue(np.allclose(probs, probs_plain))
+
+    def test_log_probs(self):
+        texts = ["hello", None, "how are you"]
+        _, probs = self.model.batch(texts, 3, 0.01)
+        labels, log_probs, counts = self.model.batch(texts, 3, 0.01, return_counts=True, log_probs=True)
+        for i in range(len(texts)):
+            np.testing.assert_allclose(log_probs[i][:counts[i]], np.log(probs[i][:counts[i]]), rtol=1e-6)
+            self.assertTrue(np.isneginf(log_probs[i][counts[i]:]).all())
+
+    def test_renormalize(self):
+        texts = ["hello", None, "how are you"]
+        _, probs, counts = self.model.batch(texts, 3, return_counts=True)
+        _, normalized = self.model.batch(texts, 3, renormalize=True)
+        _, log_normalized = self.model.batch(texts, 3, renormalize=True, log_probs=True)
+        for i in range(len(texts)):
+            if counts[i] == 0:
+                self.assertTrue((normalized[i] == 0.0).all())
+                continue
+            np.testing.assert_allclose(normalized[i], probs[i] / probs[i].sum(), rtol=1e-6)
+            self.assertAlmostEqual(normalized[i].sum(), 1.0, 5)
+            np.testing.assert_allclose(log_normalized[i], np.log(normalized[i]), rtol=1e-6)
+
+    def test_ensemble(self):
+        texts = ["hello", None, "你好", "how are you"]
+        labels, probs = self.model.batch(texts, 3)
+        self.assertEqual(self.model.get_labels(), ft.Ensemble([self.model]).get_labels())
+        for mode in ["shared", "union"]:
+            for pooling in ["mean", "max"]:
+                ensemble = ft.Ensemble([self.model, self.model], labels=mode, pooling=pooling)
+                self.assertEqual(len(ensemble), 2)
+                labels_out, probs_out = ensemble.batch(texts, 3)
+                self.assertTrue((labels_out == labels).all())
+                np.testing.assert_allclose(probs_out, probs, rtol=1e-5)
+        with self.assertRaises(Exception):
+            ft.Ensemble([])
+        with self.assertRaises(Exception):
+            ft.Ensemble([self.model], pooling="median")
+
+    def test_predict_ensemble(self):
+        texts = ["hello", None, "你好", "how are you"]
+        labels, probs = self.model.batch(texts, 3)
+        ensemble = ft.Ensemble([self.model, self.model, self.model])
+        for agg in [None, "mean", "max"]:
+            labels_out, probs_out = ensemble.predict_ensemble(texts, 3, agg=agg)
+            self.assertTrue((labels_out == labels).all())
+            np.testing.assert_allclose(probs_out, probs, rtol=1e-5)
+        labels_out, probs_out = ensemble.predict_ensemble(texts, 3, agg="vote")
+        self.assertTrue((labels_out[:, 0] == labels[:, 0])[[0, 2, 3]].all())
+        np.testing.assert_allclose(probs_out[[0, 2, 3], 0], 1.0, rtol=1e-5)
+        self.assertTrue((probs_out[:, 1:] == 0.0).all())
+        vote = ft.Ensemble([self.model, self.model], pooling="vote", weights=[3.0, 1.0])
+        self.assertTrue((vote.batch(texts, 3)[0] == labels_out).all())
+        with self.assertRaises(Exception):
+            ensemble.predict_ensemble(texts, agg="median")
+
+    def test_batch_ensemble(self):
+        texts = ["hello", None, "你好", "how are you"]
+        labels, probs = self.model.batch(texts, 3)
+        labels_out, probs_out = ft.batch_ensemble([self.model, self.model], texts, 3, weights=[3.0, 1.0])
+        self.assertTrue((labels_out == labels).all())
+        np.testing.assert_allclose(probs_out, probs, rtol=1e-5)
+        with self.assertRaises(Exception):
+            ft.batch_ensemble([self.model, self.model], texts, weights=[1.0])
+        with self.assertRaises(Exception):
+            ft.batch_ensemble([self.model], texts, weights=[-1.0])
+        with self.assertRaises(Exception):
+            ft.Ensemble([self.model], pooling="max", weights=[1.0])
+
+    def test_compare(self):
+        texts = text_iter()[:500] + [None]
+        labels, probs = self.model.batch(texts, 2)
+        result = ft.compare(self.model, self.model, texts, 2)
+        self.assertEqual(result["agreement"], 1.0)
+        self.assertTrue(result["match"].all())
+        for side in ["a", "b"]:
+            self.assertTrue((result[f"labels_{side}"] == labels).all())
+            self.assertTrue((result[f"probs_{side}"] == probs).all())
+        with tempfile.TemporaryDirectory() as tmp:
+            corpus = os.path.join(tmp, "corpus.txt")
+            with open(corpus, "w") as f:
+                f.writelines(f"__label__{'en' if i % 2 else 'xx'} {t}\n" for i, t in enumerate(texts[:200]))
+            path = os.path.join(tmp, "other.bin")
+            ft_ref.train_supervised(corpus, dim=4, epoch=1, minCount=1).save_model(path)
+            other = ft.load_model(path)
+        result = ft.compare(self.model, other, texts)
+        names, other_names = self.model.get_labels(), other.get_labels()
+        expected = [names.get(a) == other_names.get(b) for a, b in zip(labels[:, 0], result["labels_b"][:, 0])]
+        expected[-1] = True
+        self.assertEqual(list(result["match"]), expected)
+        self.assertAlmostEqual(result["agreement"], np.mean(expected))
+        result = ft.compare(self.model, other, [])
+        self.assertEqual(result["agreement"], 1.0)
+        self.assertEqual(len(result["match"]), 0)
+
+    def test_timeout(self):
+        texts = text_iter()
+        labels, probs = self.model.batch(texts, 2, timeout_secs=600.0)
+        labels_ref, probs_ref = self.model.batch(texts, 2)
+        self.assertTrue((labels == labels_ref).all())
+        with self.assertRaisesRegex(Exception, "batch timed out"):
+            self.model.batch(texts, 2, preprocess=lambda s: time.sleep(0.01) or s, timeout_secs=0.1)
+        # the pool is usable after a timeout.
+        self.assertTrue((self.model.batch(texts, 2)[0] == labels_ref).all())
+        with self.assertRaises(Exception):
+            self.model.batch(texts, timeout_secs=0.0)
+
+    def test_warmup(self):
+        model = ft.load_model(MODEL_PATH)
+        model.warmup()
+        model.warmup(0)
+        labels, probs = model.batch(["hello", "how are you"], 2)
+        labels_ref, probs_ref = self.model.batch(["hello", "how are you"], 2)
+        self.assertTrue((labels == labels_ref).all())
+        self.assertTrue((probs == probs_ref).all())
+
+    def test_reload(self):
+        model = ft.load_model(MODEL_PATH)
+        labels, probs = model.batch(["hello", "how are you"], 2)
+        ensemble = ft.Ensemble([model])
+        model.get_word_vector("hello")
+        with self.assertRaises(Exception):
+            model.reload("no/such/model.bin")
+        self.assertTrue((model.batch(["hello", "how are you"], 2)[0] == labels).all())
+        model.reload(MODEL_PATH)
+        labels_out, probs_out = model.batch(["hello", "how are you"], 2)
+        self.assertTrue((labels_out == labels).all())
+        self.assertTrue((probs_out == probs).all())
+        self.assertEqual(model.get_labels(), self.model.get_labels())
+        ensemble.batch(["hello"])
+
+    def test_mmap(self):
+        model = ft.load_model(MODEL_PATH, mmap=True)
+        texts = text_iter()[:500] + ["", "hello\nworld", "</s> hello", "__label__en hi"]
+        for k, threshold in [(1, 0.0), (5, 0.1), (len(self.model), 0.0)]:
+            labels, probs = model.batch(texts, k, threshold)
+            labels_ref, probs_ref = self.model.batch(texts, k, threshold)
+            np.testing.assert_array_equal(labels, labels_ref)
+            np.testing.assert_array_equal(probs, probs_ref)
+        for text in ["hello", "你好", "how are you", ""]:
+            np.testing.assert_array_equal(model.get_word_vector(text), self.model.get_word_vector(text))
+            np.testing.assert_array_equal(model.get_sentence_vector(text), self.model.get_sentence_vector(text))
+        model.reload(MODEL_PATH)
+        np.testing.assert_array_equal(model.batch(texts, 2)[1], self.model.batch(texts, 2)[1])
+        with tempfile.TemporaryDirectory() as tmp:
+            corpus = os.path.join(tmp, "corpus.txt")
+            with open(corpus, "w") as f:
+                f.writelines(f"__label__l{i % 2} word{i % 7} word{i % 5}\n" for i in range(100))
+            path = os.path.join(tmp, "model.ftz")
+            quantized = ft_ref.train_supervised(corpus, dim=4, epoch=1, minCount=1, wordNgrams=2, bucket=1000)
+            quantized.quantize(input=corpus, retrain=False)
+            quantized.save_model(path)
+            with self.assertRaisesRegex(ft.ModelLoadError, "quantized"):
+                ft.load_model(path, mmap=True)
+
+    def test_on_empty(self):
+        texts = ["hello", "", None, " \t ", "how are you"]
+        labels, probs = self.model.batch(texts, 2)
+        labels_out, probs_out, indices = self.model.batch(texts, 2, on_empty="skip")
+        self.assertEqual(list(indices), [0, 4])
+        self.assertTrue((labels_out == labels[indices]).all())
+        self.assertTrue((probs_out == probs[indices]).all())
+        _, _, counts, indices = self.model.batch(texts, 2, on_empty="skip", return_counts=True)
+        self.assertEqual(len(counts), 2)
+        with self.assertRaisesRegex(Exception, r"\[1, 2, 3\]"):
+            self.model.batch(texts, 2, on_empty="error")
+        self.model.batch(["hello"], 2, on_empty="error")
+        with self.assertRaises(Exception):
+            self.model.batch(texts, 2, on_empty="drop")
+
+    def test_on_error(self):
+        texts = ["hello", None, "how are you"]
+        labels, probs = self.model.batch(texts, 2)
+        labels_out, probs_out, errors = self.model.batch(texts, 2, on_error="report")
+        self.assertEqual(errors.dtype, np.int64)
+        self.assertEqual(len(errors), 0)
+        self.assertTrue((labels_out == labels).all())
+        labels_out, _ = self.model.batch(texts, 2, on_error="raise")
+        self.assertTrue((labels_out == labels).all())
+        _, _, indices, errors = self.model.batch(texts, 2, on_empty="skip", on_error="report")
+        self.assertEqual(list(indices), [0, 2])
+        with self.assertRaises(Exception):
+            self.model.batch(texts, 2, on_error="warn")
+
+    def test_on_invalid(self):
+        texts = ["hello", 3, None, b"how are you", ["list"]]
+        labels, probs = self.model.batch(texts, 2)
+        labels_ref, probs_ref = self.model.batch(["hello", None, None, "how are you", None], 2)
+        self.assertTrue((labels == labels_ref).all())
+        self.assertTrue((probs == probs_ref).all())
+        labels, probs, indices = self.model.batch(texts, 2, on_invalid="skip")
+        self.assertEqual(list(indices), [0, 2, 3])
+        self.assertTrue((labels == labels_ref[[0, 2, 3]]).all())
+        _, _, indices = self.model.batch(texts, 2, on_empty="skip", on_invalid="skip")
+        self.assertEqual(list(indices), [0, 3])
+        _, _, indices = self.model.batch(texts[:2], 2, on_empty="error", on_invalid="skip")
+        self.assertEqual(list(indices), [0])
+        with self.assertRaisesRegex(Exception, "invalid text 1"):
+            self.model.batch(texts, 2, on_invalid="raise")
+        with self.assertRaisesRegex(Exception, "invalid text 0"):
+            self.model.batch(["hello"], preprocess=lambda s: 1, on_invalid="raise")
+        # the same for texts read from numpy and arrow buffers.
+        strings = ["hello", "world", "how are you"]
+        preprocess = lambda s: 1 if s == "world" else s
+        for array in [np.array(strings), pa.array(strings)]:
+            with self.assertRaisesRegex(Exception, "invalid text 1"):
+                self.model.batch(array, preprocess=preprocess, on_invalid="raise")
+            _, _, indices = self.model.batch(array, 2, preprocess=preprocess, on_invalid="skip")
+            self.assertEqual(list(indices), [0, 2])
+        with self.assertRaises(Exception):
+            self.model.batch(texts, on_invalid="ignore")
+
+    def test_normalize(self):
+        texts = ["  Hello World  ", "HOW ARE YOU"]
+        expected = self.model.batch([t.strip().lower() for t in texts], 3)
+        for normalize in ["lower+strip", "strip+lower"]:
+            labels, probs = self.model.batch(texts, 3, normalize=normalize)
+            self.assertTrue((labels == expected[0]).all())
+            self.assertTrue((probs == expected[1]).all())
+        labels, _ = self.model.batch(texts, 3, normalize="lower")
+        self.assertTrue((labels == self.model.batch(texts, 3, lowercase=True)[0]).all())
+        with self.assertRaises(Exception):
+            self.model.batch(texts, normalize="upper")
+
+    def test_batch_iter(self):
+        texts = text_iter()[:1000] + [None]
+        labels, probs, counts = self.model.batch(texts, 3, 0.1, return_counts=True)
+        for window in [1, 3, 64]:
+            rows = list(self.model.batch_iter(texts, 3, 0.1, window=window))
+            self.assertEqual([i for i, _, _ in rows], list(range(len(texts))))
+            for i, row_labels, row_probs in rows:
+                self.assertTrue((row_labels == labels[i][:counts[i]]).all())
+                self.assertTrue((row_probs == probs[i][:counts[i]]).all())
+        # dropping the iterator early stops the background threads, even
+        # while they wait for the consumer.
+        for _ in self.model.batch_iter(texts, window=2):
+            break
+        self.assertEqual(list(self.model.batch_iter([])), [])
+        with self.assertRaises(Exception):
+            self.model.batch_iter(texts, window=0)
+
+    def test_batch_iter_unordered(self):
+        texts = text_iter()[:5000] + [None]
+        labels, probs, counts = self.model.batch(texts, 3, 0.1, return_counts=True)
+        rows = list(self.model.batch_iter(texts, 3, 0.1, ordered=False))
+        self.assertEqual(sorted(i for i, _, _ in rows), list(range(len(texts))))
+        for i, row_labels, row_probs in rows:
+            self.assertTrue((row_labels == labels[i][:counts[i]]).all())
+            self.assertTrue((row_probs == probs[i][:counts[i]]).all())
+        # dropping the iterator early stops the background threads.
+        for _ in self.model.batch_iter(texts, ordered=False):
+            break
+        self.assertEqual(list(self.model.batch_iter([], ordered=False)), [])
+
+    def test_texts_mutated(self):
+        # texts are sent to the workers without copying, so the input must
+        # not be used after it changes.
+        texts = [f"{text} {i}" for i, text in enumerate(text_iter()[:5000])]
+        labels, probs = self.model.batch(texts, 2)
+        rows = self.model.batch_stream(texts, 2)
+        texts[:] = [None] * len(texts)
+        gc.collect()
+        for i, row_labels, row_probs in rows:
+            np.testing.assert_array_equal(row_labels, labels[i][:len(row_labels)])
+            np.testing.assert_array_equal(row_probs, probs[i][:len(row_probs)])
+
+    def test_batch_stream(self):
+        texts = text_iter()[:5000] + [None]
+        labels, probs, counts = self.model.batch(texts, 3, 0.1, return_counts=True)
+        rows = list(self.model.batch_stream(texts, 3, 0.1))
+        self.assertEqual([i for i, _, _ in rows], list(range(len(texts))))
+        for i, row_labels, row_probs in rows:
+            self.assertTrue((row_labels == labels[i][:counts[i]]).all())
+            self.assertTrue((row_probs == probs[i][:counts[i]]).all())
+        rows = list(self.model.batch_stream(texts, 3, 0.1, window=5))
+        self.assertEqual([i for i, _, _ in rows], list(range(len(texts))))
+        rows = list(self.model.batch_stream(texts, 3, 0.1, ordered=False))
+        self.assertEqual(sorted(i for i, _, _ in rows), list(range(len(texts))))
+        # same positional arguments as `batch_iter`.
+        rows = list(self.model.batch_stream(texts, 3, 0.1, -1.0, 5, False))
+        self.assertEqual(sorted(i for i, _, _ in rows), list(range(len(texts))))
+        with self.assertRaises(Exception):
+            self.model.batch_stream(texts, window=0)
+        # dropping the iterator early stops the background threads.
+        for ordered in [True, False]:
+            for _ in self.model.batch_stream(texts, ordered=ordered):
+                break
+        self.assertEqual(list(self.model.batch_stream([])), [])
+
+    def test_batch_async(self):
+        texts = text_iter()[:5000] + [None]
+        labels, probs = self.model.batch(texts, 2)
+
+        async def gather():
+            return await asyncio.gather(*(self.model.batch_async(texts, 2) for _ in range(8)))
+
+        for async_labels, async_probs in asyncio.run(gather()):
+            np.testing.assert_array_equal(async_labels, labels)
+            np.testing.assert_array_equal(async_probs, probs)
+
+        async def cancel():
+            task = asyncio.ensure_future(self.model.batch_async(texts * 20))
+            await asyncio.sleep(0)
+            task.cancel()
+            with self.assertRaises(asyncio.CancelledError):
+                await task
+
+        asyncio.run(cancel())
+        with self.assertRaises(RuntimeError):
+            self.model.batch_async(texts)
+
+    def test_get_subword_id(self):
+        for subword in ["<he", "hel", "llo>", "你好", "zzzz"]:
+            self.assertEqual(self.model.get_subword_id(subword), self.model_ref.get_subword_id(subword))
+        self.assertGreaterEqual(self.model.get_subword_id("<he"), len(self.model_ref.words))
+
+    def test_detect_language(self):
+        texts = ["你好", "how are you", ""]
+        languages = self.model.detect_language(texts, k=2)
+        self.assertEqual(languages[0][0][0], "zh")
+        self.assertEqual(languages[1][0][0], "en")
+        self.assertEqual(languages[2], [])
+        for row, dicts in zip(languages, self.model.batch(texts, k=2, output="dicts")):
+            self.assertEqual(row, [(label[len("__label__"):], prob) for label, prob in dicts.items()])
+
+    def test_predict_lines(self):
+        lines = text_iter()[:100]
+        predictions = self.model.predict_lines("\n".join(lines), k=2)
+        self.assertEqual(predictions, self.model.batch(lines, k=2, output="dicts"))
+        self.assertEqual(self.model.predict_lines("hello\n"), [self.model.batch(["hello"], output="dicts")[0], {}])
+
+    def test_matrices(self):
+        input_matrix = self.model.get_input_matrix()
+        output_matrix = self.model.get_output_matrix()
+        dim = self.model_ref.get_dimension()
+        nwords = len(self.model_ref.words)
+        self.assertEqual(input_matrix.dtype, np.float32)
+        self.assertEqual(input_matrix.shape, (nwords + self.model_ref.f.getArgs().bucket, dim))
+        self.assertEqual(output_matrix.shape, (len(self.model_ref.labels), dim))
+        np.testing.assert_array_equal(input_matrix, self.model_ref.get_input_matrix())
+        np.testing.assert_array_equal(output_matrix, self.model_ref.get_output_matrix())
+        np.testing.assert_array_equal(output_matrix[1], self.model.get_output_vector(1))
+
+    def test_tokenize(self):
+        texts = ["hello world", "foo\nbar", ""]
+        tokens = self.model.tokenize(texts)
+        self.assertEqual(tokens, [self.model.tokenize_one(text) for text in texts])
+        self.assertEqual(tokens[0], ["hello", "world"])
+        self.assertEqual(tokens[1], ["foo", "</s>", "bar"])
+        self.assertEqual(tokens[2], [])
+
+    def test_oov_stats(self):
+        texts = text_iter()[:300] + [None, "", "zzqxj zzqxj hello"]
+        oov_counts, token_counts, stats = self.model.oov_stats(texts, top_n=5)
+        self.assertEqual(oov_counts.dtype, np.int32)
+        vocabulary = set(self.model_ref.get_words())
+        tokens = self.model.tokenize(texts)
+        self.assertEqual(list(token_counts), [len(row) for row in tokens])
+        oov = [[token for token in row if token not in vocabulary] for row in tokens]
+        self.assertEqual(list(oov_counts), [len(row) for row in oov])
+        self.assertEqual(oov_counts[-1], 2)
+        self.assertEqual(stats["tokens"], token_counts.sum())
+        self.assertEqual(stats["oov_tokens"], oov_counts.sum())
+        self.assertAlmostEqual(stats["oov_rate"], oov_counts.sum() / token_counts.sum())
+        counts = {}
+        for token in sum(oov, []):
+            counts[token] = counts.get(token, 0) + 1
+        self.assertEqual(stats["top_oov"], sorted(counts.items(), key=lambda item: (-item[1], item[0]))[:5])
+
+    def test_order(self):
+        texts = text_iter()
+        permutation = list(range(len(texts)))
+        random.Random(42).shuffle(permutation)
+        labels, probs = self.model.batch(texts, 2)
+        labels_shuffled, probs_shuffled = self.model.batch([texts[i] for i in permutation], 2)
+        np.testing.assert_array_equal(labels_shuffled, labels[permutation])
+        np.testing.assert_array_equal(probs_shuffled, probs[permutation])
+
     def test_benchmark(self):
         k = 2
         texts = text_iter()
@@ -48,6 +1256,81 @@ class TestFastText(unittest.TestCase):
         print(f"time taken {time}")
         print(f"ref time taken {time_ref}")
 
+    def test_keyboard_interrupt(self):
+        texts = ["hello world"] * 100000
+        timer = threading.Timer(0.5, _thread.interrupt_main)
+        timer.start()
+        start = time.time()
+        with self.assertRaises(KeyboardInterrupt):
+            self.model.batch(texts, preprocess=lambda text: (time.sleep(0.001), text)[1])
+        timer.join()
+        self.assertLess(time.time() - start, 20)
+        labels, _ = self.model.batch(texts[:10])
+        self.assertEqual(labels.shape, (10, 1))
+
+    def test_deterministic(self):
+        texts = text_iter()[:2000]
+        labels, probs = self.model.batch(texts, 3)
+        try:
+            for chunk_size in [1, 7, 1000]:
+                ft.set_chunk_size(chunk_size)
+                labels_out, probs_out = self.model.batch(texts, 3)
+                self.assertEqual(labels_out.tobytes(), labels.tobytes())
+                self.assertEqual(probs_out.tobytes(), probs.tobytes())
+        finally:
+            ft.set_chunk_size()
+
+    def test_benchmark_chunk_size(self):
+        texts = [t.split(" ")[0] for t in text_iter()]
+        ref = self.model.batch(texts, 2)
+        for chunk_size in [1, 64]:
+            ft.set_chunk_size(chunk_size)
+            labels, probs = self.model.batch(texts, 2)
+            self.assertTrue(np.array_equal(labels, ref[0]))
+            self.assertTrue(np.allclose(probs, ref[1]))
+            time = timeit.timeit(lambda: self.model.batch(texts, 2), number=10)
+            print(f"chunk_size={chunk_size} time taken {time}")
+        ft.set_chunk_size()
+        with self.assertRaises(Exception):
+            ft.set_chunk_size(0)
+
+    def test_set_log_level(self):
+        with self.assertLogs("fasttext_parallel", level=1) as logs:
+            ft.set_log_level("TRACE")
+            self.model.batch(["hello"], 2)
+        self.assertTrue(any("text sent" in line for line in logs.output))
+        # `assertNoLogs` is unavailable on Python 3.9.
+        records = []
+        handler = logging.Handler(level=1)
+        handler.emit = records.append
+        logger = logging.getLogger("fasttext_parallel")
+        level = logger.level
+        logger.addHandler(handler)
+        logger.setLevel(1)
+        try:
+            ft.set_log_level("debug")
+            self.model.batch(["hello"], 2)
+        finally:
+            logger.removeHandler(handler)
+            logger.setLevel(level)
+            ft.set_log_level("debug")
+        self.assertEqual(records, [])
+        with self.assertRaises(Exception):
+            ft.set_log_level("verbose")
+
+    def test_benchmark_top1(self):
+        texts = [t.split(" ")[0] for t in text_iter()] + [None]
+        labels, probs = self.model.predict_top1(texts, 0.5)
+        labels_ref, probs_ref, counts = self.model.batch(texts, 1, 0.5, return_counts=True)
+        self.assertEqual(labels.shape, (len(texts),))
+        self.assertTrue((labels == np.where(counts > 0, labels_ref[:, 0], -1)).all())
+        self.assertTrue((np.isnan(probs) == (counts == 0)).all())
+        self.assertTrue((probs[counts > 0] == probs_ref[counts > 0, 0]).all())
+        time = timeit.timeit(lambda: self.model.batch(texts, 1), number=10)
+        print(f"batch k=1 time taken {time}")
+        time = timeit.timeit(lambda: self.model.predict_top1(texts), number=10)
+        print(f"predict_top1 time taken {time}")
+
 
 if __name__ == '__main__':
     unittest.main()